@@ -1,16 +1,25 @@
 extern crate tempfile;
-mod memstore;
+mod workload;
 use clap::Parser;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 
-use std::{cell::RefCell, collections::HashMap, io::Write, path::PathBuf, rc::Rc, time::Instant};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::PathBuf,
+    rc::Rc,
+    time::Instant,
+};
 
 use bpts::{
     prelude::*,
-    storage::{buffile_storage::BufFileStorage, file_storage::FileStorage},
-    utils::any_as_u8_slice,
+    storage::{
+        buffile_storage::BufFileStorage, file_storage::FileStorage, memory_storage::MemoryStorage,
+    },
 };
 
-use memstore::MemStorage;
+use workload::*;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -21,7 +30,7 @@ struct Args {
 
     /// data count
     #[arg(short, long, default_value_t = 10000)]
-    count: i32,
+    count: u64,
 
     // use in-memory storage
     #[arg(short, long, default_value_t = false)]
@@ -38,6 +47,58 @@ struct Args {
     // use in-memory storage
     #[arg(short, long)]
     filename: Option<PathBuf>,
+
+    /// operations after the load phase
+    #[arg(short, long, value_enum, default_value_t = Workload::Load)]
+    workload: Workload,
+
+    /// custom mix of the run phase, like `read=50,update=30,delete=10,scan=10`
+    #[arg(long, value_parser = Mix::parse)]
+    mix: Option<Mix>,
+
+    /// operations count of the run phase, `count` by default
+    #[arg(long)]
+    ops: Option<u64>,
+
+    /// order of keys
+    #[arg(short, long, value_enum, default_value_t = KeyOrder::Seq)]
+    order: KeyOrder,
+
+    /// skew of zipfian keys
+    #[arg(long, default_value_t = 0.99)]
+    zipf_theta: f64,
+
+    /// bytes in a key
+    #[arg(long, default_value_t = 4)]
+    key_size: usize,
+
+    /// bytes in a value
+    #[arg(long, default_value_t = 4)]
+    value_size: usize,
+
+    /// writes in one transaction
+    #[arg(long, default_value_t = 1)]
+    batch: usize,
+
+    /// keys are spread over trees 1..=trees
+    #[arg(long, default_value_t = 1)]
+    trees: u32,
+
+    /// max records in one scan
+    #[arg(long, default_value_t = 100)]
+    scan_length: usize,
+
+    /// closes the storage, opens it again and reads all keys
+    #[arg(long, default_value_t = false)]
+    reopen: bool,
+
+    /// seed of random keys and operations
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// prints results as json
+    #[arg(long, default_value_t = false)]
+    json: bool,
 }
 
 struct StorageKeyCmp {}
@@ -57,109 +118,333 @@ impl KeyCmp for StorageKeyCmp {
     }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    println!("{:?}", args);
+struct Bench<'a> {
+    args: &'a Args,
+    storage: Storage,
+    rng: StdRng,
+    zipf: Zipf,
+    /// keys 0..inserted were written
+    inserted: u64,
+    next_seq: u64,
+    version: u64,
+    transaction: Option<u64>,
+    writes: usize,
+    latencies: BTreeMap<OpKind, Latencies>,
+    misses: usize,
+}
 
-    let tempdir = tempfile::tempdir().unwrap();
-    let pathbuff = if args.filename.is_none() {
-        tempdir.path().join("astorage.db")
-    } else {
-        args.filename.unwrap()
-    };
-    let filename = pathbuff.to_str().unwrap();
-    if std::path::Path::new(&filename).is_file() {
-        println!("removing {:?}", filename);
-        std::fs::remove_file(filename).unwrap();
+impl<'a> Bench<'a> {
+    fn new(args: &'a Args, storage: Storage) -> Self {
+        Bench {
+            args,
+            storage,
+            rng: StdRng::seed_from_u64(args.seed),
+            zipf: Zipf::new(args.count, args.zipf_theta),
+            inserted: 0,
+            next_seq: 0,
+            version: 0,
+            transaction: None,
+            writes: 0,
+            latencies: BTreeMap::new(),
+            misses: 0,
+        }
     }
 
-    println!("dbfile: {}", filename);
-    let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
-    let cmp = Rc::new(RefCell::new(StorageKeyCmp::new()));
-    all_cmp.insert(1u32, cmp);
+    fn tree_of(&self, id: u64) -> u32 {
+        1 + (id % self.args.trees as u64) as u32
+    }
 
-    let fstore: Rc<RefCell<dyn FlatStorage>> = if args.memstorage {
-        println!("create memstorage...");
-        Rc::new(RefCell::new(MemStorage::new()))
-    } else {
-        if args.bufstorage {
-            println!("create bufstorage...");
-            Rc::new(RefCell::new(BufFileStorage::new(&filename, args.bufsize)?))
-        } else {
-            println!("create flatstorage...");
-            Rc::new(RefCell::new(FileStorage::new(&filename)?))
+    fn progress(&self, name: &str, done: u64, total: u64) {
+        if self.args.quiet || self.args.json || total < 100 || !done.is_multiple_of(total / 100) {
+            return;
         }
-    };
+        print!("\r{} {}%    ", name, 100 * done / total);
+        let _ = std::io::stdout().flush();
+    }
 
-    let params = StorageParams::default();
-    println!("{:?}", params.tree_params);
-    let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+    fn transaction(&mut self) -> Result<u64> {
+        if let Some(tr) = self.transaction {
+            return Ok(tr);
+        }
+        let tr = self.storage.begin_transaction()?;
+        self.transaction = Some(tr);
+        Ok(tr)
+    }
 
-    let full_time_begin = Instant::now();
+    /// commits the transaction after `batch` writes.
+    fn wrote(&mut self) -> Result<()> {
+        if self.transaction.is_none() {
+            return Ok(());
+        }
+        self.writes += 1;
+        if self.writes >= self.args.batch {
+            self.commit()?;
+        }
+        Ok(())
+    }
 
-    let write_time_begin = Instant::now();
-    for key in 0..args.count {
-        let cur_begin = Instant::now();
-        let cur_key_sl = unsafe { any_as_u8_slice(&key) };
-        //let before = storage.dump_tree(1, "before".to_owned());
-        let tr = storage.begin_transaction()?;
-        storage.insert(tr, 1, &cur_key_sl, &cur_key_sl)?;
-        storage.commit_transaction(tr)?;
-        //let after1 = storage.dump_tree(1, "after1".to_owned());
-        let cur_duration = cur_begin.elapsed();
+    fn commit(&mut self) -> Result<()> {
+        if let Some(tr) = self.transaction.take() {
+            let begin = Instant::now();
+            self.storage.commit_transaction(tr)?;
+            self.latencies
+                .entry(OpKind::Commit)
+                .or_default()
+                .add(begin.elapsed());
+        }
+        self.writes = 0;
+        Ok(())
+    }
 
-        if !args.quiet {
-            print!(
-                "\rwrite cur:{}% size:{} time:{:?}                ",
-                (100f32 * key as f32) / (args.count as f32),
-                fstore.borrow().size() / 1024,
-                cur_duration
-            );
-            let _ = std::io::stdout().flush();
+    fn finish(&mut self, name: &str, ops: u64, begin: Instant) -> Result<PhaseResult> {
+        self.commit()?;
+        let elapsed = begin.elapsed();
+        if !self.args.quiet && !self.args.json {
+            println!("\r{} done in {:?}    ", name, elapsed);
         }
+        let latencies = std::mem::take(&mut self.latencies)
+            .into_iter()
+            .map(|(kind, l)| (kind, l.summary()))
+            .collect();
+        Ok(PhaseResult {
+            name: name.to_owned(),
+            ops: ops as usize,
+            misses: std::mem::take(&mut self.misses),
+            elapsed,
+            latencies,
+        })
+    }
 
-        let find_res = storage.find(1, cur_key_sl)?;
-        //let after2 = storage.dump_tree(1, "after2".to_owned());
-        //bpts::tree::debug::print_states(&[&before, &after1, &after2]);
-        if find_res.is_none() {
-            println!("\n {} ", key);
+    /// key of the run phase.
+    fn choose(&mut self) -> u64 {
+        let items = std::cmp::max(self.inserted, 1);
+        if self.args.workload == Workload::D {
+            let back = std::cmp::min(self.zipf.next(&mut self.rng), items - 1);
+            return items - 1 - back;
+        }
+        match self.args.order {
+            KeyOrder::Seq => {
+                let id = self.next_seq % items;
+                self.next_seq += 1;
+                id
+            }
+            KeyOrder::Random => self.rng.gen_range(0..items),
+            KeyOrder::Zipf => scramble(self.zipf.next(&mut self.rng), items),
         }
-        assert!(find_res.is_some());
     }
 
-    let write_duration = write_time_begin.elapsed();
+    fn op(&mut self, kind: OpKind, id: u64) -> Result<()> {
+        let tree = self.tree_of(id);
+        let key = make_key(id, self.args.key_size);
+        let begin = Instant::now();
+        match kind {
+            OpKind::Insert => {
+                let tr = self.transaction()?;
+                let value = make_value(id, 0, self.args.value_size);
+                self.storage.insert(tr, tree, &key, &value)?;
+            }
+            OpKind::Read => {
+                if self.storage.find(tree, &key)?.is_none() {
+                    self.misses += 1;
+                }
+            }
+            OpKind::Update | OpKind::ReadModifyWrite => {
+                if kind == OpKind::ReadModifyWrite && self.storage.find(tree, &key)?.is_none() {
+                    self.misses += 1;
+                }
+                self.version += 1;
+                let value = make_value(id, self.version, self.args.value_size);
+                let tr = self.transaction()?;
+                self.storage.delete(tr, tree, &key)?;
+                self.storage.insert(tr, tree, &key, &value)?;
+            }
+            OpKind::Delete => {
+                let tr = self.transaction()?;
+                if !self.storage.delete(tr, tree, &key)? {
+                    self.misses += 1;
+                }
+            }
+            OpKind::Scan => {
+                let len = self.rng.gen_range(1..=self.args.scan_length);
+                let start = self.storage.rank(tree, &key)?;
+                for n in start..start + len {
+                    if self.storage.select(tree, n)?.is_none() {
+                        break;
+                    }
+                }
+            }
+            OpKind::Commit => unreachable!(),
+        }
+        self.latencies.entry(kind).or_default().add(begin.elapsed());
+        if kind != OpKind::Read && kind != OpKind::Scan {
+            self.wrote()?;
+        }
+        Ok(())
+    }
 
-    if !args.quiet {
-        println!("");
+    fn load(&mut self) -> Result<PhaseResult> {
+        let mut ids: Vec<u64> = (0..self.args.count).collect();
+        if self.args.order != KeyOrder::Seq {
+            ids.shuffle(&mut self.rng);
+        }
+        let begin = Instant::now();
+        for (i, id) in ids.into_iter().enumerate() {
+            self.op(OpKind::Insert, id)?;
+            self.progress("load", i as u64, self.args.count);
+        }
+        self.inserted = self.args.count;
+        self.finish("load", self.args.count, begin)
     }
 
-    let read_time_begin = Instant::now();
-    for key in 0..args.count {
-        let cur_begin = Instant::now();
+    fn run(&mut self, mix: &Mix) -> Result<PhaseResult> {
+        let ops = self.args.ops.unwrap_or(self.args.count);
+        let begin = Instant::now();
+        for i in 0..ops {
+            let kind = mix.next(&mut self.rng);
+            let id = if kind == OpKind::Insert {
+                self.inserted += 1;
+                self.inserted - 1
+            } else {
+                self.choose()
+            };
+            self.op(kind, id)?;
+            self.progress("run", i, ops);
+        }
+        self.finish("run", ops, begin)
+    }
 
-        let cur_key_sl = unsafe { any_as_u8_slice(&key) };
+    /// reads all written keys in order.
+    fn read_all(&mut self, name: &str) -> Result<PhaseResult> {
+        let begin = Instant::now();
+        for id in 0..self.inserted {
+            self.op(OpKind::Read, id)?;
+            self.progress(name, id, self.inserted);
+        }
+        self.finish(name, self.inserted, begin)
+    }
+}
 
-        let find_res = storage.find(1, cur_key_sl)?;
-        assert!(find_res.is_some());
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if !args.json {
+        println!("{:?}", args);
+    }
+    let ops = args.ops.unwrap_or(args.count);
+    if args.count.saturating_add(ops) > key_space(args.key_size) || args.key_size == 0 {
+        return Err(bpts::Error::InvalidParams(format!(
+            "{} keys do not fit {} bytes",
+            args.count.saturating_add(ops),
+            args.key_size
+        )));
+    }
+    if args.batch == 0 || args.trees == 0 || args.scan_length == 0 {
+        return Err(bpts::Error::InvalidParams(
+            "batch, trees and scan-length must be positive".to_owned(),
+        ));
+    }
+    let mix = match &args.mix {
+        Some(mix) => mix.clone(),
+        None => Mix::of(args.workload),
+    };
 
-        let cur_duration = cur_begin.elapsed();
-        if !args.quiet {
-            print!(
-                "\rread cur:{}% time:{:?}                ",
-                (100f32 * key as f32) / (args.count as f32),
-                cur_duration
-            );
-            let _ = std::io::stdout().flush();
+    let tempdir = tempfile::tempdir().unwrap();
+    let pathbuff = match &args.filename {
+        Some(f) => f.clone(),
+        None => tempdir.path().join("astorage.db"),
+    };
+    let filename = pathbuff.to_str().unwrap();
+    if std::path::Path::new(filename).is_file() {
+        if !args.json {
+            println!("removing {:?}", filename);
         }
+        std::fs::remove_file(filename).unwrap();
     }
-    let read_duration = read_time_begin.elapsed();
 
-    let duration = full_time_begin.elapsed();
+    if !args.json {
+        println!("dbfile: {}", filename);
+    }
+    let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+    let cmp = Rc::new(RefCell::new(StorageKeyCmp::new()));
+    for tree_id in 1..=args.trees {
+        all_cmp.insert(tree_id, cmp.clone());
+    }
+
+    let kind = if args.memstorage {
+        "memstorage"
+    } else if args.bufstorage {
+        "bufstorage"
+    } else {
+        "flatstorage"
+    };
+    if !args.json {
+        println!("create {}...", kind);
+    }
+    let mut fstore: Rc<RefCell<dyn FlatStorage>> = if args.memstorage {
+        Rc::new(RefCell::new(MemoryStorage::new()))
+    } else if args.bufstorage {
+        Rc::new(RefCell::new(BufFileStorage::new(filename, args.bufsize)?))
+    } else {
+        Rc::new(RefCell::new(FileStorage::new(filename)?))
+    };
+
+    let params = StorageParams::default();
+    if !args.json {
+        println!("{:?}", params.tree_params);
+    }
+    let storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+
+    let mut bench = Bench::new(&args, storage);
+    let mut phases = vec![bench.load()?];
+    if args.workload == Workload::Load && args.mix.is_none() {
+        phases.push(bench.read_all("read")?);
+    } else if !mix.is_empty() {
+        phases.push(bench.run(&mix)?);
+    }
+
+    if args.reopen {
+        bench.storage.close()?;
+        fstore.borrow().close()?;
+        if !args.memstorage {
+            fstore = if args.bufstorage {
+                Rc::new(RefCell::new(BufFileStorage::open(filename, args.bufsize)?))
+            } else {
+                Rc::new(RefCell::new(FileStorage::open(filename)?))
+            };
+        }
+        bench.storage = Storage::open(fstore.clone(), all_cmp)?;
+        phases.push(bench.read_all("reopen")?);
+    }
+
+    let stats = bench.storage.file_stats()?;
+    let config = vec![
+        ("workload", json_string(&format!("{:?}", args.workload))),
+        ("mix", json_string(&mix.describe())),
+        ("order", json_string(&format!("{:?}", args.order))),
+        ("zipf_theta", args.zipf_theta.to_string()),
+        ("count", args.count.to_string()),
+        ("ops", ops.to_string()),
+        ("key_size", args.key_size.to_string()),
+        ("value_size", args.value_size.to_string()),
+        ("batch", args.batch.to_string()),
+        ("trees", args.trees.to_string()),
+        ("scan_length", args.scan_length.to_string()),
+        ("reopen", args.reopen.to_string()),
+        ("seed", args.seed.to_string()),
+        ("storage", json_string(kind)),
+    ];
+    let report = Report {
+        config: config.into_iter().map(|(k, v)| (k.to_owned(), v)).collect(),
+        phases,
+        file_size: fstore.borrow().size(),
+        live_bytes: stats.live_bytes,
+        garbage_bytes: stats.garbage_bytes,
+    };
 
-    println!("");
-    println!(" size: {}", fstore.borrow().size() / 1024);
-    println!(" total write time: {:?}", write_duration);
-    println!(" total read time: {:?}", read_duration);
-    println!(" total elapsed: {:?}", duration);
+    if args.json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
+        println!("{}", stats);
+    }
     Ok(())
 }
@@ -0,0 +1,429 @@
+use clap::ValueEnum;
+use rand::{rngs::StdRng, Rng};
+
+use std::{fmt::Write as _, time::Duration};
+
+/// order of keys in the load phase and distribution of keys in the run phase.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum KeyOrder {
+    Seq,
+    Random,
+    Zipf,
+}
+
+/// YCSB-like operation mixes. `load` only inserts and reads back all keys.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Workload {
+    Load,
+    /// 50% reads, 50% updates
+    A,
+    /// 95% reads, 5% updates
+    B,
+    /// reads only
+    C,
+    /// 95% reads of the latest keys, 5% inserts
+    D,
+    /// 95% scans, 5% inserts
+    E,
+    /// 50% reads, 50% read-modify-writes
+    F,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpKind {
+    Insert,
+    Read,
+    Update,
+    Delete,
+    Scan,
+    ReadModifyWrite,
+    Commit,
+}
+
+impl OpKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            OpKind::Insert => "insert",
+            OpKind::Read => "read",
+            OpKind::Update => "update",
+            OpKind::Delete => "delete",
+            OpKind::Scan => "scan",
+            OpKind::ReadModifyWrite => "rmw",
+            OpKind::Commit => "commit",
+        }
+    }
+}
+
+/// weights of operations in the run phase.
+#[derive(Clone, Debug, Default)]
+pub struct Mix {
+    weights: Vec<(OpKind, u32)>,
+}
+
+impl Mix {
+    pub fn of(workload: Workload) -> Mix {
+        let weights = match workload {
+            Workload::Load => vec![],
+            Workload::A => vec![(OpKind::Read, 50), (OpKind::Update, 50)],
+            Workload::B => vec![(OpKind::Read, 95), (OpKind::Update, 5)],
+            Workload::C => vec![(OpKind::Read, 100)],
+            Workload::D => vec![(OpKind::Read, 95), (OpKind::Insert, 5)],
+            Workload::E => vec![(OpKind::Scan, 95), (OpKind::Insert, 5)],
+            Workload::F => vec![(OpKind::Read, 50), (OpKind::ReadModifyWrite, 50)],
+        };
+        Mix { weights }
+    }
+
+    /// parses `read=50,update=40,delete=10`.
+    pub fn parse(s: &str) -> Result<Mix, String> {
+        let mut weights = Vec::new();
+        for part in s.split(',') {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not op=weight", part))?;
+            let kind = match name.trim() {
+                "insert" => OpKind::Insert,
+                "read" => OpKind::Read,
+                "update" => OpKind::Update,
+                "delete" => OpKind::Delete,
+                "scan" => OpKind::Scan,
+                "rmw" => OpKind::ReadModifyWrite,
+                other => return Err(format!("unknown op '{}'", other)),
+            };
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("'{}' is not a weight", weight))?;
+            weights.push((kind, weight));
+        }
+        if weights.iter().all(|(_, w)| *w == 0) {
+            return Err("all weights are zero".to_owned());
+        }
+        Ok(Mix { weights })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.iter().all(|(_, w)| *w == 0)
+    }
+
+    pub fn next(&self, rng: &mut StdRng) -> OpKind {
+        let total: u32 = self.weights.iter().map(|(_, w)| w).sum();
+        let mut n = rng.gen_range(0..total);
+        for (kind, weight) in self.weights.iter() {
+            if n < *weight {
+                return *kind;
+            }
+            n -= weight;
+        }
+        unreachable!()
+    }
+
+    pub fn describe(&self) -> String {
+        let parts: Vec<String> = self
+            .weights
+            .iter()
+            .map(|(k, w)| format!("{}={}", k.name(), w))
+            .collect();
+        parts.join(",")
+    }
+}
+
+/// zipfian numbers in [0, items), small numbers are the most popular.
+/// the algorithm from "Quickly Generating Billion-Record Synthetic Databases", as in YCSB.
+pub struct Zipf {
+    items: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+fn zeta(n: u64, theta: f64) -> f64 {
+    (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum()
+}
+
+impl Zipf {
+    pub fn new(items: u64, theta: f64) -> Zipf {
+        let items = std::cmp::max(items, 1);
+        let zeta2 = zeta(2, theta);
+        let zetan = zeta(items, theta);
+        let alpha = 1.0 / (1.0 - theta);
+        let eta = (1.0 - (2.0 / items as f64).powf(1.0 - theta)) / (1.0 - zeta2 / zetan);
+        Zipf {
+            items,
+            theta,
+            alpha,
+            zetan,
+            eta,
+        }
+    }
+
+    pub fn next(&self, rng: &mut StdRng) -> u64 {
+        let u: f64 = rng.gen();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1;
+        }
+        let n = self.items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        std::cmp::min(n as u64, self.items - 1)
+    }
+}
+
+/// spreads popular zipf ranks over the key space.
+pub fn scramble(rank: u64, items: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for b in rank.to_le_bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % items
+}
+
+/// key `id` as `size` big-endian bytes, so the order of keys is the order of ids.
+pub fn make_key(id: u64, size: usize) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    if size <= bytes.len() {
+        bytes[bytes.len() - size..].to_vec()
+    } else {
+        let mut result = vec![0u8; size - bytes.len()];
+        result.extend_from_slice(&bytes);
+        result
+    }
+}
+
+pub fn make_value(id: u64, version: u64, size: usize) -> Vec<u8> {
+    let seed = (id ^ version.rotate_left(32)).to_le_bytes();
+    seed.iter().cycle().take(size).copied().collect()
+}
+
+/// largest count of keys, which fit `size` bytes.
+pub fn key_space(size: usize) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        1u64 << (size * 8)
+    }
+}
+
+/// latencies of one kind of operations.
+#[derive(Default)]
+pub struct Latencies {
+    nanos: Vec<u64>,
+}
+
+impl Latencies {
+    pub fn add(&mut self, d: Duration) {
+        self.nanos.push(d.as_nanos() as u64);
+    }
+
+    pub fn summary(mut self) -> LatencySummary {
+        self.nanos.sort_unstable();
+        let count = self.nanos.len();
+        let percentile = |p: f64| -> u64 {
+            if count == 0 {
+                return 0;
+            }
+            let n = ((count as f64) * p).ceil() as usize;
+            self.nanos[n.clamp(1, count) - 1]
+        };
+        let total: u128 = self.nanos.iter().map(|n| *n as u128).sum();
+        LatencySummary {
+            count,
+            mean: if count == 0 {
+                0
+            } else {
+                (total / count as u128) as u64
+            },
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: self.nanos.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// latencies in nanoseconds.
+#[derive(Debug, PartialEq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub mean: u64,
+    pub p50: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+pub struct PhaseResult {
+    pub name: String,
+    pub ops: usize,
+    pub misses: usize,
+    pub elapsed: Duration,
+    pub latencies: Vec<(OpKind, LatencySummary)>,
+}
+
+impl PhaseResult {
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.ops as f64 / secs
+    }
+}
+
+pub struct Report {
+    /// (name, value) of options, values are already written as json.
+    pub config: Vec<(String, String)>,
+    pub phases: Vec<PhaseResult>,
+    pub file_size: usize,
+    pub live_bytes: usize,
+    pub garbage_bytes: usize,
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(result, "\\u{:04x}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl Report {
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n  \"config\": {");
+        for (i, (name, value)) in self.config.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}\n    {}: {}", sep, json_string(name), value).unwrap();
+        }
+        out.push_str("\n  },\n  \"phases\": [");
+        for (i, phase) in self.phases.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(
+                out,
+                "{}\n    {{\"name\": {}, \"ops\": {}, \"misses\": {}, \"elapsed_secs\": {:.6}, \"throughput\": {:.1}, \"latency_ns\": {{",
+                sep,
+                json_string(&phase.name),
+                phase.ops,
+                phase.misses,
+                phase.elapsed.as_secs_f64(),
+                phase.throughput()
+            )
+            .unwrap();
+            for (j, (kind, l)) in phase.latencies.iter().enumerate() {
+                let sep = if j == 0 { "" } else { ", " };
+                write!(
+                    out,
+                    "{}\"{}\": {{\"count\": {}, \"mean\": {}, \"p50\": {}, \"p99\": {}, \"p999\": {}, \"max\": {}}}",
+                    sep,
+                    kind.name(),
+                    l.count,
+                    l.mean,
+                    l.p50,
+                    l.p99,
+                    l.p999,
+                    l.max
+                )
+                .unwrap();
+            }
+            out.push_str("}}");
+        }
+        write!(
+            out,
+            "\n  ],\n  \"file_size\": {},\n  \"live_bytes\": {},\n  \"garbage_bytes\": {}\n}}",
+            self.file_size, self.live_bytes, self.garbage_bytes
+        )
+        .unwrap();
+        out
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for phase in self.phases.iter() {
+            writeln!(
+                f,
+                "{}: {} ops in {:?}, {:.0} ops/s, {} misses",
+                phase.name,
+                phase.ops,
+                phase.elapsed,
+                phase.throughput(),
+                phase.misses
+            )?;
+            for (kind, l) in phase.latencies.iter() {
+                writeln!(
+                    f,
+                    "  {:<7} count {:>9}  mean {:>9?}  p50 {:>9?}  p99 {:>9?}  p999 {:>9?}  max {:>9?}",
+                    kind.name(),
+                    l.count,
+                    Duration::from_nanos(l.mean),
+                    Duration::from_nanos(l.p50),
+                    Duration::from_nanos(l.p99),
+                    Duration::from_nanos(l.p999),
+                    Duration::from_nanos(l.max)
+                )?;
+            }
+        }
+        writeln!(f, "file size: {} bytes", self.file_size)?;
+        writeln!(f, "live bytes: {} bytes", self.live_bytes)?;
+        write!(f, "garbage bytes: {} bytes", self.garbage_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn percentiles() {
+        let mut l = Latencies::default();
+        for n in (1..=1000u64).rev() {
+            l.add(Duration::from_nanos(n));
+        }
+        let s = l.summary();
+        assert_eq!(s.count, 1000);
+        assert_eq!(s.p50, 500);
+        assert_eq!(s.p99, 990);
+        assert_eq!(s.p999, 999);
+        assert_eq!(s.max, 1000);
+        assert_eq!(Latencies::default().summary().p99, 0);
+    }
+
+    #[test]
+    fn zipf_is_skewed() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let zipf = Zipf::new(1000, 0.99);
+        let mut hits = vec![0usize; 1000];
+        for _ in 0..10000 {
+            hits[zipf.next(&mut rng) as usize] += 1;
+        }
+        assert!(hits[0] > hits[10] && hits[10] > hits[500]);
+        assert!(hits[..10].iter().sum::<usize>() > 2500);
+    }
+
+    #[test]
+    fn keys_and_mixes() {
+        assert_eq!(make_key(0x0102, 2), [1, 2]);
+        assert_eq!(make_key(1, 10), [0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(make_key(255, 4) < make_key(256, 4));
+        assert_eq!(make_value(7, 0, 11).len(), 11);
+        assert_eq!(key_space(2), 65536);
+
+        let mix = Mix::parse("read=1, delete=0").unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!((0..100).all(|_| mix.next(&mut rng) == OpKind::Read));
+        assert!(Mix::parse("read=0").is_err());
+        assert!(Mix::parse("write=1").is_err());
+        assert!(Mix::of(Workload::Load).is_empty());
+        assert_eq!(Mix::of(Workload::B).describe(), "read=95,update=5");
+    }
+}
@@ -0,0 +1,108 @@
+use clap::ValueEnum;
+
+use bpts::{prelude::*, tools};
+
+/// how keys and values are written on the command line.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Encoding {
+    Utf8,
+    Hex,
+    /// decimal number as 4 little-endian bytes
+    U32le,
+    /// decimal number as 8 big-endian bytes
+    U64be,
+}
+
+fn bad_input(s: &str, encoding: Encoding) -> bpts::Error {
+    bpts::Error::InvalidParams(format!("'{}' is not {:?}", s, encoding))
+}
+
+impl Encoding {
+    pub fn parse(self, s: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(s.as_bytes().to_vec()),
+            Encoding::Hex => {
+                let digits = s.strip_prefix("0x").unwrap_or(s);
+                tools::from_hex(digits).ok_or_else(|| bad_input(s, self))
+            }
+            Encoding::U32le => match s.parse::<u32>() {
+                Ok(v) => Ok(v.to_le_bytes().to_vec()),
+                Err(_) => Err(bad_input(s, self)),
+            },
+            Encoding::U64be => match s.parse::<u64>() {
+                Ok(v) => Ok(v.to_be_bytes().to_vec()),
+                Err(_) => Err(bad_input(s, self)),
+            },
+        }
+    }
+
+    /// bytes, which do not fit the encoding, are written as `0x` hex.
+    pub fn format(self, bytes: &[u8]) -> String {
+        let text = match self {
+            Encoding::Utf8 => std::str::from_utf8(bytes).ok().map(|s| s.to_owned()),
+            Encoding::Hex => None,
+            Encoding::U32le => bytes
+                .try_into()
+                .ok()
+                .map(|b| u32::from_le_bytes(b).to_string()),
+            Encoding::U64be => bytes
+                .try_into()
+                .ok()
+                .map(|b| u64::from_be_bytes(b).to_string()),
+        };
+        text.unwrap_or_else(|| format!("0x{}", tools::to_hex(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        assert_eq!(Encoding::Utf8.parse("key")?, b"key");
+        assert_eq!(Encoding::Utf8.parse("")?, b"");
+        assert_eq!(Encoding::Hex.parse("0x01ff")?, [1, 0xff]);
+        assert_eq!(Encoding::Hex.parse("01FF")?, [1, 0xff]);
+        assert_eq!(Encoding::U32le.parse("258")?, [2, 1, 0, 0]);
+        assert_eq!(Encoding::U64be.parse("258")?, [0, 0, 0, 0, 0, 0, 1, 2]);
+
+        for (encoding, s) in [
+            (Encoding::Hex, "0x1"),
+            (Encoding::Hex, "zz"),
+            (Encoding::U32le, "-1"),
+            (Encoding::U32le, "4294967296"),
+            (Encoding::U64be, "x"),
+        ] {
+            assert!(matches!(
+                encoding.parse(s),
+                Err(bpts::Error::InvalidParams(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn format() -> Result<()> {
+        assert_eq!(Encoding::Utf8.format(b"key"), "key");
+        assert_eq!(Encoding::Utf8.format(&[0xff, 0]), "0xff00");
+        assert_eq!(Encoding::Hex.format(b"ab"), "0x6162");
+        assert_eq!(Encoding::U32le.format(&[2, 1, 0, 0]), "258");
+        assert_eq!(Encoding::U32le.format(&[1, 2]), "0x0102");
+        assert_eq!(Encoding::U64be.format(&258u64.to_be_bytes()), "258");
+
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Hex,
+            Encoding::U32le,
+            Encoding::U64be,
+        ] {
+            for s in ["1", "4096", "0x10"] {
+                if let Ok(bytes) = encoding.parse(s) {
+                    assert_eq!(encoding.parse(&encoding.format(&bytes))?, bytes);
+                }
+            }
+        }
+        Ok(())
+    }
+}
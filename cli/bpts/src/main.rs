@@ -0,0 +1,217 @@
+mod encoding;
+mod shell;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use bpts::{
+    prelude::*,
+    storage::file_storage::FileStorage,
+    tools::{self, DumpFormat, SalvageMode},
+};
+
+use shell::{Op, Session, Target};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "bpts storage tools", long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// writes all trees of the storage to a dump
+    Export {
+        filename: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+
+        /// comparator of all trees
+        #[arg(long, default_value = "bytewise")]
+        cmp: String,
+
+        /// dump file. stdout by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// loads a dump into a new storage
+    Import {
+        dump: PathBuf,
+
+        filename: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+    },
+    /// prints records of the file with their offsets
+    Inspect {
+        filename: PathBuf,
+
+        /// writes the layout as json
+        #[arg(long)]
+        json: bool,
+
+        /// output file. stdout by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// rebuilds trees of a damaged file from its kv records into a new file
+    Salvage {
+        damaged: PathBuf,
+
+        filename: PathBuf,
+
+        /// keeps all records instead of the last commit. removed keys come back
+        #[arg(long)]
+        all: bool,
+
+        /// comparator of all trees
+        #[arg(long, default_value = "bytewise")]
+        cmp: String,
+    },
+    /// opens an existing file and runs the command. without a command reads commands from stdin
+    Open {
+        #[command(flatten)]
+        target: Target,
+
+        #[command(subcommand)]
+        op: Option<Op>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    fn dump_format(self) -> DumpFormat {
+        match self {
+            Format::Jsonl => DumpFormat::JsonLines,
+            Format::Csv => DumpFormat::Csv,
+        }
+    }
+}
+
+pub(crate) fn find_cmp(name: &str) -> Result<Rc<RefCell<dyn KeyCmp>>> {
+    match tools::builtin_comparators().remove(name) {
+        Some(c) => Ok(c),
+        None => Err(bpts::Error::NotFound(format!("comparator '{}'", name))),
+    }
+}
+
+pub(crate) fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Command::Export {
+            filename,
+            format,
+            cmp,
+            output,
+        } => {
+            let fstore = Rc::new(RefCell::new(FileStorage::open(path_str(&filename))?));
+            let mut storage =
+                Storage::open(fstore, HashMap::new())?.with_default_cmp(find_cmp(&cmp)?);
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout().lock())),
+            };
+            let count = tools::export(&mut storage, format.dump_format(), &mut out)?;
+            eprintln!("exported {} records", count);
+        }
+        Command::Import {
+            dump,
+            filename,
+            format,
+        } => {
+            if filename.exists() {
+                return Err(bpts::Error::InvalidParams(format!(
+                    "{} already exists",
+                    filename.display()
+                )));
+            }
+            let mut input = BufReader::new(File::open(&dump)?);
+            let fstore = Rc::new(RefCell::new(FileStorage::new(path_str(&filename))?));
+            let mut storage = tools::import(
+                &mut input,
+                format.dump_format(),
+                fstore,
+                &StorageParams::default(),
+                &tools::builtin_comparators(),
+            )?;
+            eprintln!("imported {} trees", storage.tree_ids()?.len());
+            storage.close()?;
+        }
+        Command::Inspect {
+            filename,
+            json,
+            output,
+        } => {
+            let layout = tools::inspect_file(&filename)?;
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout().lock())),
+            };
+            if json {
+                layout.write_json(&mut out)?;
+            } else {
+                layout.write_text(&mut out)?;
+            }
+        }
+        Command::Salvage {
+            damaged,
+            filename,
+            all,
+            cmp,
+        } => {
+            if filename.exists() {
+                return Err(bpts::Error::InvalidParams(format!(
+                    "{} already exists",
+                    filename.display()
+                )));
+            }
+            let data = std::fs::read(&damaged)?;
+            let mode = if all {
+                SalvageMode::AllRecords
+            } else {
+                SalvageMode::LastCommit
+            };
+            let fstore = Rc::new(RefCell::new(FileStorage::new(path_str(&filename))?));
+            let (mut storage, report) =
+                tools::salvage(&data, mode, fstore, HashMap::new(), Some(find_cmp(&cmp)?))?;
+            storage.close()?;
+            println!("{}", report);
+        }
+        Command::Open { target, op } => {
+            let mut session = Session::open(target)?;
+            match op {
+                Some(op) => {
+                    session.run(op)?;
+                }
+                None => session.interactive()?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,351 @@
+use clap::{Args, Parser, Subcommand};
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use bpts::{prelude::*, storage::file_storage::FileStorage, tools};
+
+use crate::{encoding::Encoding, find_cmp, path_str};
+
+#[derive(Args, Debug)]
+pub struct Target {
+    filename: PathBuf,
+
+    /// encoding of keys
+    #[arg(short, long, value_enum, default_value_t = Encoding::Utf8)]
+    key: Encoding,
+
+    /// encoding of values
+    #[arg(short, long, value_enum, default_value_t = Encoding::Utf8)]
+    value: Encoding,
+
+    /// current tree
+    #[arg(short, long, default_value_t = 1)]
+    tree: u32,
+
+    /// comparator of all trees
+    #[arg(long, default_value = "bytewise")]
+    cmp: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Op {
+    /// prints the value of the key
+    Get { key: String },
+    /// inserts or replaces the value of the key
+    Put { key: String, value: String },
+    /// removes the key
+    Del { key: String },
+    /// prints records with keys in [from, to]
+    Scan {
+        from: Option<String>,
+        to: Option<String>,
+
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+    /// prints trees of the file
+    Trees,
+    /// prints sizes of the file and its trees
+    Stats,
+    /// checks invariants of all trees
+    Check,
+    /// prints all records of the current tree
+    Dump {
+        /// prints nodes of the tree as a graphviz graph
+        #[arg(long)]
+        dot: bool,
+    },
+    /// rewrites the file without old versions of nodes and removed records
+    Compact,
+    /// switches the current tree
+    Use { tree: u32 },
+    /// leaves the shell
+    #[command(alias = "exit")]
+    Quit,
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    no_binary_name = true,
+    disable_version_flag = true,
+    override_usage = "<COMMAND> [ARGS]..."
+)]
+struct ShellLine {
+    #[command(subcommand)]
+    op: Op,
+}
+
+fn open(filename: &Path, cmp: &str) -> Result<Storage> {
+    if !filename.exists() {
+        return Err(bpts::Error::NotFound(filename.display().to_string()));
+    }
+    let fstore = Rc::new(RefCell::new(FileStorage::open(path_str(filename))?));
+    Ok(Storage::open(fstore, HashMap::new())?.with_default_cmp(find_cmp(cmp)?))
+}
+
+fn record(target: &Target, key: &[u8], value: &[u8]) -> String {
+    format!("{}\t{}", target.key.format(key), target.value.format(value))
+}
+
+pub struct Session {
+    target: Target,
+    storage: Storage,
+}
+
+impl Session {
+    pub fn open(target: Target) -> Result<Self> {
+        let storage = open(&target.filename, &target.cmp)?;
+        Ok(Session { target, storage })
+    }
+
+    /// returns false after `quit`.
+    pub fn run(&mut self, op: Op) -> Result<bool> {
+        let tree = self.target.tree;
+        match op {
+            Op::Get { key } => {
+                let key = self.target.key.parse(&key)?;
+                match self.storage.find(tree, &key)? {
+                    Some(value) => println!("{}", self.target.value.format(&value)),
+                    None => println!("not found"),
+                }
+            }
+            Op::Put { key, value } => {
+                let key = self.target.key.parse(&key)?;
+                let value = self.target.value.parse(&value)?;
+                let tr = self.storage.begin_transaction()?;
+                self.storage.delete(tr, tree, &key)?;
+                self.storage.insert(tr, tree, &key, &value)?;
+                self.storage.commit_transaction(tr)?;
+            }
+            Op::Del { key } => {
+                let key = self.target.key.parse(&key)?;
+                let tr = self.storage.begin_transaction()?;
+                let found = self.storage.delete(tr, tree, &key)?;
+                self.storage.commit_transaction(tr)?;
+                if !found {
+                    println!("not found");
+                }
+            }
+            Op::Scan { from, to, limit } => self.scan(from, to, limit)?,
+            Op::Trees => {
+                for tree_id in self.storage.tree_ids()? {
+                    let keys = self.storage.stats(tree_id)?.keys;
+                    let cmp = self.storage.cmp_name(tree_id)?;
+                    println!("{}\t{}\t{} keys", tree_id, cmp, keys);
+                }
+            }
+            Op::Stats => {
+                let stats = self.storage.file_stats()?;
+                println!("total bytes:       {}", stats.total_bytes);
+                println!("kv bytes:          {}", stats.kv_bytes);
+                println!("node bytes:        {}", stats.node_bytes);
+                println!("transaction bytes: {}", stats.transaction_bytes);
+                println!("live bytes:        {}", stats.live_bytes);
+                println!("garbage bytes:     {}", stats.garbage_bytes);
+                for t in stats.trees.iter() {
+                    println!(
+                        "tree {}: height {}, nodes {}, leafs {}, keys {}",
+                        t.tree_id, t.height, t.nodes, t.leafs, t.keys
+                    );
+                }
+            }
+            Op::Check => {
+                let violations = self.storage.check()?;
+                for (tree_id, v) in violations.iter() {
+                    println!("tree {}: {:?}", tree_id, v);
+                }
+                if violations.is_empty() {
+                    println!("ok");
+                }
+            }
+            Op::Dump { dot } => {
+                if dot {
+                    println!("digraph G {{");
+                    // the tree is loaded by the read.
+                    if self.storage.first(tree)?.is_some() {
+                        println!("{}", self.storage.dump_tree(tree, format!("tree{}", tree)));
+                    }
+                    println!("}}");
+                } else {
+                    let mut out = std::io::stdout().lock();
+                    self.storage.for_each(tree, |key, value| {
+                        writeln!(out, "{}", record(&self.target, key, value))?;
+                        Ok(())
+                    })?;
+                }
+            }
+            Op::Compact => self.compact()?,
+            Op::Use { tree } => self.target.tree = tree,
+            Op::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn scan(
+        &mut self,
+        from: Option<String>,
+        to: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<()> {
+        let tree = self.target.tree;
+        let from = from.map(|key| self.target.key.parse(&key)).transpose()?;
+        let to = to.map(|key| self.target.key.parse(&key)).transpose()?;
+        let mut left = limit.unwrap_or(usize::MAX);
+        if left == 0 {
+            return Ok(());
+        }
+        let mut out = std::io::stdout().lock();
+        self.storage
+            .scan_range(tree, from.as_deref(), to.as_deref(), |key, value| {
+                writeln!(out, "{}", record(&self.target, key, value))?;
+                left -= 1;
+                Ok(left > 0)
+            })
+    }
+
+    /// writes live records to `<file>.compact` and replaces the file with it.
+    fn compact(&mut self) -> Result<()> {
+        let filename = self.target.filename.clone();
+        let mut tmp = filename.clone().into_os_string();
+        tmp.push(".compact");
+        let tmp = PathBuf::from(tmp);
+        if tmp.exists() {
+            return Err(bpts::Error::InvalidParams(format!(
+                "{} already exists",
+                tmp.display()
+            )));
+        }
+
+        let before = std::fs::metadata(&filename)?.len();
+        let fstore = Rc::new(RefCell::new(FileStorage::new(path_str(&tmp))?));
+        let res = tools::compact(&mut self.storage, fstore, &tools::builtin_comparators())
+            .and_then(|mut compacted| compacted.close());
+        if let Err(e) = res {
+            std::fs::remove_file(&tmp)?;
+            return Err(e);
+        }
+        std::fs::rename(&tmp, &filename)?;
+        self.storage = open(&filename, &self.target.cmp)?;
+        let after = std::fs::metadata(&filename)?.len();
+        println!("{} -> {} bytes", before, after);
+        Ok(())
+    }
+
+    /// reads commands from stdin until `quit` or the end of the input.
+    pub fn interactive(&mut self) -> Result<()> {
+        let stdin = std::io::stdin();
+        let prompt = stdin.is_terminal();
+        let mut lines = stdin.lock().lines();
+        loop {
+            if prompt {
+                print!("bpts> ");
+                std::io::stdout().flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let words = match split_words(&line) {
+                Some(words) => words,
+                None => {
+                    eprintln!("error: unclosed quote");
+                    continue;
+                }
+            };
+            if words.is_empty() {
+                continue;
+            }
+            let op = match ShellLine::try_parse_from(words) {
+                Ok(l) => l.op,
+                Err(e) => {
+                    let _ = e.print();
+                    continue;
+                }
+            };
+            match self.run(op) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+    }
+}
+
+/// splits the line by spaces. double quotes keep spaces, `\` escapes the next char.
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut result = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            c if c.is_whitespace() && !quoted => {
+                if let Some(w) = word.take() {
+                    result.push(w);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    result.extend(word);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn split_line() {
+        assert_eq!(split_words(""), Some(Vec::<String>::new()));
+        assert_eq!(split_words("  \t "), Some(Vec::<String>::new()));
+        assert_eq!(split_words("get key"), Some(strings(&["get", "key"])));
+        assert_eq!(
+            split_words("  put  a   b "),
+            Some(strings(&["put", "a", "b"]))
+        );
+        assert_eq!(
+            split_words(r#"put "a key" "value with \"quotes\"""#),
+            Some(strings(&["put", "a key", r#"value with "quotes""#]))
+        );
+        assert_eq!(split_words(r#"put "" x"#), Some(strings(&["put", "", "x"])));
+        assert_eq!(split_words(r#"get a\ b"#), Some(strings(&["get", "a b"])));
+        assert_eq!(
+            split_words(r#"get ab"cd ef"gh"#),
+            Some(strings(&["get", "abcd efgh"]))
+        );
+        assert_eq!(split_words(r#"get "unclosed"#), None);
+        assert_eq!(split_words(r#"get a\"#), None);
+    }
+
+    #[test]
+    fn parse_line() {
+        let op = |line: &str| ShellLine::try_parse_from(split_words(line).unwrap()).map(|l| l.op);
+        assert!(matches!(
+            op("scan a z --limit 5"),
+            Ok(Op::Scan { from: Some(f), to: Some(t), limit: Some(5) }) if f == "a" && t == "z"
+        ));
+        assert!(matches!(op("exit"), Ok(Op::Quit)));
+        assert!(matches!(op("use 3"), Ok(Op::Use { tree: 3 })));
+        assert!(op("use x").is_err());
+        assert!(op("unknown").is_err());
+    }
+}
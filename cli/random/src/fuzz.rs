@@ -0,0 +1,598 @@
+use rand::prelude::*;
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use bpts::{
+    prelude::*,
+    storage::{
+        buffile_storage::BufFileStorage, file_storage::FileStorage, memory_storage::MemoryStorage,
+    },
+    tools::{from_hex, to_hex},
+    tree::TreeParams,
+};
+
+/// operations of the differential test. writes go to the open transaction or to
+/// a transaction of one operation, reads see the last commit.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Insert {
+        tree: u32,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Remove {
+        tree: u32,
+        key: Vec<u8>,
+    },
+    Find {
+        tree: u32,
+        key: Vec<u8>,
+    },
+    Scan {
+        tree: u32,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+    Begin,
+    Commit,
+    Rollback,
+    /// closes the storage and opens it again, the open transaction is lost.
+    Reopen,
+    Check,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Insert { tree, key, value } => {
+                write!(f, "insert {} {} {}", tree, hex(key), hex(value))
+            }
+            Op::Remove { tree, key } => write!(f, "remove {} {}", tree, hex(key)),
+            Op::Find { tree, key } => write!(f, "find {} {}", tree, hex(key)),
+            Op::Scan { tree, from, to } => write!(f, "scan {} {} {}", tree, hex(from), hex(to)),
+            Op::Begin => write!(f, "begin"),
+            Op::Commit => write!(f, "commit"),
+            Op::Rollback => write!(f, "rollback"),
+            Op::Reopen => write!(f, "reopen"),
+            Op::Check => write!(f, "check"),
+        }
+    }
+}
+
+/// empty bytes are written as `-`.
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        "-".to_owned()
+    } else {
+        to_hex(bytes)
+    }
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        Some(Vec::new())
+    } else {
+        from_hex(s)
+    }
+}
+
+impl Op {
+    pub fn parse(line: &str) -> Option<Op> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let tree = || words.get(1)?.parse::<u32>().ok();
+        let bytes = |n: usize| unhex(words.get(n)?);
+        let op = match words.first().copied()? {
+            "insert" if words.len() == 4 => Op::Insert {
+                tree: tree()?,
+                key: bytes(2)?,
+                value: bytes(3)?,
+            },
+            "remove" if words.len() == 3 => Op::Remove {
+                tree: tree()?,
+                key: bytes(2)?,
+            },
+            "find" if words.len() == 3 => Op::Find {
+                tree: tree()?,
+                key: bytes(2)?,
+            },
+            "scan" if words.len() == 4 => Op::Scan {
+                tree: tree()?,
+                from: bytes(2)?,
+                to: bytes(3)?,
+            },
+            "begin" if words.len() == 1 => Op::Begin,
+            "commit" if words.len() == 1 => Op::Commit,
+            "rollback" if words.len() == 1 => Op::Rollback,
+            "reopen" if words.len() == 1 => Op::Reopen,
+            "check" if words.len() == 1 => Op::Check,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+/// one operation per line, `#` starts a comment.
+pub fn parse_ops(text: &str) -> std::result::Result<Vec<Op>, String> {
+    let mut result = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match Op::parse(line) {
+            Some(op) => result.push(op),
+            None => return Err(format!("line {}: bad operation '{}'", n + 1, line)),
+        }
+    }
+    Ok(result)
+}
+
+pub fn format_ops(ops: &[Op]) -> String {
+    let mut result = String::new();
+    for op in ops.iter() {
+        result.push_str(&op.to_string());
+        result.push('\n');
+    }
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Backend {
+    File,
+    Buf,
+    Mem,
+}
+
+impl Backend {
+    pub fn all() -> [Backend; 3] {
+        [Backend::File, Backend::Buf, Backend::Mem]
+    }
+}
+
+pub struct GenParams {
+    pub trees: u32,
+    /// keys are big-endian u16 numbers below `keys`
+    pub keys: u16,
+    pub max_value: usize,
+}
+
+pub fn generate(rng: &mut StdRng, len: usize, params: &GenParams) -> Vec<Op> {
+    let mut result = Vec::with_capacity(len);
+    let key = |rng: &mut StdRng| rng.gen_range(0..params.keys).to_be_bytes().to_vec();
+    for _ in 0..len {
+        let tree = rng.gen_range(1..=params.trees);
+        let op = match rng.gen_range(0..100) {
+            0..=39 => {
+                let value_len = rng.gen_range(0..=params.max_value);
+                let mut value = vec![0u8; value_len];
+                rng.fill_bytes(&mut value);
+                Op::Insert {
+                    tree,
+                    key: key(rng),
+                    value,
+                }
+            }
+            40..=54 => Op::Remove {
+                tree,
+                key: key(rng),
+            },
+            55..=69 => Op::Find {
+                tree,
+                key: key(rng),
+            },
+            70..=77 => {
+                let a = key(rng);
+                let b = key(rng);
+                let (from, to) = if a <= b { (a, b) } else { (b, a) };
+                Op::Scan { tree, from, to }
+            }
+            78..=85 => Op::Begin,
+            86..=92 => Op::Commit,
+            93..=95 => Op::Rollback,
+            96..=97 => Op::Reopen,
+            _ => Op::Check,
+        };
+        result.push(op);
+    }
+    result
+}
+
+type Tree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// the expected state: committed trees and trees of the open transaction.
+#[derive(Default)]
+struct Model {
+    committed: BTreeMap<u32, Tree>,
+    pending: Option<BTreeMap<u32, Tree>>,
+}
+
+struct Subject {
+    backend: Backend,
+    path: PathBuf,
+    t: usize,
+    store: Rc<RefCell<dyn FlatStorage>>,
+    /// the store of the memory backend, reopened from a snapshot in `path`.
+    memory: Option<Rc<RefCell<MemoryStorage>>>,
+    storage: Storage,
+    transaction: Option<u64>,
+}
+
+fn cmp() -> HashMap<u32, Rc<RefCell<dyn KeyCmp>>> {
+    HashMap::new()
+}
+
+impl Subject {
+    fn new(backend: Backend, dir: &Path, t: usize) -> Result<Self> {
+        let path = dir.join(format!("{:?}.db", backend).to_lowercase());
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        let name = path.to_str().unwrap();
+        let memory = match backend {
+            Backend::Mem => Some(Rc::new(RefCell::new(MemoryStorage::new()))),
+            _ => None,
+        };
+        let store: Rc<RefCell<dyn FlatStorage>> = match backend {
+            Backend::File => Rc::new(RefCell::new(FileStorage::new(name)?)),
+            Backend::Buf => Rc::new(RefCell::new(BufFileStorage::new(name, 256)?)),
+            Backend::Mem => memory.clone().unwrap(),
+        };
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(t).with_min_size_root(2);
+        let storage = Storage::new(store.clone(), &params, cmp())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        Ok(Subject {
+            backend,
+            path,
+            t,
+            store,
+            memory,
+            storage,
+            transaction: None,
+        })
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        self.transaction = None;
+        self.storage.close()?;
+        self.store.borrow().close()?;
+        let name = self.path.to_str().unwrap();
+        match self.backend {
+            Backend::File => self.store = Rc::new(RefCell::new(FileStorage::open(name)?)),
+            Backend::Buf => self.store = Rc::new(RefCell::new(BufFileStorage::open(name, 256)?)),
+            Backend::Mem => {
+                self.memory
+                    .as_ref()
+                    .unwrap()
+                    .borrow()
+                    .save_to_file(&self.path)?;
+                let memory = Rc::new(RefCell::new(MemoryStorage::load_from_file(&self.path)?));
+                self.store = memory.clone();
+                self.memory = Some(memory);
+            }
+        }
+        self.storage = Storage::open(self.store.clone(), cmp())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        if self.storage.params().tree_params.t != self.t {
+            return Err(bpts::Error::Fail("params are not restored".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// runs `f` in the open transaction or in a new one.
+    fn write<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Storage, u64) -> Result<T>,
+    {
+        if let Some(tr) = self.transaction {
+            return f(&mut self.storage, tr);
+        }
+        let tr = self.storage.begin_transaction()?;
+        let res = f(&mut self.storage, tr)?;
+        self.storage.commit_transaction(tr)?;
+        Ok(res)
+    }
+
+    fn scan(&mut self, tree: u32, from: &[u8], to: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut result = Vec::new();
+        let mut cur = self.storage.ceiling(tree, from)?;
+        while let Some((key, value)) = cur {
+            if key.as_slice() > to {
+                break;
+            }
+            cur = self.storage.higher(tree, &key)?;
+            result.push((key, value));
+        }
+        Ok(result)
+    }
+}
+
+/// a difference between the storage and the model.
+#[derive(Debug)]
+pub struct Failure {
+    pub step: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {}", self.step, self.message)
+    }
+}
+
+fn expect<T: PartialEq + fmt::Debug>(actual: T, expected: T) -> std::result::Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {:?}, got {:?}", expected, actual))
+    }
+}
+
+fn step(subject: &mut Subject, model: &mut Model, op: &Op) -> std::result::Result<(), String> {
+    let e = |e: bpts::Error| format!("error {:?}", e);
+    match op {
+        Op::Insert { tree, key, value } => {
+            subject
+                .write(|s, tr| {
+                    s.delete(tr, *tree, key)?;
+                    s.insert(tr, *tree, key, value)
+                })
+                .map_err(e)?;
+            let target = model.pending.as_mut().unwrap_or(&mut model.committed);
+            target
+                .entry(*tree)
+                .or_default()
+                .insert(key.clone(), value.clone());
+        }
+        Op::Remove { tree, key } => {
+            let found = subject.write(|s, tr| s.delete(tr, *tree, key)).map_err(e)?;
+            let target = model.pending.as_mut().unwrap_or(&mut model.committed);
+            let expected = target.entry(*tree).or_default().remove(key).is_some();
+            expect(found, expected)?;
+        }
+        Op::Find { tree, key } => {
+            let value = subject.storage.find(*tree, key).map_err(e)?;
+            let expected = model.committed.get(tree).and_then(|t| t.get(key)).cloned();
+            expect(value, expected)?;
+        }
+        Op::Scan { tree, from, to } => {
+            let records = subject.scan(*tree, from, to).map_err(e)?;
+            let expected: Vec<(Vec<u8>, Vec<u8>)> = match model.committed.get(tree) {
+                Some(t) => t
+                    .range(from.clone()..=to.clone())
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                None => Vec::new(),
+            };
+            expect(records, expected)?;
+        }
+        Op::Begin => {
+            if subject.transaction.is_none() {
+                subject.transaction = Some(subject.storage.begin_transaction().map_err(e)?);
+                model.pending = Some(model.committed.clone());
+            }
+        }
+        Op::Commit => {
+            if let Some(tr) = subject.transaction.take() {
+                subject.storage.commit_transaction(tr).map_err(e)?;
+                model.committed = model.pending.take().unwrap();
+            }
+        }
+        Op::Rollback => {
+            if let Some(tr) = subject.transaction.take() {
+                subject.storage.rollback_transaction(tr).map_err(e)?;
+                model.pending = None;
+            }
+        }
+        Op::Reopen => {
+            subject.reopen().map_err(e)?;
+            model.pending = None;
+        }
+        Op::Check => {
+            let violations = subject.storage.check().map_err(e)?;
+            expect(violations, Vec::new())?;
+            for (tree, records) in model.committed.iter() {
+                let mut actual = Vec::new();
+                subject
+                    .storage
+                    .for_each(*tree, |k, v| {
+                        actual.push((k.to_vec(), v.to_vec()));
+                        Ok(())
+                    })
+                    .map_err(e)?;
+                let expected: Vec<(Vec<u8>, Vec<u8>)> = records
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                expect(actual, expected)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// runs `ops` on a new storage in `dir` and compares results with the model.
+/// the last step reopens the storage and checks all trees. panics are failures too.
+pub fn run(backend: Backend, dir: &Path, t: usize, ops: &[Op]) -> std::result::Result<(), Failure> {
+    let tail = [Op::Reopen, Op::Check];
+    let all: Vec<&Op> = ops.iter().chain(tail.iter()).collect();
+    let mut at = 0;
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut subject = Subject::new(backend, dir, t).map_err(|e| format!("error {:?}", e))?;
+        let mut model = Model::default();
+        for (n, op) in all.iter().enumerate() {
+            at = n;
+            step(&mut subject, &mut model, op)?;
+        }
+        Ok(())
+    }));
+    let message = match res {
+        Ok(Ok(())) => return Ok(()),
+        Ok(Err(message)) => message,
+        Err(panic) => {
+            let message = if let Some(s) = panic.downcast_ref::<String>() {
+                s.clone()
+            } else if let Some(s) = panic.downcast_ref::<&str>() {
+                s.to_string()
+            } else {
+                "unknown".to_owned()
+            };
+            format!("panic: {}", message)
+        }
+    };
+    Err(Failure {
+        step: at,
+        message: format!("{}: {}", all[at], message),
+    })
+}
+
+/// removes operations while `fails` holds: chunks of halving size, then single
+/// operations. returns the shortest failing sequence found.
+pub fn shrink<F>(ops: &[Op], mut fails: F) -> Vec<Op>
+where
+    F: FnMut(&[Op]) -> bool,
+{
+    let mut result = ops.to_vec();
+    let mut chunk = std::cmp::max(result.len() / 2, 1);
+    loop {
+        let mut changed = false;
+        let mut start = 0;
+        while start < result.len() {
+            let end = std::cmp::min(start + chunk, result.len());
+            let mut candidate = result[..start].to_vec();
+            candidate.extend_from_slice(&result[end..]);
+            if fails(&candidate) {
+                result = candidate;
+                changed = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if chunk == 1 && !changed {
+            return result;
+        }
+        if !changed {
+            chunk = std::cmp::max(chunk / 2, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regressions() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("regressions")
+    }
+
+    #[test]
+    fn ops_text() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let params = GenParams {
+            trees: 2,
+            keys: 100,
+            max_value: 4,
+        };
+        let ops = generate(&mut rng, 500, &params);
+        assert_eq!(parse_ops(&format_ops(&ops)).unwrap(), ops);
+        assert!(parse_ops("insert 1 00").is_err());
+        assert_eq!(
+            parse_ops("# comment\n\nremove 2 0a # key 10\n").unwrap(),
+            [Op::Remove {
+                tree: 2,
+                key: vec![10]
+            }]
+        );
+    }
+
+    #[test]
+    fn random_sequences() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let params = GenParams {
+            trees: 2,
+            keys: 64,
+            max_value: 8,
+        };
+        for seed in 0..4 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let ops = generate(&mut rng, 300, &params);
+            for backend in Backend::all() {
+                if let Err(f) = run(backend, tempdir.path(), 3, &ops) {
+                    panic!("seed {} {:?}: {}", seed, backend, f);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shrinks_to_reproducer() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let params = GenParams {
+            trees: 2,
+            keys: 16,
+            max_value: 2,
+        };
+        let ops = generate(&mut rng, 300, &params);
+        // fails, if a rollback follows a remove from tree 2.
+        let fails = |ops: &[Op]| {
+            let remove = ops
+                .iter()
+                .position(|op| matches!(op, Op::Remove { tree: 2, .. }));
+            remove.is_some_and(|n| ops[n..].contains(&Op::Rollback))
+        };
+        assert!(fails(&ops));
+        let min = shrink(&ops, fails);
+        assert_eq!(min.len(), 2);
+        assert!(matches!(min[0], Op::Remove { tree: 2, .. }));
+        assert_eq!(min[1], Op::Rollback);
+    }
+
+    #[test]
+    fn saved_regressions() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut files: Vec<PathBuf> = std::fs::read_dir(regressions())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "ops"))
+            .collect();
+        files.sort();
+        assert!(!files.is_empty());
+        for file in files.iter() {
+            let ops = parse_ops(&std::fs::read_to_string(file).unwrap()).unwrap();
+            for backend in Backend::all() {
+                if let Err(f) = run(backend, tempdir.path(), 3, &ops) {
+                    panic!("{} {:?}: {}", file.display(), backend, f);
+                }
+            }
+        }
+    }
+
+    /// found with `t` = 2: an internal node is left with one child and no keys after a merge.
+    #[test]
+    fn internal_node_with_one_child() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let ops = parse_ops(
+            "insert 1 014a 01\n\
+             insert 1 00c8 02\n\
+             insert 1 00c7 03\n\
+             insert 1 004f 04\n\
+             insert 1 0069 05\n\
+             insert 1 00ff 06\n\
+             insert 1 00c0 07\n\
+             insert 1 01c2 08\n\
+             remove 1 0069\n",
+        )
+        .unwrap();
+        for backend in Backend::all() {
+            if let Err(f) = run(backend, tempdir.path(), 2, &ops) {
+                panic!("{:?}: {}", backend, f);
+            }
+        }
+    }
+}
@@ -10,10 +10,164 @@ use bpts::{
     tree::{mocks::MockNodeStorage, node::Node, TreeParams},
     types::Id,
 };
+use clap::{Parser, Subcommand};
 use rand::prelude::*;
+use std::path::PathBuf;
 use std::time::Instant;
 
+mod fuzz;
+
+use fuzz::{Backend, GenParams};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// inserts, finds and removes shuffled numbers in trees with t from 4 to 100
+    Tree,
+    /// compares random operations on a storage with a model
+    Storage {
+        /// seed of the first run, next runs use the next seeds
+        #[arg(short, long, default_value_t = 0)]
+        seed: u64,
+
+        #[arg(short, long, default_value_t = 100)]
+        runs: u64,
+
+        /// operations in a run
+        #[arg(short, long, default_value_t = 500)]
+        len: usize,
+
+        /// all backends by default
+        #[arg(short, long, value_enum)]
+        backend: Option<Backend>,
+
+        #[arg(short, long, default_value_t = 3)]
+        t: usize,
+
+        #[arg(long, default_value_t = 2)]
+        trees: u32,
+
+        /// count of distinct keys
+        #[arg(long, default_value_t = 64)]
+        keys: u16,
+
+        #[arg(long, default_value_t = 8)]
+        max_value: usize,
+
+        /// runs operations from the file instead of random ones
+        #[arg(long)]
+        replay: Option<PathBuf>,
+
+        /// writes the shrunk failing sequence to the file. files in `regressions`
+        /// are replayed by tests with t = 3
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+}
+
 fn main() {
+    let args = Args::parse();
+    match args.command {
+        None | Some(Command::Tree) => trees(),
+        Some(Command::Storage {
+            seed,
+            runs,
+            len,
+            backend,
+            t,
+            trees,
+            keys,
+            max_value,
+            replay,
+            save,
+        }) => {
+            let backends = match backend {
+                Some(b) => vec![b],
+                None => Backend::all().to_vec(),
+            };
+            let sequences: Vec<(String, Vec<fuzz::Op>)> = match replay {
+                Some(path) => {
+                    let text = std::fs::read_to_string(&path).unwrap();
+                    let ops = fuzz::parse_ops(&text).unwrap_or_else(|e| panic!("{}", e));
+                    vec![(path.display().to_string(), ops)]
+                }
+                None => {
+                    let params = GenParams {
+                        trees,
+                        keys,
+                        max_value,
+                    };
+                    (seed..seed + runs)
+                        .map(|s| {
+                            let mut rng = StdRng::seed_from_u64(s);
+                            (
+                                format!("seed {}", s),
+                                fuzz::generate(&mut rng, len, &params),
+                            )
+                        })
+                        .collect()
+                }
+            };
+            let ok = storage(&sequences, &backends, t, save);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+    }
+}
+
+/// returns false after the first failure, which is shrunk and printed.
+fn storage(
+    sequences: &[(String, Vec<fuzz::Op>)],
+    backends: &[Backend],
+    t: usize,
+    save: Option<PathBuf>,
+) -> bool {
+    let tempdir = tempfile::tempdir().unwrap();
+    let dir = tempdir.path();
+    for (name, ops) in sequences.iter() {
+        for backend in backends.iter() {
+            print!("\r{} {:?}    ", name, backend);
+            std::io::stdout().flush().unwrap();
+            let failure = match fuzz::run(*backend, dir, t, ops) {
+                Ok(()) => continue,
+                Err(f) => f,
+            };
+            println!();
+            println!("{:?} failed at {}", backend, failure);
+
+            let hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(|_| {}));
+            let min = fuzz::shrink(ops, |ops| fuzz::run(*backend, dir, t, ops).is_err());
+            let failure = fuzz::run(*backend, dir, t, &min).unwrap_err();
+            std::panic::set_hook(hook);
+
+            println!("shrunk to {} operations, {}", min.len(), failure);
+            let text = format!(
+                "# {}, {:?}: {}\n{}",
+                name,
+                backend,
+                failure,
+                fuzz::format_ops(&min)
+            );
+            print!("{}", text);
+            if let Some(path) = save {
+                std::fs::write(&path, text).unwrap();
+                println!("saved to {}", path.display());
+            }
+            return false;
+        }
+    }
+    println!();
+    println!("{} sequences passed", sequences.len());
+    true
+}
+
+fn trees() {
     let count = 10000;
     let mut rng = rand::thread_rng();
 
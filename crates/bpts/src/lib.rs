@@ -1,7 +1,9 @@
 use std::fmt::Display;
 pub mod prelude;
 pub mod storage;
+pub mod tools;
 pub mod tree;
+pub mod typed;
 pub mod types;
 pub mod utils;
 
@@ -11,11 +13,54 @@ pub enum Error {
     IO(std::io::Error),
     IsFull,
     TransactionNotFound,
+    /// the file content does not match the format.
+    Corrupted(String),
+    UnknownTree(u32),
+    /// the comparator for the tree is not registered.
+    MissingComparator(u32),
+    NotFound(String),
+    InvalidParams(String),
+    /// the key of a unique index is already used by another record.
+    UniqueViolation(u32),
+    /// the file is written in another version of the format.
+    UnsupportedVersion(u32),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error({:?})", self)
+        match self {
+            Error::Fail(msg) => write!(f, "{}", msg),
+            Error::IO(e) => write!(f, "io error: {}", e),
+            Error::IsFull => write!(f, "buffer is full"),
+            Error::TransactionNotFound => write!(f, "transaction not found"),
+            Error::Corrupted(msg) => write!(f, "storage is corrupted: {}", msg),
+            Error::UnknownTree(id) => write!(f, "unknown tree {}", id),
+            Error::MissingComparator(id) => write!(f, "comparator for tree {} not found", id),
+            Error::NotFound(msg) => write!(f, "not found: {}", msg),
+            Error::InvalidParams(msg) => write!(f, "invalid params: {}", msg),
+            Error::UniqueViolation(id) => write!(f, "duplicate key in unique index {}", id),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "file format version {} is not supported, expected {}",
+                v,
+                crate::storage::FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IO(e)
     }
 }
 
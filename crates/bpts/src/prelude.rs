@@ -1,6 +1,14 @@
 pub use crate::Result;
 
+pub use crate::storage::batch::WriteBatch;
+pub use crate::storage::bulk_load::BulkLoadParams;
+pub use crate::storage::changes::{Change, ChangeOp, Commit, CommitObserver};
 pub use crate::storage::flat_storage::FlatStorage;
+pub use crate::storage::index::IndexDef;
+pub use crate::storage::replication::{Follower, Replicator, Transport};
 pub use crate::storage::store::Storage;
+pub use crate::storage::Aggregate;
+pub use crate::storage::BytewiseKeyCmp;
 pub use crate::storage::KeyCmp;
 pub use crate::storage::StorageParams;
+pub use crate::typed::{Key, TypedTree, Value};
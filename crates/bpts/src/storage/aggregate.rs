@@ -0,0 +1,22 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{flat_storage::FlatStorage, store::Storage, Aggregate};
+use crate::tree::{aggregate::NodeAggregate, record::Record};
+
+pub struct StorageNodeAggregate {
+    pub(super) store: Rc<RefCell<dyn FlatStorage>>,
+    pub(super) aggregate: Rc<RefCell<dyn Aggregate>>,
+}
+
+impl NodeAggregate for StorageNodeAggregate {
+    fn summarize(&self, key: u32, _value: &Record) -> crate::Result<Vec<u8>> {
+        let store = self.store.borrow();
+        let k = Storage::read_key(&*store, key as usize)?;
+        let v = Storage::read_kdata(&*store, key as usize)?;
+        Ok(self.aggregate.borrow().summarize(&k, &v))
+    }
+
+    fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        self.aggregate.borrow().combine(left, right)
+    }
+}
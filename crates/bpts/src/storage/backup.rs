@@ -0,0 +1,299 @@
+use std::io::{Read, Write};
+
+use super::{
+    flat_storage::FlatStorage, store::StorageHeader, MAGIC_BACKUP_INCREMENT, MAGIC_HEADER,
+    MAGIC_TRANSACTION_LIST, U32SZ,
+};
+use crate::Result;
+
+/// size of chunks of copies.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// end of the last transaction list. records after it are not committed.
+pub(super) fn committed_end(store: &dyn FlatStorage, header: &StorageHeader) -> Result<usize> {
+    if header.offset == 0 {
+        return Ok(0);
+    }
+    let count = store.read_u32(header.offset as usize + U32SZ)? as usize;
+    Ok(header.offset as usize + 2 * U32SZ + count * U32SZ)
+}
+
+/// digest of the transaction list at `list`, if the list ends at `end`. copies of
+/// a storage have the same digests of the same commits. None - no list ends at `end`.
+pub(super) fn commit_digest(
+    store: &dyn FlatStorage,
+    list: usize,
+    end: usize,
+) -> Result<Option<u64>> {
+    if list == 0 {
+        return Ok(if end == 0 { Some(0) } else { None });
+    }
+    if list.saturating_add(2 * U32SZ) > end || store.read_u32(list)? != MAGIC_TRANSACTION_LIST {
+        return Ok(None);
+    }
+    let count = store.read_u32(list + U32SZ)? as usize;
+    if list + 2 * U32SZ + count * U32SZ != end {
+        return Ok(None);
+    }
+    let mut bytes = vec![0u8; end - list];
+    store.read_bytes(list, &mut bytes)?;
+    // fnv-1a
+    let digest = bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    Ok(Some(digest))
+}
+
+/// appends bytes [from, to) of the source to the target.
+pub(super) fn copy_range(
+    source: &dyn FlatStorage,
+    target: &dyn FlatStorage,
+    from: usize,
+    to: usize,
+) -> Result<()> {
+    let mut buf = vec![0u8; std::cmp::min(to.saturating_sub(from), CHUNK_SIZE)];
+    let mut pos = from;
+    while pos < to {
+        let n = std::cmp::min(to - pos, buf.len());
+        source.read_bytes(pos, &mut buf[..n])?;
+        target.write_bytes(&buf[..n])?;
+        pos += n;
+    }
+    Ok(())
+}
+
+/// copies the committed image of the source to the empty target.
+/// the target must be of the same kind as the source, so offsets match.
+pub(super) fn copy_image(
+    source: &dyn FlatStorage,
+    header: &StorageHeader,
+    target: &dyn FlatStorage,
+) -> Result<usize> {
+    if target.size() != 0 {
+        return Err(crate::Error::InvalidParams(
+            "backup target is not empty".to_owned(),
+        ));
+    }
+    target.params_write(&source.params_read()?)?;
+    let end = committed_end(source, header)?;
+    // the params may be a part of the image.
+    copy_range(source, target, target.size(), end)?;
+    target.flush()?;
+    target.header_write(header)?;
+    target.flush()?;
+    Ok(end)
+}
+
+/// [magic][since][end][header offset][bytes since..end]
+pub(super) fn write_increment(
+    source: &dyn FlatStorage,
+    header: &StorageHeader,
+    since: usize,
+    out: &mut dyn Write,
+) -> Result<usize> {
+    let end = committed_end(source, header)?;
+    if since > end {
+        return Err(crate::Error::InvalidParams(format!(
+            "backup offset {} is after the last commit {}",
+            since, end
+        )));
+    }
+    out.write_all(&MAGIC_BACKUP_INCREMENT.to_le_bytes())?;
+    out.write_all(&(since as u64).to_le_bytes())?;
+    out.write_all(&(end as u64).to_le_bytes())?;
+    out.write_all(&header.offset.to_le_bytes())?;
+    let mut buf = vec![0u8; std::cmp::min(end - since, CHUNK_SIZE)];
+    let mut pos = since;
+    while pos < end {
+        let n = std::cmp::min(end - pos, buf.len());
+        source.read_bytes(pos, &mut buf[..n])?;
+        out.write_all(&buf[..n])?;
+        pos += n;
+    }
+    out.flush()?;
+    Ok(end)
+}
+
+/// size of the head of an increment: [magic][since][end][header offset].
+pub(super) const INCREMENT_HEAD_SIZE: usize = 2 * U32SZ + 2 * 8;
+
+/// full size of the increment by its head.
+pub(super) fn increment_size(head: &[u8]) -> Result<usize> {
+    let bad = || crate::Error::Corrupted("bad backup increment".to_owned());
+    let head = head.get(..INCREMENT_HEAD_SIZE).ok_or_else(bad)?;
+    if u32::from_le_bytes(head[..U32SZ].try_into().unwrap()) != MAGIC_BACKUP_INCREMENT {
+        return Err(crate::Error::Corrupted(
+            "bad backup increment magic".to_owned(),
+        ));
+    }
+    let since = u64::from_le_bytes(head[U32SZ..U32SZ + 8].try_into().unwrap());
+    let end = u64::from_le_bytes(head[U32SZ + 8..U32SZ + 16].try_into().unwrap());
+    end.checked_sub(since)
+        .and_then(|n| usize::try_from(n).ok())
+        .and_then(|n| n.checked_add(INCREMENT_HEAD_SIZE))
+        .ok_or_else(bad)
+}
+
+fn read_array<const N: usize>(input: &mut dyn Read) -> Result<[u8; N]> {
+    let mut result = [0u8; N];
+    input.read_exact(&mut result)?;
+    Ok(result)
+}
+
+/// appends the increment to the image in the target. returns the new committed end.
+pub(super) fn apply_increment(target: &dyn FlatStorage, input: &mut dyn Read) -> Result<usize> {
+    if u32::from_le_bytes(read_array(input)?) != MAGIC_BACKUP_INCREMENT {
+        return Err(crate::Error::Corrupted(
+            "bad backup increment magic".to_owned(),
+        ));
+    }
+    let since = u64::from_le_bytes(read_array(input)?) as usize;
+    let end = u64::from_le_bytes(read_array(input)?) as usize;
+    let offset = u32::from_le_bytes(read_array(input)?);
+
+    let current = committed_end(target, &target.header_read()?)?;
+    if current != since {
+        return Err(crate::Error::InvalidParams(format!(
+            "backup increment starts at {}, the image ends at {}",
+            since, current
+        )));
+    }
+    if end < since || (offset as usize) < since || (offset as usize) >= end {
+        return Err(crate::Error::Corrupted("bad backup increment".to_owned()));
+    }
+
+    // the image may end with the header, which is in the increment too.
+    let mut skip = target.size() - since;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut left = end - since;
+    while left > 0 {
+        let n = std::cmp::min(left, buf.len());
+        input.read_exact(&mut buf[..n])?;
+        left -= n;
+        let from = std::cmp::min(skip, n);
+        skip -= from;
+        target.write_bytes(&buf[from..n])?;
+    }
+    target.flush()?;
+    target.header_write(&StorageHeader {
+        magic: MAGIC_HEADER,
+        offset,
+        is_closed: 0,
+    })?;
+    target.flush()?;
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    use super::*;
+    use crate::storage::{
+        file_storage::FileStorage, store::Storage, BytewiseKeyCmp, KeyCmp, StorageParams,
+    };
+    use crate::tree::TreeParams;
+
+    fn all_cmp() -> HashMap<u32, Rc<RefCell<dyn KeyCmp>>> {
+        let mut result: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        result.insert(1, Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        result
+    }
+
+    fn file(dir: &tempfile::TempDir, name: &str) -> Result<Rc<RefCell<FileStorage>>> {
+        let path = dir.path().join(name);
+        Ok(Rc::new(RefCell::new(FileStorage::new(
+            path.to_str().unwrap(),
+        )?)))
+    }
+
+    fn insert_range(storage: &mut Storage, from: u32, to: u32) -> Result<()> {
+        let tr = storage.begin_transaction()?;
+        for i in from..to {
+            storage.insert(tr, 1, &i.to_be_bytes(), &i.to_le_bytes())?;
+        }
+        storage.commit_transaction(tr)
+    }
+
+    fn count(storage: &mut Storage) -> Result<usize> {
+        storage.count_range(1, &[0; 4], &[0xff; 4])
+    }
+
+    #[test]
+    fn full_and_incremental() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(4);
+        let mut storage = Storage::new(file(&dir, "source")?, &params, all_cmp())?;
+
+        let empty = file(&dir, "empty")?;
+        assert_eq!(storage.backup_to(&mut *empty.borrow_mut())?, 0);
+
+        insert_range(&mut storage, 0, 100)?;
+        let full = file(&dir, "full")?;
+        let since = storage.backup_to(&mut *full.borrow_mut())?;
+        assert!(matches!(
+            storage.backup_to(&mut *full.borrow_mut()),
+            Err(crate::Error::InvalidParams(_))
+        ));
+
+        insert_range(&mut storage, 100, 150)?;
+        let mut first = Vec::new();
+        let since = storage.backup_incremental(since, &mut first)?;
+
+        // records of the open transaction are not in the backup.
+        insert_range(&mut storage, 150, 170)?;
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[0xff; 4], &[])?;
+        let mut second = Vec::new();
+        let last = storage.backup_incremental(since, &mut second)?;
+        storage.commit_transaction(tr)?;
+        assert!(storage.backup_incremental(last, &mut Vec::new())? > last);
+
+        let restored = file(&dir, "restored")?;
+        let mut target = Storage::restore(
+            restored.clone(),
+            &*full.borrow(),
+            &mut [&mut &first[..], &mut &second[..]],
+            all_cmp(),
+        )?;
+        assert_eq!(count(&mut target)?, 170);
+        assert_eq!(target.find(1, &[0xff; 4])?, None);
+        assert_eq!(
+            target.find(1, &7u32.to_be_bytes())?,
+            Some(7u32.to_le_bytes().to_vec())
+        );
+
+        let mut storage = Storage::open(full.clone(), all_cmp())?;
+        assert_eq!(count(&mut storage)?, 100);
+
+        // the chain starts at an empty storage too.
+        let mut from_empty = Vec::new();
+        let storage = Storage::open(restored, all_cmp())?;
+        storage.backup_incremental(0, &mut from_empty)?;
+        let mut target = Storage::restore(
+            file(&dir, "from_empty")?,
+            &*empty.borrow(),
+            &mut [&mut &from_empty[..]],
+            all_cmp(),
+        )?;
+        assert_eq!(count(&mut target)?, 170);
+
+        // increments must follow each other.
+        let err = Storage::restore(
+            file(&dir, "bad_order")?,
+            &*full.borrow(),
+            &mut [&mut &second[..]],
+            all_cmp(),
+        );
+        assert!(matches!(err, Err(crate::Error::InvalidParams(_))));
+        let err = Storage::restore(
+            file(&dir, "bad_magic")?,
+            &*full.borrow(),
+            &mut [&mut &second[4..]],
+            all_cmp(),
+        );
+        assert!(matches!(err, Err(crate::Error::Corrupted(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+pub enum BatchOp {
+    Put {
+        tree_id: u32,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        tree_id: u32,
+        key: Vec<u8>,
+    },
+    DeleteRange {
+        tree_id: u32,
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+}
+
+impl BatchOp {
+    pub fn tree_id(&self) -> u32 {
+        match self {
+            BatchOp::Put { tree_id, .. } => *tree_id,
+            BatchOp::Delete { tree_id, .. } => *tree_id,
+            BatchOp::DeleteRange { tree_id, .. } => *tree_id,
+        }
+    }
+
+    /// key used to order operations inside one tree.
+    pub fn sort_key(&self) -> &[u8] {
+        match self {
+            BatchOp::Put { key, .. } => key,
+            BatchOp::Delete { key, .. } => key,
+            BatchOp::DeleteRange { from, .. } => from,
+        }
+    }
+}
+
+/// list of puts and deletes applied by `Storage::write` with one commit.
+pub struct WriteBatch {
+    pub(super) ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch { ops: Vec::new() }
+    }
+
+    pub fn put(&mut self, tree_id: u32, key: &[u8], value: &[u8]) -> &mut Self {
+        self.ops.push(BatchOp::Put {
+            tree_id,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    pub fn delete(&mut self, tree_id: u32, key: &[u8]) -> &mut Self {
+        self.ops.push(BatchOp::Delete {
+            tree_id,
+            key: key.to_vec(),
+        });
+        self
+    }
+
+    /// removes all keys in [from, to].
+    pub fn delete_range(&mut self, tree_id: u32, from: &[u8], to: &[u8]) -> &mut Self {
+        self.ops.push(BatchOp::DeleteRange {
+            tree_id,
+            from: from.to_vec(),
+            to: to.to_vec(),
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -73,6 +73,15 @@ impl Buffer {
         Err(Error::IsFull)
     }
 
+    pub fn write_slice(&mut self, v: &[u8]) -> Result<()> {
+        if self.pos + v.len() <= self.data.len() {
+            self.data[self.pos..self.pos + v.len()].copy_from_slice(v);
+            self.pos += v.len();
+            return Ok(());
+        }
+        Err(Error::IsFull)
+    }
+
     create_write_method!(write_u16, u16);
     create_write_method!(write_u32, u32);
     create_write_method!(write_u64, u64);
@@ -19,11 +19,12 @@ impl BufFileStorage {
             .read(true)
             .append(true)
             .create(true)
-            .open(filename);
+            .open(filename)
+            .map_err(crate::Error::IO)?;
 
         Ok(BufFileStorage {
             buffer: RefCell::new(Buffer::new(buffsize)),
-            file: RefCell::new(f.unwrap()),
+            file: RefCell::new(f),
         })
     }
 
@@ -32,11 +33,15 @@ impl BufFileStorage {
             .read(true)
             .append(true)
             .create(false)
-            .open(filename);
+            .open(filename)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => crate::Error::NotFound(filename.to_owned()),
+                _ => crate::Error::IO(e),
+            })?;
 
         Ok(BufFileStorage {
             buffer: RefCell::new(Buffer::new(buffsize)),
-            file: RefCell::new(f.unwrap()),
+            file: RefCell::new(f),
         })
     }
 
@@ -200,6 +205,24 @@ impl FlatStorage for BufFileStorage {
         let v = self.read::<u64, SIZE>(std::io::SeekFrom::Start(seek as u64))?;
         return Ok(v);
     }
+
+    fn write_bytes(&self, v: &[u8]) -> Result<()> {
+        if self.buffer.borrow_mut().write_slice(v).is_ok() {
+            return Ok(());
+        }
+        self.flush()?;
+        if self.buffer.borrow_mut().write_slice(v).is_ok() {
+            return Ok(());
+        }
+        self.write_slice(v)
+    }
+
+    fn read_bytes(&self, seek: usize, buf: &mut [u8]) -> Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(std::io::SeekFrom::Start(seek as u64))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -302,6 +325,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn bytes() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let pathbuff = tempdir.path().join("bytes");
+        let storage = BufFileStorage::new(pathbuff.to_str().unwrap(), 16)?;
+        storage.write_u8(1)?;
+        storage.write_bytes(&[2; 10])?;
+        storage.write_bytes(&[3; 40])?;
+        storage.write_bytes(&[4; 8])?;
+        storage.flush()?;
+        assert_eq!(storage.size(), 59);
+
+        let mut buf = [0u8; 59];
+        storage.read_bytes(0, &mut buf)?;
+        let mut expected = vec![1u8];
+        expected.extend_from_slice(&[2; 10]);
+        expected.extend_from_slice(&[3; 40]);
+        expected.extend_from_slice(&[4; 8]);
+        assert_eq!(buf.to_vec(), expected);
+        assert!(storage.read_bytes(50, &mut buf).is_err());
+        Ok(())
+    }
+
     #[test]
     fn db() -> Result<()> {
         let tempdir = tempfile::tempdir().unwrap();
@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+use super::{flat_storage::FlatStorage, store::Storage, KeyCmp};
+use crate::Result;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BulkLoadParams {
+    /// part of the node capacity filled by the loader.
+    pub fill_factor: f32,
+    /// records sorted in memory at once, if the input is not sorted.
+    pub run_size: usize,
+}
+
+impl BulkLoadParams {
+    pub fn with_fill_factor(mut self, v: f32) -> Self {
+        self.fill_factor = v;
+        self
+    }
+
+    pub fn with_run_size(mut self, v: usize) -> Self {
+        self.run_size = v;
+        self
+    }
+}
+
+impl Default for BulkLoadParams {
+    fn default() -> Self {
+        BulkLoadParams {
+            fill_factor: 0.9,
+            run_size: 100_000,
+        }
+    }
+}
+
+type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// sorts records by spilling sorted runs to temporary files.
+pub(super) struct ExternalSorter {
+    run_size: usize,
+    buffer: Vec<KeyValue>,
+    runs: Vec<File>,
+}
+
+impl ExternalSorter {
+    pub fn new(run_size: usize) -> Self {
+        ExternalSorter {
+            run_size: std::cmp::max(run_size, 1),
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: Vec<u8>, value: Vec<u8>, cmp: &dyn KeyCmp) -> Result<()> {
+        self.buffer.push((key, value));
+        if self.buffer.len() >= self.run_size {
+            self.spill(cmp)?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self, cmp: &dyn KeyCmp) -> Result<()> {
+        self.buffer.sort_by(|a, b| cmp.compare(&a.0, &b.0));
+
+        let mut file = tempfile::tempfile().map_err(crate::Error::IO)?;
+        {
+            let mut writer = BufWriter::new(&mut file);
+            for (key, value) in self.buffer.drain(..) {
+                write_slice(&mut writer, &key)?;
+                write_slice(&mut writer, &value)?;
+            }
+            writer.flush().map_err(crate::Error::IO)?;
+        }
+        file.seek(SeekFrom::Start(0)).map_err(crate::Error::IO)?;
+        self.runs.push(file);
+        Ok(())
+    }
+
+    pub fn finish(mut self, cmp: &dyn KeyCmp) -> Result<Vec<RunReader>> {
+        if !self.buffer.is_empty() {
+            self.spill(cmp)?;
+        }
+        Ok(self.runs.into_iter().map(RunReader::new).collect())
+    }
+}
+
+fn write_slice(writer: &mut dyn Write, v: &[u8]) -> Result<()> {
+    writer
+        .write_all(&(v.len() as u32).to_le_bytes())
+        .map_err(crate::Error::IO)?;
+    writer.write_all(v).map_err(crate::Error::IO)
+}
+
+pub(super) struct RunReader {
+    reader: BufReader<File>,
+}
+
+impl RunReader {
+    fn new(f: File) -> Self {
+        RunReader {
+            reader: BufReader::new(f),
+        }
+    }
+
+    fn read_slice(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(crate::Error::IO(e)),
+        }
+        let mut result = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader
+            .read_exact(&mut result)
+            .map_err(crate::Error::IO)?;
+        Ok(Some(result))
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<KeyValue>> {
+        let key = match self.read_slice()? {
+            Some(k) => k,
+            None => return Ok(None),
+        };
+        match self.read_slice()? {
+            Some(value) => Ok(Some((key, value))),
+            None => Err(crate::Error::Corrupted("broken run file".to_owned())),
+        }
+    }
+}
+
+/// merges records already written to the store (sorted, by offset) with the spilled runs.
+/// records from runs are written to the store. returns offsets of all records in key order.
+pub(super) fn merge_runs(
+    store: &dyn FlatStorage,
+    cmp: &dyn KeyCmp,
+    written: Vec<u32>,
+    mut runs: Vec<RunReader>,
+) -> Result<Vec<u32>> {
+    let mut result = Vec::with_capacity(written.len());
+
+    let mut written = written.into_iter();
+    let mut written_head = match written.next() {
+        Some(offset) => Some((Storage::read_key(store, offset as usize)?, offset)),
+        None => None,
+    };
+
+    let mut run_heads = Vec::with_capacity(runs.len());
+    for r in runs.iter_mut() {
+        run_heads.push(r.next_record()?);
+    }
+
+    loop {
+        let mut min_run: Option<usize> = None;
+        for (i, head) in run_heads.iter().enumerate() {
+            if let Some((key, _)) = head {
+                let is_less = match min_run {
+                    None => true,
+                    Some(m) => cmp.compare(key, &run_heads[m].as_ref().unwrap().0).is_lt(),
+                };
+                if is_less {
+                    min_run = Some(i);
+                }
+            }
+        }
+
+        let from_written = match (&written_head, min_run) {
+            (None, None) => break,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some((key, _)), Some(m)) => {
+                cmp.compare(key, &run_heads[m].as_ref().unwrap().0).is_le()
+            }
+        };
+
+        if from_written {
+            result.push(written_head.as_ref().unwrap().1);
+            written_head = match written.next() {
+                Some(offset) => Some((Storage::read_key(store, offset as usize)?, offset)),
+                None => None,
+            };
+        } else {
+            let m = min_run.unwrap();
+            let (key, value) = run_heads[m].take().unwrap();
+            result.push(Storage::write_kv(store, &key, &value)?);
+            run_heads[m] = runs[m].next_record()?;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BytesCmp {}
+
+    impl KeyCmp for BytesCmp {
+        fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
+            key1.cmp(key2)
+        }
+    }
+
+    #[test]
+    fn sorted_runs() -> Result<()> {
+        let cmp = BytesCmp {};
+        let mut sorter = ExternalSorter::new(7);
+        for i in 0..100u32 {
+            let key = ((i * 37) % 100).to_be_bytes().to_vec();
+            sorter.push(key.clone(), key, &cmp)?;
+        }
+        let mut runs = sorter.finish(&cmp)?;
+        assert_eq!(runs.len(), 15);
+
+        let mut total = 0;
+        for r in runs.iter_mut() {
+            let mut prev: Option<Vec<u8>> = None;
+            while let Some((key, value)) = r.next_record()? {
+                assert_eq!(key, value);
+                if let Some(p) = prev {
+                    assert!(p < key);
+                }
+                prev = Some(key);
+                total += 1;
+            }
+        }
+        assert_eq!(total, 100);
+        Ok(())
+    }
+}
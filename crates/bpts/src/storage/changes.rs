@@ -0,0 +1,141 @@
+use crate::Result;
+
+use super::U32SZ;
+
+/// tree of the change feed. records are keyed by the commit sequence number
+/// and the number of the change in the commit.
+pub const CHANGES_TREE_ID: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeOp {
+    Put,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub tree_id: u32,
+    pub key: Vec<u8>,
+    pub op: ChangeOp,
+    /// the inserted value. None for deletes.
+    pub value: Option<Vec<u8>>,
+}
+
+/// changes of one committed transaction, in the order they were made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub seq: u64,
+    pub changes: Vec<Change>,
+}
+
+/// called by `Storage::commit_transaction` after the transaction is saved.
+pub trait CommitObserver {
+    fn on_commit(&mut self, commit: &Commit);
+}
+
+pub(super) fn log_key(seq: u64, n: u32) -> Vec<u8> {
+    let mut result = seq.to_be_bytes().to_vec();
+    result.extend_from_slice(&n.to_be_bytes());
+    result
+}
+
+pub(super) fn log_seq(key: &[u8]) -> Result<u64> {
+    match key.get(..8) {
+        Some(bytes) => Ok(u64::from_be_bytes(bytes.try_into().unwrap())),
+        None => Err(crate::Error::Corrupted("bad change key".to_owned())),
+    }
+}
+
+/// number of the record that keeps the sequence number of the last commit,
+/// after all changes of the feed are trimmed. it is not a change.
+pub(super) const SEQ_MARKER: u32 = u32::MAX;
+
+pub(super) fn is_seq_marker(key: &[u8]) -> bool {
+    key.get(8..12) == Some(&SEQ_MARKER.to_be_bytes()[..])
+}
+
+/// [tree_id][op][key len][key][value]
+pub(super) fn encode_change(change: &Change) -> Vec<u8> {
+    let mut result = Vec::new();
+    result.extend_from_slice(&change.tree_id.to_le_bytes());
+    result.push(match change.op {
+        ChangeOp::Put => 0,
+        ChangeOp::Delete => 1,
+    });
+    result.extend_from_slice(&(change.key.len() as u32).to_le_bytes());
+    result.extend_from_slice(&change.key);
+    if let Some(v) = change.value.as_ref() {
+        result.extend_from_slice(v);
+    }
+    result
+}
+
+pub(super) fn decode_change(data: &[u8]) -> Result<Change> {
+    let bad = || crate::Error::Corrupted("bad change record".to_owned());
+    if data.len() < 2 * U32SZ + 1 {
+        return Err(bad());
+    }
+    let tree_id = u32::from_le_bytes(data[..U32SZ].try_into().unwrap());
+    let op = match data[U32SZ] {
+        0 => ChangeOp::Put,
+        1 => ChangeOp::Delete,
+        _ => return Err(bad()),
+    };
+    let rest = &data[U32SZ + 1..];
+    let key_len = u32::from_le_bytes(rest[..U32SZ].try_into().unwrap()) as usize;
+    let rest = &rest[U32SZ..];
+    if rest.len() < key_len {
+        return Err(bad());
+    }
+    let (key, value) = rest.split_at(key_len);
+    Ok(Change {
+        tree_id,
+        key: key.to_vec(),
+        op,
+        value: match op {
+            ChangeOp::Put => Some(value.to_vec()),
+            ChangeOp::Delete => None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_records() -> Result<()> {
+        let changes = [
+            Change {
+                tree_id: 3,
+                key: b"key".to_vec(),
+                op: ChangeOp::Put,
+                value: Some(b"value".to_vec()),
+            },
+            Change {
+                tree_id: 0,
+                key: Vec::new(),
+                op: ChangeOp::Put,
+                value: Some(Vec::new()),
+            },
+            Change {
+                tree_id: 7,
+                key: b"key".to_vec(),
+                op: ChangeOp::Delete,
+                value: None,
+            },
+        ];
+        for c in changes.iter() {
+            assert_eq!(&decode_change(&encode_change(c))?, c);
+        }
+        assert!(decode_change(&[1, 2, 3]).is_err());
+        assert!(decode_change(&[0, 0, 0, 0, 2, 0, 0, 0, 0]).is_err());
+        assert!(decode_change(&[0, 0, 0, 0, 0, 5, 0, 0, 0, 1]).is_err());
+
+        assert!(log_key(1, u32::MAX) < log_key(2, 0));
+        assert_eq!(log_seq(&log_key(42, 7))?, 42);
+        assert!(is_seq_marker(&log_key(42, SEQ_MARKER)));
+        assert!(!is_seq_marker(&log_key(42, 7)));
+        Ok(())
+    }
+}
@@ -3,17 +3,106 @@ use std::{cell::RefCell, rc::Rc};
 use super::{flat_storage::FlatStorage, store::Storage, KeyCmp};
 use crate::tree::node::NodeKeyCmp;
 
+/// the first error of reading keys while comparing. `compare` can not fail,
+/// so the storage takes the error after each tree operation.
+pub(super) type CmpErrorRc = Rc<RefCell<Option<crate::Error>>>;
+
+fn read_key(store: &dyn FlatStorage, error: &CmpErrorRc, offset: u32) -> Vec<u8> {
+    match Storage::read_key(store, offset as usize) {
+        Ok(k) => k,
+        Err(e) => {
+            let mut first = error.borrow_mut();
+            if first.is_none() {
+                *first = Some(e);
+            }
+            Vec::new()
+        }
+    }
+}
+
+/// position of the key relative to [from, to].
+pub(super) fn range_position(
+    cmp: &dyn KeyCmp,
+    key: &[u8],
+    from: &[u8],
+    to: &[u8],
+) -> std::cmp::Ordering {
+    if cmp.compare(key, from).is_lt() {
+        std::cmp::Ordering::Less
+    } else if cmp.compare(key, to).is_gt() {
+        std::cmp::Ordering::Greater
+    } else {
+        std::cmp::Ordering::Equal
+    }
+}
+
+/// the smallest key greater than all keys with the prefix. None, if there is no such key.
+pub(super) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut result = prefix.to_vec();
+    while let Some(last) = result.pop() {
+        if last != u8::MAX {
+            result.push(last + 1);
+            return Some(result);
+        }
+    }
+    None
+}
+
+/// position of the key relative to the keys with the prefix.
+/// `upper` is the upper bound of the prefix for bytewise comparators.
+pub(super) fn prefix_position(
+    cmp: &dyn KeyCmp,
+    key: &[u8],
+    prefix: &[u8],
+    upper: Option<&[u8]>,
+) -> std::cmp::Ordering {
+    if cmp.is_bytewise() {
+        if key < prefix {
+            return std::cmp::Ordering::Less;
+        }
+        return match upper {
+            Some(u) if key >= u => std::cmp::Ordering::Greater,
+            _ => std::cmp::Ordering::Equal,
+        };
+    }
+    if cmp.has_prefix(key, prefix) {
+        std::cmp::Ordering::Equal
+    } else if cmp.compare(key, prefix).is_lt() {
+        std::cmp::Ordering::Less
+    } else {
+        std::cmp::Ordering::Greater
+    }
+}
+
 pub struct StorageNodeCmp {
     pub(super) store: Rc<RefCell<dyn FlatStorage>>,
     pub(super) cmp: Rc<RefCell<dyn KeyCmp>>,
+    pub(super) error: CmpErrorRc,
+    /// equal keys are ordered by offsets, i.e. by the insertion sequence.
+    pub(super) by_offset: bool,
+}
+
+/// records are appended to the file, so the offset order is the insertion order.
+fn offset_tie_break(
+    by_offset: bool,
+    key1: u32,
+    key2: u32,
+    res: std::cmp::Ordering,
+) -> std::cmp::Ordering {
+    if by_offset {
+        res.then(key1.cmp(&key2))
+    } else {
+        res
+    }
 }
 
 impl NodeKeyCmp for StorageNodeCmp {
     fn compare(&self, key1: u32, key2: u32) -> std::cmp::Ordering {
         let store = self.store.borrow();
-        let k1 = Storage::read_key(&*store, key1 as usize).unwrap();
-        let k2 = Storage::read_key(&*store, key2 as usize).unwrap();
-        return self.cmp.borrow().compare(&k1, &k2);
+        let k1 = read_key(&*store, &self.error, key1);
+        let k2 = read_key(&*store, &self.error, key2);
+        let res = self.cmp.borrow().compare(&k1, &k2);
+        offset_tie_break(self.by_offset, key1, key2, res)
     }
 }
 
@@ -21,12 +110,15 @@ pub(super) struct StorageKeyCmpRef {
     pub(super) user_key: Vec<u8>,
     pub(super) store: Rc<RefCell<dyn FlatStorage>>,
     pub(super) cmp: Rc<RefCell<dyn KeyCmp>>,
+    pub(super) error: CmpErrorRc,
+    /// the user key is equal to all its duplicates.
+    pub(super) by_offset: bool,
 }
 
 impl StorageKeyCmpRef {
     fn cmp_with_left(&self, key2: u32) -> std::cmp::Ordering {
         let store = self.store.borrow();
-        let kv2 = Storage::read_key(&*store, key2 as usize).unwrap();
+        let kv2 = read_key(&*store, &self.error, key2);
         return self
             .cmp
             .borrow()
@@ -35,7 +127,7 @@ impl StorageKeyCmpRef {
 
     fn cmp_with_right(&self, key1: u32) -> std::cmp::Ordering {
         let store = self.store.borrow();
-        let kv1 = Storage::read_key(&*store, key1 as usize).unwrap();
+        let kv1 = read_key(&*store, &self.error, key1);
         return self
             .cmp
             .borrow()
@@ -51,9 +143,10 @@ impl NodeKeyCmp for StorageKeyCmpRef {
 
         if key1 != std::u32::MAX && key2 != std::u32::MAX {
             let store = self.store.borrow();
-            let k1 = Storage::read_key(&*store, key1 as usize).unwrap();
-            let k2 = Storage::read_key(&*store, key2 as usize).unwrap();
-            return self.cmp.borrow().compare(&k1, &k2);
+            let k1 = read_key(&*store, &self.error, key1);
+            let k2 = read_key(&*store, &self.error, key2);
+            let res = self.cmp.borrow().compare(&k1, &k2);
+            return offset_tie_break(self.by_offset, key1, key2, res);
         }
 
         if key1 == std::u32::MAX && key2 != std::u32::MAX {
@@ -63,3 +156,49 @@ impl NodeKeyCmp for StorageKeyCmpRef {
         return self.cmp_with_right(key1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::BytewiseKeyCmp;
+    use std::cmp::Ordering;
+
+    struct CaseInsensitiveCmp {}
+
+    impl KeyCmp for CaseInsensitiveCmp {
+        fn compare(&self, key1: &[u8], key2: &[u8]) -> Ordering {
+            key1.to_ascii_lowercase().cmp(&key2.to_ascii_lowercase())
+        }
+
+        fn has_prefix(&self, key: &[u8], prefix: &[u8]) -> bool {
+            key.len() >= prefix.len() && key[..prefix.len()].eq_ignore_ascii_case(prefix)
+        }
+    }
+
+    #[test]
+    fn upper_bound() {
+        assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_upper_bound(&[1, 0xff]), Some(vec![2]));
+        assert_eq!(prefix_upper_bound(&[0xff, 0xff]), None);
+        assert_eq!(prefix_upper_bound(&[]), None);
+    }
+
+    #[test]
+    fn positions() {
+        let cmp = BytewiseKeyCmp {};
+        let upper = prefix_upper_bound(b"ab");
+        let pos = |k: &[u8]| prefix_position(&cmp, k, b"ab", upper.as_deref());
+        assert_eq!(pos(b"aa"), Ordering::Less);
+        assert_eq!(pos(b"a"), Ordering::Less);
+        assert_eq!(pos(b"ab"), Ordering::Equal);
+        assert_eq!(pos(b"ab\xff"), Ordering::Equal);
+        assert_eq!(pos(b"ac"), Ordering::Greater);
+
+        let cmp = CaseInsensitiveCmp {};
+        let pos = |k: &[u8]| prefix_position(&cmp, k, b"Ab", None);
+        assert_eq!(pos(b"AA"), Ordering::Less);
+        assert_eq!(pos(b"aB"), Ordering::Equal);
+        assert_eq!(pos(b"abC"), Ordering::Equal);
+        assert_eq!(pos(b"AC"), Ordering::Greater);
+    }
+}
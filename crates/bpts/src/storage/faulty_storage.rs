@@ -0,0 +1,591 @@
+use super::flat_storage::FlatStorage;
+use super::store::StorageHeader;
+use super::StorageParams;
+use crate::types::Id;
+use crate::utils::any_as_u8_slice;
+use crate::Result;
+
+use std::cell::{Cell, RefCell};
+
+/// calls of the storage, which faults are bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Write,
+    Read,
+    Flush,
+    HeaderWrite,
+}
+
+/// when a fault fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum At {
+    /// the n-th call of the access kind, from 0. fires once.
+    Call(usize),
+    /// every write or read, which covers the offset.
+    Offset(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// the call fails, nothing is written.
+    Error,
+    /// the first n bytes are written, then the call fails.
+    Short(usize),
+    /// the first n bytes are written, the rest are zeros. the call succeeds.
+    Torn(usize),
+    /// the bit of the value is flipped. the call succeeds.
+    FlipBit(usize),
+    /// unflushed writes are lost, the call and all later calls fail.
+    Crash,
+}
+
+struct Rule {
+    access: Access,
+    at: At,
+    fault: Fault,
+}
+
+/// wraps a storage and injects scripted faults.
+/// writes and headers are held in memory until `flush`, like in a page cache, and are
+/// lost on a crash. headers are then appended to the data of the inner storage, so it must
+/// read the header from the end, as `FileStorage` and `BufFileStorage` do.
+pub struct FaultyStorage<S: FlatStorage> {
+    inner: S,
+    rules: RefCell<Vec<Rule>>,
+    calls: RefCell<[usize; 4]>,
+    unflushed: RefCell<Vec<u8>>,
+    crashed: Cell<bool>,
+}
+
+fn injected(what: &str) -> crate::Error {
+    crate::Error::IO(std::io::Error::other(format!("injected fault: {}", what)))
+}
+
+fn index(access: Access) -> usize {
+    match access {
+        Access::Write => 0,
+        Access::Read => 1,
+        Access::Flush => 2,
+        Access::HeaderWrite => 3,
+    }
+}
+
+impl<S: FlatStorage> FaultyStorage<S> {
+    pub fn new(inner: S) -> Self {
+        FaultyStorage {
+            inner,
+            rules: RefCell::new(Vec::new()),
+            calls: RefCell::new([0; 4]),
+            unflushed: RefCell::new(Vec::new()),
+            crashed: Cell::new(false),
+        }
+    }
+
+    pub fn with_fault(self, access: Access, at: At, fault: Fault) -> Self {
+        self.add_fault(access, at, fault);
+        self
+    }
+
+    pub fn add_fault(&self, access: Access, at: At, fault: Fault) {
+        self.rules.borrow_mut().push(Rule { access, at, fault });
+    }
+
+    pub fn clear_faults(&self) {
+        self.rules.borrow_mut().clear();
+    }
+
+    /// count of calls of the access kind.
+    pub fn calls(&self, access: Access) -> usize {
+        self.calls.borrow()[index(access)]
+    }
+
+    /// bytes written after the last flush.
+    pub fn unflushed(&self) -> usize {
+        self.unflushed.borrow().len()
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.crashed.get()
+    }
+
+    /// loses unflushed writes. all later calls fail.
+    pub fn crash(&self) {
+        self.unflushed.borrow_mut().clear();
+        self.crashed.set(true);
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// the inner storage with flushed writes only.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// counts the call and returns the fault, which fires on it.
+    fn fault(&self, access: Access, offset: usize, len: usize) -> Result<Option<Fault>> {
+        if self.crashed.get() {
+            return Err(injected("crashed"));
+        }
+        let call = {
+            let mut calls = self.calls.borrow_mut();
+            calls[index(access)] += 1;
+            calls[index(access)] - 1
+        };
+        let fault = self.rules.borrow().iter().find_map(|r| {
+            let fires = r.access == access
+                && match r.at {
+                    At::Call(n) => n == call,
+                    At::Offset(o) => offset <= o && o < offset + len,
+                };
+            if fires {
+                Some(r.fault)
+            } else {
+                None
+            }
+        });
+        if fault == Some(Fault::Crash) {
+            self.crash();
+            return Err(injected("crash"));
+        }
+        Ok(fault)
+    }
+
+    fn write_access(&self, access: Access, bytes: &[u8]) -> Result<()> {
+        let offset = self.inner.size() + self.unflushed.borrow().len();
+        let mut bytes = bytes.to_vec();
+        let fault = self.fault(access, offset, bytes.len())?;
+        let mut res = Ok(());
+        match fault {
+            None => {}
+            Some(Fault::Error) => return Err(injected("write")),
+            Some(Fault::Short(n)) => {
+                bytes.truncate(n);
+                res = Err(injected("short write"));
+            }
+            Some(Fault::Torn(n)) => {
+                for b in bytes.iter_mut().skip(n) {
+                    *b = 0;
+                }
+            }
+            Some(Fault::FlipBit(bit)) => flip(&mut bytes, bit),
+            Some(Fault::Crash) => unreachable!(),
+        }
+        self.unflushed.borrow_mut().extend_from_slice(&bytes);
+        res
+    }
+
+    fn read_array<const N: usize>(&self, seek: usize) -> Result<[u8; N]> {
+        let mut result = [0u8; N];
+        self.read_into(seek, &mut result)?;
+        Ok(result)
+    }
+
+    fn read_into(&self, seek: usize, result: &mut [u8]) -> Result<()> {
+        let fault = self.fault(Access::Read, seek, result.len())?;
+        let flushed = self.inner.size();
+        let from_inner = flushed.saturating_sub(seek).min(result.len());
+        if from_inner > 0 {
+            self.inner.read_bytes(seek, &mut result[..from_inner])?;
+        }
+        let unflushed = self.unflushed.borrow();
+        let start = (seek + from_inner).saturating_sub(flushed);
+        match unflushed.get(start..start.saturating_add(result.len() - from_inner)) {
+            Some(bytes) => result[from_inner..].copy_from_slice(bytes),
+            None => return Err(injected("read after the end")),
+        }
+        match fault {
+            None => {}
+            Some(Fault::Error) | Some(Fault::Short(_)) => return Err(injected("read")),
+            Some(Fault::Torn(n)) => {
+                for b in result.iter_mut().skip(n) {
+                    *b = 0;
+                }
+            }
+            Some(Fault::FlipBit(bit)) => flip(result, bit),
+            Some(Fault::Crash) => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+const HEADER_SIZE: usize = std::mem::size_of::<StorageHeader>();
+
+fn flip(bytes: &mut [u8], bit: usize) {
+    if !bytes.is_empty() {
+        let bit = bit % (bytes.len() * 8);
+        bytes[bit / 8] ^= 1 << (bit % 8);
+    }
+}
+
+impl<S: FlatStorage> FlatStorage for FaultyStorage<S> {
+    fn close(&self) -> Result<()> {
+        self.flush()?;
+        self.inner.close()
+    }
+
+    fn flush(&self) -> Result<()> {
+        if self.fault(Access::Flush, 0, 0)?.is_some() {
+            return Err(injected("flush"));
+        }
+        let bytes = std::mem::take(&mut *self.unflushed.borrow_mut());
+        // the headers are a part of the data.
+        self.inner.write_bytes(&bytes)?;
+        self.inner.flush()
+    }
+
+    fn params_write(&self, h: &StorageParams) -> Result<()> {
+        self.inner.params_write(h)
+    }
+
+    fn params_read(&self) -> Result<StorageParams> {
+        self.inner.params_read()
+    }
+
+    fn header_write(&self, h: &StorageHeader) -> Result<()> {
+        let bytes = unsafe { any_as_u8_slice(h) };
+        self.write_access(Access::HeaderWrite, bytes)
+    }
+
+    fn header_read(&self) -> Result<StorageHeader> {
+        if self.crashed.get() {
+            return Err(injected("crashed"));
+        }
+        let flushed = self.inner.size();
+        let unflushed = self.unflushed.borrow();
+        if unflushed.is_empty() {
+            return self.inner.header_read();
+        }
+        let end = flushed + unflushed.len();
+        let mut bytes = [0u8; HEADER_SIZE];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            let pos = (end + i).saturating_sub(HEADER_SIZE);
+            *b = if pos < flushed {
+                self.inner.read_u8(pos)?
+            } else {
+                unflushed[pos - flushed]
+            };
+        }
+        Ok(unsafe { (bytes.as_ptr() as *const StorageHeader).read_unaligned() })
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size() + self.unflushed.borrow().len()
+    }
+
+    fn write_id(&self, v: Id) -> Result<()> {
+        self.write_u32(v.0)
+    }
+
+    fn write_bool(&self, v: bool) -> Result<()> {
+        self.write_u8(v as u8)
+    }
+
+    fn write_u8(&self, v: u8) -> Result<()> {
+        self.write_access(Access::Write, &[v])
+    }
+
+    fn write_u16(&self, v: u16) -> Result<()> {
+        self.write_access(Access::Write, &v.to_ne_bytes())
+    }
+
+    fn write_u32(&self, v: u32) -> Result<()> {
+        self.write_access(Access::Write, &v.to_ne_bytes())
+    }
+
+    fn write_u64(&self, v: u64) -> Result<()> {
+        self.write_access(Access::Write, &v.to_ne_bytes())
+    }
+
+    fn read_id(&self, seek: usize) -> Result<Id> {
+        Ok(Id(self.read_u32(seek)?))
+    }
+
+    fn read_bool(&self, seek: usize) -> Result<bool> {
+        Ok(self.read_u8(seek)? == 1)
+    }
+
+    fn read_u8(&self, seek: usize) -> Result<u8> {
+        Ok(self.read_array::<1>(seek)?[0])
+    }
+
+    fn read_u16(&self, seek: usize) -> Result<u16> {
+        Ok(u16::from_ne_bytes(self.read_array(seek)?))
+    }
+
+    fn read_u32(&self, seek: usize) -> Result<u32> {
+        Ok(u32::from_ne_bytes(self.read_array(seek)?))
+    }
+
+    fn read_u64(&self, seek: usize) -> Result<u64> {
+        Ok(u64::from_ne_bytes(self.read_array(seek)?))
+    }
+
+    fn write_bytes(&self, v: &[u8]) -> Result<()> {
+        self.write_access(Access::Write, v)
+    }
+
+    fn read_bytes(&self, seek: usize, buf: &mut [u8]) -> Result<()> {
+        self.read_into(seek, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        file_storage::FileStorage, memory_storage::MemoryStorage, store::Storage, BytewiseKeyCmp,
+    };
+    use std::{collections::HashMap, path::Path, rc::Rc};
+
+    type Records = Vec<(Vec<u8>, Vec<u8>)>;
+
+    fn open(store: Rc<RefCell<dyn FlatStorage>>) -> Result<Storage> {
+        Ok(Storage::open(store, HashMap::new())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {}))))
+    }
+
+    fn records(path: &Path) -> Result<Records> {
+        let store = Rc::new(RefCell::new(FileStorage::open(path.to_str().unwrap())?));
+        let mut storage = open(store)?;
+        assert!(storage.check()?.is_empty());
+        let mut result = Vec::new();
+        storage.for_each(1, |k, v| {
+            result.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    /// the commit of the test: removes 5 records, replaces 15 and adds 10.
+    fn commit(storage: &mut Storage) -> Result<()> {
+        let tr = storage.begin_transaction()?;
+        for i in 0..30u32 {
+            storage.delete(tr, 1, &i.to_be_bytes())?;
+            if i >= 5 {
+                storage.insert(tr, 1, &i.to_be_bytes(), b"new")?;
+            }
+        }
+        storage.commit_transaction(tr)
+    }
+
+    /// runs the commit on a copy of `base` with the fault, crashes and returns records
+    /// after the reopen.
+    fn run(base: &Path, access: Access, at: At, fault: Fault) -> Result<(bool, Records)> {
+        let path = base.with_extension("work");
+        std::fs::copy(base, &path)?;
+        let inner = FileStorage::open(path.to_str().unwrap())?;
+        let faulty = Rc::new(RefCell::new(
+            FaultyStorage::new(inner).with_fault(access, at, fault),
+        ));
+        let committed = open(faulty.clone())
+            .and_then(|mut s| commit(&mut s))
+            .is_ok();
+        faulty.borrow().crash();
+        Ok((committed, records(&path)?))
+    }
+
+    #[test]
+    fn commit_is_atomic() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let base = tempdir.path().join("base");
+        {
+            let store = Rc::new(RefCell::new(FileStorage::new(base.to_str().unwrap())?));
+            let mut storage = Storage::new(store, &StorageParams::default(), HashMap::new())?
+                .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+            let tr = storage.begin_transaction()?;
+            for i in 0..20u32 {
+                storage.insert(tr, 1, &i.to_be_bytes(), b"old")?;
+            }
+            storage.commit_transaction(tr)?;
+        }
+        let old = records(&base)?;
+        assert_eq!(old.len(), 20);
+
+        // counts calls of the commit without faults.
+        let path = base.with_extension("counted");
+        std::fs::copy(&base, &path)?;
+        let faulty = Rc::new(RefCell::new(FaultyStorage::new(FileStorage::open(
+            path.to_str().unwrap(),
+        )?)));
+        commit(&mut open(faulty.clone())?)?;
+        let writes = faulty.borrow().calls(Access::Write);
+        let flushes = faulty.borrow().calls(Access::Flush);
+        let headers = faulty.borrow().calls(Access::HeaderWrite);
+        assert!(writes > 100 && flushes > 0 && headers > 0);
+        let new = records(&path)?;
+        assert_eq!(new.len(), 25);
+
+        let mut cases = Vec::new();
+        for n in (0..writes).step_by(writes / 40) {
+            cases.push((Access::Write, n, Fault::Error));
+            cases.push((Access::Write, n, Fault::Short(1)));
+            cases.push((Access::Write, n, Fault::Crash));
+        }
+        for n in 0..flushes {
+            cases.push((Access::Flush, n, Fault::Error));
+            cases.push((Access::Flush, n, Fault::Crash));
+        }
+        for n in 0..headers {
+            cases.push((Access::HeaderWrite, n, Fault::Error));
+            cases.push((Access::HeaderWrite, n, Fault::Short(5)));
+            cases.push((Access::HeaderWrite, n, Fault::Torn(2)));
+        }
+        for (access, n, fault) in cases {
+            let (committed, records) = run(&base, access, At::Call(n), fault)?;
+            // a torn header is not seen by the commit, the previous header is used.
+            let ok = match (committed, fault) {
+                (true, Fault::Torn(_)) => records == new || records == old,
+                (true, _) => records == new,
+                (false, _) => records == old,
+            };
+            assert!(
+                ok,
+                "{:?} {} {:?}: committed {}",
+                access, n, fault, committed
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn crash_loses_unflushed() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("storage");
+        let faulty = Rc::new(RefCell::new(FaultyStorage::new(FileStorage::new(
+            path.to_str().unwrap(),
+        )?)));
+        let mut storage = Storage::new(faulty.clone(), &StorageParams::default(), HashMap::new())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"a", b"1")?;
+        storage.commit_transaction(tr)?;
+        assert_eq!(faulty.borrow().unflushed(), 0);
+
+        // the kv record of "b" is flushed, but no header points to it.
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"b", b"2")?;
+        let size = faulty.borrow().size();
+
+        // unflushed writes are read back before the crash.
+        faulty.borrow().write_u32(7)?;
+        faulty.borrow().write_u8(8)?;
+        assert_eq!(faulty.borrow().unflushed(), 5);
+        assert_eq!(faulty.borrow().size(), size + 5);
+        assert_eq!(faulty.borrow().read_u32(size)?, 7);
+        assert_eq!(faulty.borrow().read_u8(size + 4)?, 8);
+        let mut bytes = [0u8; 7];
+        faulty.borrow().read_bytes(size - 2, &mut bytes)?;
+        assert_eq!(bytes[2..], [7, 0, 0, 0, 8]);
+        assert_eq!(
+            faulty.borrow().read_u16(size - 2)?.to_ne_bytes(),
+            bytes[..2]
+        );
+        assert!(faulty.borrow().read_bytes(size, &mut bytes).is_err());
+        assert_eq!(storage.find(1, b"a")?, Some(b"1".to_vec()));
+
+        faulty.borrow().crash();
+        assert!(faulty.borrow().is_crashed());
+        assert!(storage.commit_transaction(tr).is_err());
+        assert!(matches!(
+            faulty.borrow().read_u8(0),
+            Err(crate::Error::IO(_))
+        ));
+        assert_eq!(std::fs::metadata(&path)?.len() as usize, size);
+        assert_eq!(records(&path)?, [(b"a".to_vec(), b"1".to_vec())]);
+        Ok(())
+    }
+
+    #[test]
+    fn read_faults() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("storage");
+        {
+            let store = Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?));
+            let mut storage = Storage::new(store, &StorageParams::default(), HashMap::new())?
+                .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1, b"key", b"value")?;
+            storage.commit_transaction(tr)?;
+        }
+        let inner = FileStorage::open(path.to_str().unwrap())?;
+        let list = inner.header_read()?.offset as usize;
+
+        // a flipped bit in the transaction list is found by the magic.
+        let faulty = Rc::new(RefCell::new(FaultyStorage::new(inner).with_fault(
+            Access::Read,
+            At::Offset(list),
+            Fault::FlipBit(3),
+        )));
+        let mut storage = open(faulty.clone())?;
+        assert!(matches!(
+            storage.find(1, b"key"),
+            Err(crate::Error::Corrupted(_))
+        ));
+
+        faulty.borrow().clear_faults();
+        let mut storage = open(faulty.clone())?;
+        assert_eq!(storage.find(1, b"key")?, Some(b"value".to_vec()));
+
+        let mut storage = open(faulty.clone())?;
+        let reads = faulty.borrow().calls(Access::Read);
+        faulty
+            .borrow()
+            .add_fault(Access::Read, At::Call(reads), Fault::Error);
+        assert!(matches!(storage.find(1, b"key"), Err(crate::Error::IO(_))));
+        assert_eq!(storage.find(1, b"key")?, Some(b"value".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_commit_keeps_state() -> Result<()> {
+        let faulty = Rc::new(RefCell::new(FaultyStorage::new(MemoryStorage::new())));
+        let mut storage = Storage::new(faulty.clone(), &StorageParams::default(), HashMap::new())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        let fail_next_header = || {
+            let calls = faulty.borrow().calls(Access::HeaderWrite);
+            faulty
+                .borrow()
+                .add_fault(Access::HeaderWrite, At::Call(calls), Fault::Error);
+        };
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"a", b"1")?;
+        storage.commit_transaction(tr)?;
+
+        // the transaction stays open and is committed again.
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"b", b"2")?;
+        storage.delete(tr, 1, b"a")?;
+        fail_next_header();
+        assert!(matches!(
+            storage.commit_transaction(tr),
+            Err(crate::Error::IO(_))
+        ));
+        assert_eq!(storage.find(1, b"a")?, Some(b"1".to_vec()));
+        assert_eq!(storage.find(1, b"b")?, None);
+        storage.commit_transaction(tr)?;
+        assert_eq!(storage.find(1, b"a")?, None);
+        assert_eq!(storage.find(1, b"b")?, Some(b"2".to_vec()));
+
+        fail_next_header();
+        assert!(storage.remove(1, b"b").is_err());
+        assert_eq!(storage.find(1, b"b")?, Some(b"2".to_vec()));
+        storage.remove(1, b"b")?;
+        assert_eq!(storage.find(1, b"b")?, None);
+
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 2, b"c", b"3")?;
+        fail_next_header();
+        assert!(storage.commit_transaction(tr).is_err());
+        storage.rollback_transaction(tr)?;
+        assert_eq!(storage.tree_ids()?, [1]);
+        assert!(storage.check()?.is_empty());
+
+        let mut storage = open(faulty)?;
+        assert_eq!(storage.find(1, b"a")?, None);
+        assert_eq!(storage.find(1, b"b")?, None);
+        assert_eq!(storage.tree_ids()?, [1]);
+        Ok(())
+    }
+}
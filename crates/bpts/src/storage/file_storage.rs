@@ -16,10 +16,11 @@ impl FileStorage {
             .read(true)
             .append(true)
             .create(true)
-            .open(filename);
+            .open(filename)
+            .map_err(crate::Error::IO)?;
 
         Ok(FileStorage {
-            file: RefCell::new(f.unwrap()),
+            file: RefCell::new(f),
         })
     }
 
@@ -28,10 +29,14 @@ impl FileStorage {
             .read(true)
             .append(true)
             .create(false)
-            .open(filename);
+            .open(filename)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => crate::Error::NotFound(filename.to_owned()),
+                _ => crate::Error::IO(e),
+            })?;
 
         Ok(FileStorage {
-            file: RefCell::new(f.unwrap()),
+            file: RefCell::new(f),
         })
     }
 
@@ -167,6 +172,17 @@ impl FlatStorage for FileStorage {
         let v = self.read::<u64, SIZE>(std::io::SeekFrom::Start(seek as u64))?;
         return Ok(v);
     }
+
+    fn write_bytes(&self, v: &[u8]) -> Result<()> {
+        self.write_slice(v)
+    }
+
+    fn read_bytes(&self, seek: usize, buf: &mut [u8]) -> Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(std::io::SeekFrom::Start(seek as u64))?;
+        file.read_exact(buf)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -358,4 +374,23 @@ mod tests {
         println!("size: {}kb", fstorage.borrow().size() as f32 / 1024f32);
         Ok(())
     }
+
+    #[test]
+    fn open_errors() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let pathbuff = tempdir.path().join("flat_file_storage_missing");
+        let filename = pathbuff.to_str().unwrap();
+        assert!(matches!(
+            FileStorage::open(filename),
+            Err(crate::Error::NotFound(_))
+        ));
+
+        // an empty file has no header.
+        std::fs::write(filename, []).unwrap();
+        let fstorage = Rc::new(RefCell::new(FileStorage::open(filename)?));
+        let err = Storage::open(fstorage, HashMap::new()).err().unwrap();
+        assert!(matches!(err, crate::Error::IO(_)));
+        assert!(std::error::Error::source(&err).is_some());
+        Ok(())
+    }
 }
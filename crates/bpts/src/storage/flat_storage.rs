@@ -25,4 +25,20 @@ pub trait FlatStorage {
     fn read_u16(&self, seek: usize) -> Result<u16>;
     fn read_u32(&self, seek: usize) -> Result<u32>;
     fn read_u64(&self, seek: usize) -> Result<u64>;
+
+    /// appends the bytes.
+    fn write_bytes(&self, v: &[u8]) -> Result<()> {
+        for b in v.iter() {
+            self.write_u8(*b)?;
+        }
+        Ok(())
+    }
+
+    /// fills `buf` with the bytes from `seek`.
+    fn read_bytes(&self, seek: usize, buf: &mut [u8]) -> Result<()> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = self.read_u8(seek + i)?;
+        }
+        Ok(())
+    }
 }
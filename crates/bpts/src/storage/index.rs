@@ -0,0 +1,120 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use super::{BytewiseKeyCmp, KeyCmp, U32SZ};
+
+/// extracts the index key from the value of a primary record. None - the record is not indexed.
+pub type IndexKeyFn = Rc<dyn Fn(&[u8]) -> Option<Vec<u8>>>;
+
+/// secondary index of a tree. entries are stored in the own tree and updated
+/// in the same transaction as the primary records.
+#[derive(Clone)]
+pub struct IndexDef {
+    /// tree of the index entries.
+    pub tree_id: u32,
+    /// a key may be indexed by one primary record only.
+    pub unique: bool,
+    pub key: IndexKeyFn,
+    /// comparator of index keys.
+    pub cmp: Rc<RefCell<dyn KeyCmp>>,
+}
+
+impl IndexDef {
+    pub fn new<F>(tree_id: u32, key: F) -> Self
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>> + 'static,
+    {
+        IndexDef {
+            tree_id,
+            unique: false,
+            key: Rc::new(key),
+            cmp: Rc::new(RefCell::new(BytewiseKeyCmp {})),
+        }
+    }
+
+    pub fn with_unique(mut self, v: bool) -> Self {
+        self.unique = v;
+        self
+    }
+
+    pub fn with_cmp(mut self, cmp: Rc<RefCell<dyn KeyCmp>>) -> Self {
+        self.cmp = cmp;
+        self
+    }
+}
+
+/// key of an index entry: [index key len][index key][primary key].
+pub(super) fn entry_key(index_key: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(U32SZ + index_key.len() + primary_key.len());
+    result.extend_from_slice(&(index_key.len() as u32).to_le_bytes());
+    result.extend_from_slice(index_key);
+    result.extend_from_slice(primary_key);
+    result
+}
+
+/// index key and primary key of the entry.
+pub(super) fn split_entry(entry: &[u8]) -> (&[u8], &[u8]) {
+    if entry.len() < U32SZ {
+        return (&[], &[]);
+    }
+    let len = u32::from_le_bytes(entry[..U32SZ].try_into().unwrap()) as usize;
+    let body = &entry[U32SZ..];
+    body.split_at(std::cmp::min(len, body.len()))
+}
+
+/// orders entries by the index key, then by the primary key.
+pub(super) struct IndexEntryCmp {
+    pub index: Rc<RefCell<dyn KeyCmp>>,
+    pub primary: Rc<RefCell<dyn KeyCmp>>,
+}
+
+impl KeyCmp for IndexEntryCmp {
+    fn compare(&self, key1: &[u8], key2: &[u8]) -> Ordering {
+        let (index1, primary1) = split_entry(key1);
+        let (index2, primary2) = split_entry(key2);
+        self.index
+            .borrow()
+            .compare(index1, index2)
+            .then_with(|| self.primary.borrow().compare(primary1, primary2))
+    }
+}
+
+/// position of the entry relative to the entries of the index key.
+pub(super) fn entry_position(cmp: &dyn KeyCmp, entry: &[u8], index_key: &[u8]) -> Ordering {
+    cmp.compare(split_entry(entry).0, index_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let entry = entry_key(b"idx", b"pk");
+        assert_eq!(split_entry(&entry), (&b"idx"[..], &b"pk"[..]));
+        assert_eq!(split_entry(&entry_key(b"", b"")), (&b""[..], &b""[..]));
+
+        let cmp = IndexEntryCmp {
+            index: Rc::new(RefCell::new(BytewiseKeyCmp {})),
+            primary: Rc::new(RefCell::new(BytewiseKeyCmp {})),
+        };
+        // the index key goes first, whatever its length.
+        let mut entries = vec![
+            entry_key(b"b", b"1"),
+            entry_key(b"ab", b"2"),
+            entry_key(b"a", b"3"),
+            entry_key(b"a", b"1"),
+        ];
+        entries.sort_by(|a, b| cmp.compare(a, b));
+        assert_eq!(
+            entries,
+            vec![
+                entry_key(b"a", b"1"),
+                entry_key(b"a", b"3"),
+                entry_key(b"ab", b"2"),
+                entry_key(b"b", b"1"),
+            ]
+        );
+        assert!(entry_position(&BytewiseKeyCmp {}, &entries[1], b"a").is_eq());
+        assert!(entry_position(&BytewiseKeyCmp {}, &entries[2], b"a").is_gt());
+    }
+}
@@ -0,0 +1,258 @@
+use super::flat_storage::FlatStorage;
+use super::store::StorageHeader;
+use super::StorageParams;
+use crate::types::Id;
+use crate::utils::any_as_u8_slice;
+use crate::Result;
+
+use std::cell::RefCell;
+use std::path::Path;
+
+const PARAMS_SIZE: usize = std::mem::size_of::<StorageParams>();
+const HEADER_SIZE: usize = std::mem::size_of::<StorageHeader>();
+
+/// keeps the data in memory with the layout of `FileStorage`, so snapshots saved by
+/// `save_to_file` are opened by `FileStorage` and files of `FileStorage` are loaded
+/// by `load_from_file`.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: RefCell<Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage {
+            data: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        MemoryStorage {
+            data: RefCell::new(data),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+
+    /// writes the data to `<path>.tmp` and renames it to `path`.
+    pub fn save_to_file(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, &*self.data.borrow())?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => crate::Error::NotFound(path.display().to_string()),
+            _ => crate::Error::IO(e),
+        })?;
+        Ok(Self::from_bytes(data))
+    }
+
+    fn write_slice(&self, value: &[u8]) -> Result<()> {
+        self.data.borrow_mut().extend_from_slice(value);
+        Ok(())
+    }
+
+    fn read<const SIZE: usize>(&self, seek: usize) -> Result<[u8; SIZE]> {
+        let data = self.data.borrow();
+        match data.get(seek..seek.saturating_add(SIZE)) {
+            Some(bytes) => Ok(bytes.try_into().unwrap()),
+            None => Err(crate::Error::Corrupted(format!(
+                "read of {} bytes at {} after the end {}",
+                SIZE,
+                seek,
+                data.len()
+            ))),
+        }
+    }
+}
+
+impl FlatStorage for MemoryStorage {
+    fn close(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn params_write(&self, h: &StorageParams) -> Result<()> {
+        let ptr = unsafe { any_as_u8_slice(h) };
+        self.write_slice(ptr)
+    }
+
+    fn params_read(&self) -> Result<StorageParams> {
+        let bytes = self.read::<PARAMS_SIZE>(0)?;
+        Ok(unsafe { (bytes.as_ptr() as *const StorageParams).read_unaligned() })
+    }
+
+    fn header_write(&self, h: &StorageHeader) -> Result<()> {
+        let ptr = unsafe { any_as_u8_slice(h) };
+        self.write_slice(ptr)
+    }
+
+    fn header_read(&self) -> Result<StorageHeader> {
+        let end = self.size();
+        if end < HEADER_SIZE {
+            return Err(crate::Error::Corrupted("no header".to_owned()));
+        }
+        let bytes = self.read::<HEADER_SIZE>(end - HEADER_SIZE)?;
+        Ok(unsafe { (bytes.as_ptr() as *const StorageHeader).read_unaligned() })
+    }
+
+    fn size(&self) -> usize {
+        self.data.borrow().len()
+    }
+
+    fn write_id(&self, v: Id) -> Result<()> {
+        self.write_u32(v.0)
+    }
+
+    fn write_bool(&self, v: bool) -> Result<()> {
+        self.write_u8(v as u8)
+    }
+
+    fn write_u8(&self, v: u8) -> Result<()> {
+        self.data.borrow_mut().push(v);
+        Ok(())
+    }
+
+    fn write_u16(&self, v: u16) -> Result<()> {
+        self.write_slice(&v.to_ne_bytes())
+    }
+
+    fn write_u32(&self, v: u32) -> Result<()> {
+        self.write_slice(&v.to_ne_bytes())
+    }
+
+    fn write_u64(&self, v: u64) -> Result<()> {
+        self.write_slice(&v.to_ne_bytes())
+    }
+
+    fn read_id(&self, seek: usize) -> Result<Id> {
+        Ok(Id(self.read_u32(seek)?))
+    }
+
+    fn read_bool(&self, seek: usize) -> Result<bool> {
+        Ok(self.read_u8(seek)? == 1)
+    }
+
+    fn read_u8(&self, seek: usize) -> Result<u8> {
+        Ok(self.read::<1>(seek)?[0])
+    }
+
+    fn read_u16(&self, seek: usize) -> Result<u16> {
+        Ok(u16::from_ne_bytes(self.read(seek)?))
+    }
+
+    fn read_u32(&self, seek: usize) -> Result<u32> {
+        Ok(u32::from_ne_bytes(self.read(seek)?))
+    }
+
+    fn read_u64(&self, seek: usize) -> Result<u64> {
+        Ok(u64::from_ne_bytes(self.read(seek)?))
+    }
+
+    fn write_bytes(&self, v: &[u8]) -> Result<()> {
+        self.write_slice(v)
+    }
+
+    fn read_bytes(&self, seek: usize, buf: &mut [u8]) -> Result<()> {
+        let data = self.data.borrow();
+        match data.get(seek..seek.saturating_add(buf.len())) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(crate::Error::Corrupted(format!(
+                "read of {} bytes at {} after the end {}",
+                buf.len(),
+                seek,
+                data.len()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{file_storage::FileStorage, store::Storage, BytewiseKeyCmp};
+    use std::{collections::HashMap, rc::Rc};
+
+    fn open(store: Rc<RefCell<dyn FlatStorage>>) -> Result<Storage> {
+        Ok(Storage::open(store, HashMap::new())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {}))))
+    }
+
+    #[test]
+    fn snapshots() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("snapshot");
+        let mem = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut storage = Storage::new(mem.clone(), &StorageParams::default(), HashMap::new())?
+            .with_default_cmp(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        for i in 0..100u32 {
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1 + i % 2, &i.to_be_bytes(), b"value")?;
+            storage.commit_transaction(tr)?;
+        }
+        mem.borrow().save_to_file(&path)?;
+        assert!(!tempdir.path().join("snapshot.tmp").exists());
+
+        // the snapshot is a file of FileStorage.
+        let file = Rc::new(RefCell::new(FileStorage::open(path.to_str().unwrap())?));
+        let mut from_file = open(file.clone())?;
+        assert_eq!(from_file.tree_ids()?, [1, 2]);
+        assert_eq!(from_file.count_range(2, &[0; 4], &[0xff; 4])?, 50);
+        let tr = from_file.begin_transaction()?;
+        from_file.insert(tr, 3, b"file", b"1")?;
+        from_file.commit_transaction(tr)?;
+
+        let loaded = Rc::new(RefCell::new(MemoryStorage::load_from_file(&path)?));
+        assert_eq!(loaded.borrow().size(), file.borrow().size());
+        let mut storage = open(loaded)?;
+        assert_eq!(storage.find(3, b"file")?, Some(b"1".to_vec()));
+        assert_eq!(
+            storage.find(1, &98u32.to_be_bytes())?,
+            Some(b"value".to_vec())
+        );
+        assert!(storage.check()?.is_empty());
+
+        assert!(matches!(
+            MemoryStorage::load_from_file(&tempdir.path().join("missing")),
+            Err(crate::Error::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_after_the_end() -> Result<()> {
+        let mem = MemoryStorage::new();
+        assert!(matches!(mem.header_read(), Err(crate::Error::Corrupted(_))));
+        mem.write_u32(0x01020304)?;
+        mem.write_bool(true)?;
+        assert_eq!(mem.read_u32(0)?, 0x01020304);
+        assert!(mem.read_bool(4)?);
+        assert!(matches!(mem.read_u32(2), Err(crate::Error::Corrupted(_))));
+        assert!(matches!(
+            mem.read_u64(usize::MAX),
+            Err(crate::Error::Corrupted(_))
+        ));
+        mem.write_bytes(&[7, 8, 9])?;
+        let mut buf = [0u8; 4];
+        mem.read_bytes(4, &mut buf)?;
+        assert_eq!(buf, [1, 7, 8, 9]);
+        assert!(matches!(
+            mem.read_bytes(5, &mut buf),
+            Err(crate::Error::Corrupted(_))
+        ));
+        assert_eq!(MemoryStorage::from_bytes(mem.to_bytes()).size(), 8);
+        Ok(())
+    }
+}
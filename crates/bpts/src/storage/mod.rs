@@ -1,37 +1,143 @@
+mod aggregate;
+mod backup;
+pub mod batch;
 pub mod buffer;
 pub mod buffile_storage;
+pub mod bulk_load;
+pub mod changes;
 pub(self) mod cmp;
+pub mod faulty_storage;
 pub mod file_storage;
 pub mod flat_storage;
+pub mod index;
+pub mod memory_storage;
 pub mod node_storage;
+pub mod replication;
+pub mod stats;
 pub mod store;
 
 use std::{cell::RefCell, rc::Rc};
 
-use crate::tree::{node::NodeKeyCmp, TreeParams};
+use crate::tree::{aggregate::NodeAggregate, node::NodeKeyCmp, TreeParams};
 
-pub(super) const MAGIC_HEADER: u32 = 0x99669966;
-pub(super) const MAGIC_TRANSACTION: u32 = 0x66996699;
-pub(super) const MAGIC_TRANSACTION_LIST: u32 = 0xDDDBDDDB;
+pub(crate) const MAGIC_HEADER: u32 = 0x99669966;
+pub(crate) const MAGIC_TRANSACTION: u32 = 0x66996699;
+pub(crate) const MAGIC_TRANSACTION_LIST: u32 = 0xDDDBDDDB;
+pub(super) const MAGIC_BACKUP_INCREMENT: u32 = 0xBAC0BAC0;
+pub(crate) const MAGIC_PARAMS: u32 = 0xB9A7B9A7;
+/// version of the file format. version 1 has no magic and version in the params block
+/// and no counts and aggregates in internal nodes.
+pub const FORMAT_VERSION: u32 = 2;
 pub(super) const U8SZ: usize = std::mem::size_of::<u8>();
 pub(super) const U32SZ: usize = std::mem::size_of::<u32>();
 
 pub trait KeyCmp {
     fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering;
+
+    /// true, if keys are ordered as byte strings. prefix scans then compare bytes directly.
+    fn is_bytewise(&self) -> bool {
+        false
+    }
+
+    /// keys with a prefix must follow each other and must not be less than the prefix.
+    fn has_prefix(&self, key: &[u8], prefix: &[u8]) -> bool {
+        key.starts_with(prefix)
+    }
+
+    /// name of the comparator in dumps. comparators are found by names on import.
+    fn name(&self) -> &str {
+        ""
+    }
+}
+
+/// orders keys as byte strings.
+pub struct BytewiseKeyCmp {}
+
+impl KeyCmp for BytewiseKeyCmp {
+    fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
+        key1.cmp(key2)
+    }
+
+    fn is_bytewise(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "bytewise"
+    }
 }
 
 pub type KeyCmpRc = Rc<RefCell<dyn NodeKeyCmp>>;
 
+/// summary of values in a tree (min, max, sum...). summaries are stored in internal nodes,
+/// so the aggregate must be set before the first write to the tree.
+/// an empty summary stands for no records.
+pub trait Aggregate {
+    fn summarize(&self, key: &[u8], value: &[u8]) -> Vec<u8>;
+    fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8>;
+}
+
+pub type AggregateRc = Rc<RefCell<dyn NodeAggregate>>;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct StorageParams {
     pub tree_params: TreeParams,
+    pub(crate) magic: u32,
+    pub(crate) format_version: u32,
+    /// limit of the file size in bytes. 0 - unlimited.
+    pub max_file_size: u64,
 }
 
 impl StorageParams {
     pub fn default() -> Self {
         Self {
             tree_params: TreeParams::default(),
+            magic: MAGIC_PARAMS,
+            format_version: FORMAT_VERSION,
+            max_file_size: 0,
         }
     }
+
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// parses the params block from the start of a file. in version 1 the tree params are
+    /// followed by the first header, the file size is unlimited.
+    pub(crate) fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        let legacy_size = std::mem::size_of::<TreeParams>();
+        if data.len() >= legacy_size + U32SZ
+            && u32::from_ne_bytes(data[legacy_size..legacy_size + U32SZ].try_into().unwrap())
+                == MAGIC_HEADER
+        {
+            let tree_params = unsafe { (data.as_ptr() as *const TreeParams).read_unaligned() };
+            return Ok(Self {
+                tree_params,
+                magic: 0,
+                format_version: 1,
+                max_file_size: 0,
+            });
+        }
+        if data.len() < std::mem::size_of::<StorageParams>() {
+            return Err(crate::Error::Corrupted(format!(
+                "params block of {} bytes",
+                data.len()
+            )));
+        }
+        // the block is written as the memory of the struct.
+        let params = unsafe { (data.as_ptr() as *const StorageParams).read_unaligned() };
+        if params.magic != MAGIC_PARAMS {
+            return Err(crate::Error::Corrupted(format!(
+                "bad params magic {:#x}",
+                params.magic
+            )));
+        }
+        Ok(params)
+    }
+
+    pub fn with_max_file_size(mut self, v: u64) -> Self {
+        self.max_file_size = v;
+        self
+    }
 }
@@ -6,6 +6,7 @@ use std::{
 
 use crate::{
     tree::{
+        aggregate::NodeAggregate,
         node::{self, Node, NodeKeyCmp, RcNode},
         nodestorage::NodeStorage,
         record::Record,
@@ -15,13 +16,14 @@ use crate::{
     verbose, Result,
 };
 
-use super::{flat_storage::FlatStorage, KeyCmpRc, MAGIC_TRANSACTION, U32SZ, U8SZ};
+use super::{flat_storage::FlatStorage, AggregateRc, KeyCmpRc, MAGIC_TRANSACTION, U32SZ, U8SZ};
 
 pub(super) type StorageNodeStorageRc = Rc<RefCell<StorageNodeStorage>>;
 
 pub struct StorageNodeStorage {
     pub(super) offset: u32,
     pub(super) cmp: Option<KeyCmpRc>,
+    pub(super) aggregate: Option<AggregateRc>,
     pub(super) nodes: RefCell<HashMap<u32, RcNode>>,
     pub(super) nodes_to_offset: RefCell<HashMap<u32, usize>>,
     pub tree_params: TreeParams,
@@ -38,6 +40,7 @@ impl StorageNodeStorage {
         Rc::new(RefCell::new(StorageNodeStorage {
             offset: offset as u32,
             cmp: Some(cmp),
+            aggregate: None,
             nodes: RefCell::new(HashMap::new()),
             nodes_to_offset: RefCell::new(HashMap::new()),
             tree_params: params,
@@ -59,6 +62,7 @@ impl StorageNodeStorage {
         Rc::new(RefCell::new(StorageNodeStorage {
             offset: 0u32,
             cmp: cmp,
+            aggregate: self.aggregate.clone(),
             nodes: RefCell::new(nodes),
             nodes_to_offset: RefCell::new(offsets),
             tree_params: p,
@@ -70,6 +74,11 @@ impl StorageNodeStorage {
         self
     }
 
+    pub(super) fn set_aggregate(&mut self, a: Option<AggregateRc>) -> &mut Self {
+        self.aggregate = a;
+        self
+    }
+
     pub(super) fn set_offset(&mut self, v: u32) -> &mut Self {
         self.offset = v;
         self
@@ -103,12 +112,72 @@ impl StorageNodeStorage {
             match *d {
                 Record::Value(v) => flat_store.write_u32(v)?,
                 Record::Ptr(ptr) => flat_store.write_id(ptr)?,
-                Record::Empty => todo!(),
+                Record::Empty => {
+                    return Err(crate::Error::Corrupted(format!(
+                        "empty record in node {:?}",
+                        n.id
+                    )))
+                }
+            }
+        }
+
+        if !n.is_leaf {
+            for c in n.counts.iter().take(n.data_count) {
+                flat_store.write_u32(*c as u32)?;
+            }
+            for a in n.aggregates.iter().take(n.data_count) {
+                flat_store.write_u32(a.len() as u32)?;
+                for v in a.iter() {
+                    flat_store.write_u8(*v)?;
+                }
             }
         }
         Ok(())
     }
 
+    /// size of the node record written by `save_node`.
+    pub(super) fn node_size(n: &Node) -> usize {
+        // id, is_leaf, parent, left, right, keys_count, data_count
+        let mut result = U32SZ + U8SZ + 5 * U32SZ;
+        result += (n.keys_count + n.data_count) * U32SZ;
+        if !n.is_leaf {
+            result += n.data_count * U32SZ;
+            for a in n.aggregates.iter().take(n.data_count) {
+                result += U32SZ + a.len();
+            }
+        }
+        result
+    }
+
+    /// returns the node without caching it.
+    pub(super) fn peek_node(&self, id: Id) -> Result<RcNode> {
+        if let Some(n) = self.nodes.borrow().get(&id.0) {
+            return Ok(n.clone());
+        }
+        match self.get_node_offset(id) {
+            Some(offset) => self.load_node(offset as u32, &*self.flat_store.borrow()),
+            None => Err(crate::Error::NotFound(format!("node Id={}", id.0))),
+        }
+    }
+
+    /// bytes, which `save` will write.
+    pub(super) fn save_size(&self) -> usize {
+        if self.offset != 0 {
+            return 0;
+        }
+        let nodes = self.nodes.borrow();
+        let offsets = self.nodes_to_offset.borrow();
+        let mut result = 3 * U32SZ;
+        for (id, n) in nodes.iter() {
+            result += U32SZ;
+            if !offsets.contains_key(id) {
+                result += Self::node_size(&n.borrow());
+            }
+        }
+        result += offsets.keys().filter(|id| !nodes.contains_key(id)).count() * U32SZ;
+        result
+    }
+
     pub(super) fn save(&mut self, tree_id: u32, flat_store: &dyn FlatStorage) -> Result<u32> {
         if self.offset != 0 {
             return Ok(self.offset);
@@ -133,7 +202,13 @@ impl StorageNodeStorage {
                 all_nodes.push(i.clone());
             }
         }
-        assert!(self.get_root().unwrap().borrow().id == all_nodes[0].borrow().id);
+        let root_id = self.get_root().map(|r| r.borrow().id);
+        if root_id.is_none() || root_id != all_nodes.first().map(|n| n.borrow().id) {
+            return Err(crate::Error::Corrupted(format!(
+                "tree {} has no single root",
+                tree_id
+            )));
+        }
         for node in all_nodes {
             let node_ref = node.borrow();
             if let Some(exists_offset) = self.get_node_offset(node_ref.id) {
@@ -189,6 +264,13 @@ impl StorageNodeStorage {
         offset += U32SZ;
         let data_count = flat_store.read_u32(offset)?;
         offset += U32SZ;
+        let capacity = self.tree_params.get_keys_count();
+        if keys_count as usize > capacity || data_count as usize > capacity {
+            return Err(crate::Error::Corrupted(format!(
+                "node {} at {}: keys_count={} data_count={} capacity={}",
+                id.0, node_offset, keys_count, data_count, capacity
+            )));
+        }
 
         let mut keys = Vec::with_capacity(keys_count as usize);
         keys.resize(self.tree_params.get_keys_count(), 0u32);
@@ -223,6 +305,30 @@ impl StorageNodeStorage {
             right,
         );
 
+        if !is_leaf {
+            let mut node_ref = node.borrow_mut();
+            for i in 0..data_count as usize {
+                node_ref.counts[i] = flat_store.read_u32(offset)? as usize;
+                offset += U32SZ;
+            }
+            for i in 0..data_count as usize {
+                let len = flat_store.read_u32(offset)? as usize;
+                offset += U32SZ;
+                if len > flat_store.size().saturating_sub(offset) {
+                    return Err(crate::Error::Corrupted(format!(
+                        "node {} at {}: aggregate of {} bytes",
+                        id.0, node_offset, len
+                    )));
+                }
+                let mut aggregate = Vec::with_capacity(len);
+                for _ in 0..len {
+                    aggregate.push(flat_store.read_u8(offset)?);
+                    offset += U8SZ;
+                }
+                node_ref.aggregates[i] = aggregate;
+            }
+        }
+
         return Ok(node);
     }
 
@@ -315,7 +421,7 @@ impl NodeStorage for StorageNodeStorage {
                 self.nodes.borrow_mut().insert(id.0, node.clone());
                 Ok(node)
             } else {
-                Err(crate::Error::Fail(format!("not found Id={}", id.0)))
+                Err(crate::Error::NotFound(format!("node Id={}", id.0)))
             }
         }
     }
@@ -344,6 +450,32 @@ impl NodeStorage for StorageNodeStorage {
         verbose!("mark_as_changed {:?}", id);
         self.nodes_to_offset.borrow_mut().remove(&id.0);
     }
+
+    fn get_aggregate(&self) -> Option<&dyn NodeAggregate> {
+        match self.aggregate {
+            Some(_) => Some(self),
+            None => None,
+        }
+    }
+}
+
+impl NodeAggregate for StorageNodeStorage {
+    fn summarize(&self, key: u32, value: &Record) -> Result<Vec<u8>> {
+        match &self.aggregate {
+            Some(a) => a.borrow().summarize(key, value),
+            None => Err(crate::Error::InvalidParams(
+                "aggregate is not set".to_owned(),
+            )),
+        }
+    }
+
+    fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        match &self.aggregate {
+            Some(a) => a.borrow().combine(left, right),
+            // `get_aggregate` returns the storage only with the aggregate.
+            None => unreachable!("aggregate is not set"),
+        }
+    }
 }
 
 impl NodeKeyCmp for StorageNodeStorage {
@@ -353,7 +485,8 @@ impl NodeKeyCmp for StorageNodeStorage {
                 let r = c.borrow();
                 return r.compare(key1, key2);
             }
-            None => panic!(),
+            // the comparator is set in `new` and is never taken.
+            None => unreachable!("comparator is not set"),
         }
     }
 }
@@ -0,0 +1,824 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, RecvError, Sender, TryRecvError};
+
+use super::backup::{increment_size, INCREMENT_HEAD_SIZE};
+use super::{flat_storage::FlatStorage, store::Storage, KeyCmp, StorageParams};
+use crate::tree::TreeParams;
+use crate::Result;
+
+/*
+follower -> leader: HELLO [applied offset][last transaction list offset][digest of the list]
+                          [has params][params]
+leader -> follower: PARAMS [params], then INCREMENT [backup increment] after commits.
+                    increments over the message limit are continued by PART [bytes] messages.
+ */
+const MSG_HELLO: u8 = 0;
+const MSG_PARAMS: u8 = 1;
+const MSG_INCREMENT: u8 = 2;
+const MSG_PART: u8 = 3;
+
+/// limit of the bytes of an increment in one message.
+const PIECE_SIZE: usize = 1 << 20;
+
+/// delivers messages between the leader and the follower in the order they were sent.
+pub trait Transport {
+    fn send(&mut self, msg: &[u8]) -> Result<()>;
+    /// None, if `wait` is false and there is no message yet.
+    fn recv(&mut self, wait: bool) -> Result<Option<Vec<u8>>>;
+    /// limit of the message size.
+    fn max_message(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// in-process transport. both ends are returned by `channel`.
+pub struct ChannelTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+pub fn channel() -> (ChannelTransport, ChannelTransport) {
+    let (tx1, rx1) = std::sync::mpsc::channel();
+    let (tx2, rx2) = std::sync::mpsc::channel();
+    (
+        ChannelTransport { tx: tx1, rx: rx2 },
+        ChannelTransport { tx: tx2, rx: rx1 },
+    )
+}
+
+fn closed() -> crate::Error {
+    crate::Error::Fail("replication channel is closed".to_owned())
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        self.tx.send(msg.to_vec()).map_err(|_| closed())
+    }
+
+    fn recv(&mut self, wait: bool) -> Result<Option<Vec<u8>>> {
+        if wait {
+            return self.rx.recv().map(Some).map_err(|_: RecvError| closed());
+        }
+        match self.rx.try_recv() {
+            Ok(msg) => Ok(Some(msg)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(closed()),
+        }
+    }
+}
+
+/// default limit of the message size of `TcpTransport`.
+pub const MAX_FRAME_SIZE: usize = 256 << 20;
+
+/// frames are [u32 len][message].
+pub struct TcpTransport {
+    stream: TcpStream,
+    max_frame: usize,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> Self {
+        TcpTransport {
+            stream,
+            max_frame: MAX_FRAME_SIZE,
+        }
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Ok(Self::new(TcpStream::connect(addr)?))
+    }
+
+    /// messages larger than `v` are not sent or received. larger increments are sent in parts.
+    pub fn with_max_frame(mut self, v: usize) -> Self {
+        self.max_frame = std::cmp::min(v, u32::MAX as usize);
+        self
+    }
+}
+
+fn frame_error(len: usize, max_frame: usize) -> String {
+    format!(
+        "replication message of {} bytes is over the limit {}",
+        len, max_frame
+    )
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        if msg.len() > self.max_frame {
+            return Err(crate::Error::InvalidParams(frame_error(
+                msg.len(),
+                self.max_frame,
+            )));
+        }
+        self.stream.write_all(&(msg.len() as u32).to_le_bytes())?;
+        self.stream.write_all(msg)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self, wait: bool) -> Result<Option<Vec<u8>>> {
+        if !wait {
+            let mut len = [0u8; 4];
+            self.stream.set_nonblocking(true)?;
+            let peeked = self.stream.peek(&mut len);
+            self.stream.set_nonblocking(false)?;
+            match peeked {
+                Ok(0) => return Err(closed()),
+                Ok(n) if n < len.len() => return Ok(None),
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > self.max_frame {
+            return Err(crate::Error::Corrupted(frame_error(len, self.max_frame)));
+        }
+        let mut msg = vec![0u8; len];
+        self.stream.read_exact(&mut msg)?;
+        Ok(Some(msg))
+    }
+
+    fn max_message(&self) -> usize {
+        self.max_frame
+    }
+}
+
+/// sends the written increment in messages, which fit in the message limit.
+struct IncrementWriter<'a> {
+    transport: &'a mut dyn Transport,
+    piece: usize,
+    msg: Vec<u8>,
+    error: Option<crate::Error>,
+}
+
+impl<'a> IncrementWriter<'a> {
+    fn new(transport: &'a mut dyn Transport) -> Self {
+        let piece = std::cmp::min(transport.max_message().saturating_sub(1), PIECE_SIZE);
+        IncrementWriter {
+            transport,
+            piece: std::cmp::max(piece, 1),
+            msg: vec![MSG_INCREMENT],
+            error: None,
+        }
+    }
+
+    fn send(&mut self) -> std::io::Result<()> {
+        if let Err(e) = self.transport.send(&self.msg) {
+            self.error = Some(e);
+            return Err(std::io::Error::other("replication send failed"));
+        }
+        self.msg.truncate(0);
+        self.msg.push(MSG_PART);
+        Ok(())
+    }
+
+    /// sends the rest of the increment.
+    fn finish(mut self) -> Result<()> {
+        if self.msg.len() > 1 {
+            self.send()
+                .map_err(|e| self.error.take().unwrap_or(e.into()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for IncrementWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::cmp::min(buf.len(), self.piece + 1 - self.msg.len());
+        self.msg.extend_from_slice(&buf[..n]);
+        if self.msg.len() == self.piece + 1 {
+            self.send()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn params_to_u64s(params: &StorageParams) -> [u64; 5] {
+    let tp = &params.tree_params;
+    [
+        tp.t as u64,
+        tp.min_size_root as u64,
+        tp.min_size_node as u64,
+        tp.min_size_leaf as u64,
+        params.max_file_size,
+    ]
+}
+
+fn encode_u64s(tag: u8, values: &[u64]) -> Vec<u8> {
+    let mut result = vec![tag];
+    for v in values {
+        result.extend_from_slice(&v.to_le_bytes());
+    }
+    result
+}
+
+fn encode_params(params: &StorageParams) -> Vec<u8> {
+    encode_u64s(MSG_PARAMS, &params_to_u64s(params))
+}
+
+/// `params` are None for a new follower.
+fn encode_hello(
+    applied: usize,
+    list: usize,
+    digest: u64,
+    params: Option<&StorageParams>,
+) -> Vec<u8> {
+    let mut values = vec![applied as u64, list as u64, digest, params.is_some() as u64];
+    values.extend_from_slice(&params.map(params_to_u64s).unwrap_or_default());
+    encode_u64s(MSG_HELLO, &values)
+}
+
+fn decode_u64s<const N: usize>(msg: &[u8], tag: u8) -> Result<[u64; N]> {
+    if msg.len() != 1 + 8 * N || msg[0] != tag {
+        return Err(crate::Error::Corrupted(format!(
+            "unexpected replication message {:?}",
+            msg.first()
+        )));
+    }
+    let mut result = [0u64; N];
+    for (i, v) in result.iter_mut().enumerate() {
+        *v = u64::from_le_bytes(msg[1 + 8 * i..9 + 8 * i].try_into().unwrap());
+    }
+    Ok(result)
+}
+
+fn decode_params(msg: &[u8]) -> Result<StorageParams> {
+    Ok(params_from_u64s(decode_u64s::<5>(msg, MSG_PARAMS)?))
+}
+
+fn params_from_u64s(values: [u64; 5]) -> StorageParams {
+    let [t, min_size_root, min_size_node, min_size_leaf, max_file_size] = values;
+    StorageParams {
+        tree_params: TreeParams {
+            t: t as usize,
+            min_size_root: min_size_root as usize,
+            min_size_node: min_size_node as usize,
+            min_size_leaf: min_size_leaf as usize,
+        },
+        max_file_size,
+        ..StorageParams::default()
+    }
+}
+
+fn same_params(a: &StorageParams, b: &StorageParams) -> bool {
+    params_to_u64s(a) == params_to_u64s(b)
+}
+
+/// the leader side. sends records committed since the last `ship` to one follower.
+pub struct Replicator {
+    transport: Box<dyn Transport>,
+    shipped: usize,
+}
+
+impl Replicator {
+    /// waits for the follower to connect and tell its applied offset.
+    /// the follower must have the params of the leader and end on one of its commits.
+    pub fn accept(mut transport: Box<dyn Transport>, storage: &Storage) -> Result<Self> {
+        let hello = match transport.recv(true)? {
+            Some(msg) => msg,
+            None => return Err(closed()),
+        };
+        let [shipped, list, digest, has_params, params @ ..] = decode_u64s::<9>(&hello, MSG_HELLO)?;
+        let end = storage.committed_end()?;
+        if shipped as usize > end {
+            return Err(crate::Error::InvalidParams(format!(
+                "follower offset {} is after the last commit {}",
+                shipped, end
+            )));
+        }
+        if has_params != 0 && !same_params(&params_from_u64s(params), &storage.params()) {
+            return Err(crate::Error::InvalidParams(
+                "follower params differ from the leader".to_owned(),
+            ));
+        }
+        if storage.commit_digest(list as usize, shipped as usize)? != Some(digest) {
+            return Err(crate::Error::InvalidParams(format!(
+                "follower offset {} is not the end of a commit of the leader",
+                shipped
+            )));
+        }
+        transport.send(&encode_params(&storage.params()))?;
+        Ok(Replicator {
+            transport,
+            shipped: shipped as usize,
+        })
+    }
+
+    /// sends the committed records, which the follower does not have yet.
+    /// returns false, if there were no new commits.
+    pub fn ship(&mut self, storage: &Storage) -> Result<bool> {
+        if storage.committed_end()? == self.shipped {
+            return Ok(false);
+        }
+        let mut out = IncrementWriter::new(&mut *self.transport);
+        let end = match storage.backup_incremental(self.shipped, &mut out) {
+            Ok(end) => end,
+            Err(e) => return Err(out.error.take().unwrap_or(e)),
+        };
+        out.finish()?;
+        self.shipped = end;
+        Ok(true)
+    }
+
+    /// offset of the last shipped commit.
+    pub fn shipped_offset(&self) -> usize {
+        self.shipped
+    }
+}
+
+/// the read-only copy of the leader storage. serves reads from the latest applied commit.
+pub struct Follower {
+    transport: Box<dyn Transport>,
+    storage: Storage,
+    applied: usize,
+}
+
+impl Follower {
+    /// `target` is empty or holds the state of a previous follower of the same leader.
+    pub fn connect(
+        target: Rc<RefCell<dyn FlatStorage>>,
+        mut transport: Box<dyn Transport>,
+        cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
+    ) -> Result<Self> {
+        let existing = if target.borrow().size() == 0 {
+            None
+        } else {
+            Some(Storage::open(target.clone(), cmp.clone())?)
+        };
+        let hello = match existing.as_ref() {
+            Some(storage) => {
+                let applied = storage.committed_end()?;
+                let list = storage.header_offset();
+                let digest = match storage.commit_digest(list, applied)? {
+                    Some(d) => d,
+                    None => return Err(crate::Error::Corrupted("bad transaction list".to_owned())),
+                };
+                encode_hello(applied, list, digest, Some(&storage.params()))
+            }
+            None => encode_hello(0, 0, 0, None),
+        };
+        transport.send(&hello)?;
+
+        let params = match transport.recv(true)? {
+            Some(msg) => decode_params(&msg)?,
+            None => return Err(closed()),
+        };
+        let (storage, applied) = match existing {
+            Some(storage) => {
+                if !same_params(&storage.params(), &params) {
+                    return Err(crate::Error::InvalidParams(
+                        "follower params differ from the leader".to_owned(),
+                    ));
+                }
+                let applied = storage.committed_end()?;
+                (storage, applied)
+            }
+            None => (Storage::new(target, &params, cmp)?, 0),
+        };
+        Ok(Follower {
+            transport,
+            storage,
+            applied,
+        })
+    }
+
+    /// receives the parts of the increment, which starts in `msg`, and applies it.
+    fn apply(&mut self, msg: Vec<u8>) -> Result<()> {
+        let mut increment = match msg.split_first() {
+            Some((&MSG_INCREMENT, bytes)) => bytes.to_vec(),
+            _ => {
+                return Err(crate::Error::Corrupted(format!(
+                    "unexpected replication message {:?}",
+                    msg.first()
+                )))
+            }
+        };
+        // the increment is applied after all parts are received, so a broken
+        // transfer leaves no partial commit.
+        loop {
+            if increment.len() >= INCREMENT_HEAD_SIZE {
+                let size = increment_size(&increment)?;
+                if increment.len() == size {
+                    break;
+                }
+                if increment.len() > size {
+                    return Err(crate::Error::Corrupted(
+                        "replication increment is longer than its head".to_owned(),
+                    ));
+                }
+            }
+            match self.transport.recv(true)? {
+                Some(part) if part.first() == Some(&MSG_PART) => {
+                    increment.extend_from_slice(&part[1..])
+                }
+                part => {
+                    return Err(crate::Error::Corrupted(format!(
+                        "unexpected replication message {:?} in an increment",
+                        part.as_ref().and_then(|p| p.first())
+                    )))
+                }
+            }
+        }
+        self.applied = self.storage.apply_increment(&mut &increment[..])?;
+        Ok(())
+    }
+
+    /// applies the received commits without waiting for new ones. the rest of a started
+    /// increment is waited for. returns the number of applied increments.
+    pub fn poll(&mut self) -> Result<usize> {
+        let mut result = 0;
+        while let Some(msg) = self.transport.recv(false)? {
+            self.apply(msg)?;
+            result += 1;
+        }
+        Ok(result)
+    }
+
+    /// waits for the next commits of the leader and applies them.
+    pub fn wait(&mut self) -> Result<()> {
+        match self.transport.recv(true)? {
+            Some(msg) => self.apply(msg),
+            None => Err(closed()),
+        }
+    }
+
+    /// offset of the last applied commit in the leader file.
+    pub fn applied_offset(&self) -> usize {
+        self.applied
+    }
+
+    pub fn find(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.storage.find(tree_id, key)
+    }
+
+    pub fn get_all(&mut self, tree_id: u32, key: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.storage.get_all(tree_id, key)
+    }
+
+    pub fn count_range(&mut self, tree_id: u32, from: &[u8], to: &[u8]) -> Result<usize> {
+        self.storage.count_range(tree_id, from, to)
+    }
+
+    pub fn first(&mut self, tree_id: u32) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.storage.first(tree_id)
+    }
+
+    pub fn last(&mut self, tree_id: u32) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.storage.last(tree_id)
+    }
+
+    pub fn scan_prefix(&mut self, tree_id: u32, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.storage.scan_prefix(tree_id, prefix)
+    }
+
+    pub fn for_each<F>(&mut self, tree_id: u32, f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        self.storage.for_each(tree_id, f)
+    }
+
+    pub fn tree_ids(&mut self) -> Result<Vec<u32>> {
+        self.storage.tree_ids()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::storage::{file_storage::FileStorage, BytewiseKeyCmp};
+
+    fn all_cmp() -> HashMap<u32, Rc<RefCell<dyn KeyCmp>>> {
+        let mut result: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        result.insert(1, Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        result
+    }
+
+    fn file(path: &std::path::Path) -> Result<Rc<RefCell<FileStorage>>> {
+        Ok(Rc::new(RefCell::new(FileStorage::new(
+            path.to_str().unwrap(),
+        )?)))
+    }
+
+    fn insert_range(storage: &mut Storage, from: u32, to: u32) -> Result<()> {
+        let tr = storage.begin_transaction()?;
+        for i in from..to {
+            storage.insert(tr, 1, &i.to_be_bytes(), &i.to_le_bytes())?;
+        }
+        storage.commit_transaction(tr)
+    }
+
+    fn params() -> StorageParams {
+        let mut result = StorageParams::default();
+        result.tree_params = TreeParams::default_with_t(4);
+        result
+    }
+
+    fn count(follower: &mut Follower) -> Result<usize> {
+        follower.count_range(1, &[0; 4], &[0xff; 4])
+    }
+
+    #[test]
+    fn channel_replication() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("follower");
+        let (leader_end, follower_end) = channel();
+        let follower = std::thread::spawn(move || -> Result<()> {
+            let mut follower = Follower::connect(file(&path)?, Box::new(follower_end), all_cmp())?;
+            follower.wait()?;
+            assert_eq!(count(&mut follower)?, 50);
+
+            // an open transaction of the leader is not shipped.
+            follower.wait()?;
+            assert_eq!(count(&mut follower)?, 60);
+            assert_eq!(follower.find(1, &[0xff; 4])?, None);
+            assert_eq!(
+                follower.find(1, &55u32.to_be_bytes())?,
+                Some(55u32.to_le_bytes().to_vec())
+            );
+
+            follower.wait()?;
+            assert_eq!(follower.poll()?, 0);
+            assert_eq!(follower.find(1, &[0xff; 4])?, Some(Vec::new()));
+            assert_eq!(follower.find(1, &7u32.to_be_bytes())?, None);
+            assert_eq!(follower.scan_prefix(1, &[0, 0, 0])?.len(), 59);
+            assert_eq!(follower.tree_ids()?, [1]);
+            let applied = follower.applied_offset();
+            drop(follower);
+
+            // the follower file opens as a usual storage.
+            let target = FileStorage::open(path.to_str().unwrap())?;
+            let mut copy = Storage::open(Rc::new(RefCell::new(target)), all_cmp())?;
+            assert_eq!(copy.committed_end()?, applied);
+            assert_eq!(copy.count_range(1, &[0; 4], &[0xff; 4])?, 60);
+            assert!(copy.check()?.is_empty());
+            Ok(())
+        });
+
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+        let mut replicator = Replicator::accept(Box::new(leader_end), &leader)?;
+        assert!(!replicator.ship(&leader)?);
+        insert_range(&mut leader, 0, 50)?;
+        assert!(replicator.ship(&leader)?);
+        assert!(!replicator.ship(&leader)?);
+
+        insert_range(&mut leader, 50, 60)?;
+        let tr = leader.begin_transaction()?;
+        leader.insert(tr, 1, &[0xff; 4], &[])?;
+        assert!(replicator.ship(&leader)?);
+
+        leader.commit_transaction(tr)?;
+        leader.remove(1, &7u32.to_be_bytes())?;
+        assert!(replicator.ship(&leader)?);
+        assert_eq!(replicator.shipped_offset(), leader.committed_end()?);
+        follower.join().unwrap()
+    }
+
+    #[test]
+    fn follower_restart() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("follower");
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+
+        let (leader_end, follower_end) = channel();
+        let target = path.clone();
+        let first = std::thread::spawn(move || -> Result<usize> {
+            let mut follower =
+                Follower::connect(file(&target)?, Box::new(follower_end), all_cmp())?;
+            follower.wait()?;
+            Ok(follower.applied_offset())
+        });
+        let mut replicator = Replicator::accept(Box::new(leader_end), &leader)?;
+        insert_range(&mut leader, 0, 20)?;
+        replicator.ship(&leader)?;
+        let applied = first.join().unwrap()?;
+        assert_eq!(applied, replicator.shipped_offset());
+
+        // the restarted follower gets only the missed commits.
+        insert_range(&mut leader, 20, 40)?;
+        let (leader_end, follower_end) = channel();
+        let target = path.clone();
+        let second = std::thread::spawn(move || -> Result<usize> {
+            let target = Rc::new(RefCell::new(FileStorage::open(target.to_str().unwrap())?));
+            let mut follower = Follower::connect(target, Box::new(follower_end), all_cmp())?;
+            assert_eq!(count(&mut follower)?, 20);
+            follower.wait()?;
+            count(&mut follower)
+        });
+        let mut replicator = Replicator::accept(Box::new(leader_end), &leader)?;
+        assert_eq!(replicator.shipped_offset(), applied);
+        replicator.ship(&leader)?;
+        assert_eq!(second.join().unwrap()?, 40);
+
+        // the follower is ahead of the leader.
+        let (leader_end, follower_end) = channel();
+        let other = Storage::new(file(&dir.path().join("other"))?, &params(), all_cmp())?;
+        let third = std::thread::spawn(move || -> Result<()> {
+            let target = Rc::new(RefCell::new(FileStorage::open(path.to_str().unwrap())?));
+            Follower::connect(target, Box::new(follower_end), all_cmp()).map(|_| ())
+        });
+        let err = Replicator::accept(Box::new(leader_end), &other);
+        assert!(matches!(err, Err(crate::Error::InvalidParams(_))));
+        assert!(third.join().unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn leader_checks_hello() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+        insert_range(&mut leader, 0, 10)?;
+        let end = leader.committed_end()?;
+        let list = leader.header_offset();
+        let digest = leader.commit_digest(list, end)?.unwrap();
+        insert_range(&mut leader, 10, 20)?;
+
+        let accept = |hello: Vec<u8>| {
+            let (leader_end, mut follower_end) = channel();
+            follower_end.send(&hello)?;
+            Replicator::accept(Box::new(leader_end), &leader).map(|r| r.shipped_offset())
+        };
+        let rejected = |r: Result<usize>| matches!(r, Err(crate::Error::InvalidParams(_)));
+
+        assert_eq!(accept(encode_hello(0, 0, 0, None))?, 0);
+        assert_eq!(
+            accept(encode_hello(end, list, digest, Some(&params())))?,
+            end
+        );
+
+        // the offset is inside of a commit, the list is of another storage, the params differ.
+        assert!(rejected(accept(encode_hello(end - 4, list, digest, None))));
+        assert!(rejected(accept(encode_hello(end, list + 4, digest, None))));
+        assert!(rejected(accept(encode_hello(end, list, digest + 1, None))));
+        assert!(rejected(accept(encode_hello(end, 0, 0, None))));
+        let mut other = params();
+        other.tree_params = TreeParams::default_with_t(3);
+        assert!(rejected(accept(encode_hello(
+            end,
+            list,
+            digest,
+            Some(&other)
+        ))));
+        assert!(matches!(
+            accept(encode_hello(end, list, digest, None)[1..].to_vec()),
+            Err(crate::Error::Corrupted(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn tcp_replication() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let path = dir.path().join("follower");
+        let follower = std::thread::spawn(move || -> Result<Vec<usize>> {
+            let transport = TcpTransport::connect(addr)?;
+            let mut follower = Follower::connect(file(&path)?, Box::new(transport), all_cmp())?;
+            let mut counts = Vec::new();
+            for _ in 0..3 {
+                follower.wait()?;
+                counts.push(count(&mut follower)?);
+            }
+            assert_eq!(follower.poll()?, 0);
+            Ok(counts)
+        });
+
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+        let (stream, _) = listener.accept()?;
+        let mut replicator = Replicator::accept(Box::new(TcpTransport::new(stream)), &leader)?;
+        for i in 0..3 {
+            insert_range(&mut leader, i * 100, (i + 1) * 100)?;
+            assert!(replicator.ship(&leader)?);
+        }
+        assert_eq!(follower.join().unwrap()?, [100, 200, 300]);
+        Ok(())
+    }
+
+    #[test]
+    fn tcp_increment_parts() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let path = dir.path().join("follower");
+        let follower = std::thread::spawn(move || -> Result<Vec<usize>> {
+            let transport = TcpTransport::connect(addr)?.with_max_frame(1024);
+            let mut follower = Follower::connect(file(&path)?, Box::new(transport), all_cmp())?;
+            let mut counts = Vec::new();
+            for _ in 0..2 {
+                follower.wait()?;
+                counts.push(count(&mut follower)?);
+            }
+            assert_eq!(follower.poll()?, 0);
+            assert!(follower.storage.check()?.is_empty());
+            Ok(counts)
+        });
+
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+        let (stream, _) = listener.accept()?;
+        let transport = TcpTransport::new(stream).with_max_frame(1024);
+        let mut replicator = Replicator::accept(Box::new(transport), &leader)?;
+        // the follower lags behind by many commits, then one commit is over the limit.
+        for i in 0..10 {
+            insert_range(&mut leader, i * 10, (i + 1) * 10)?;
+        }
+        assert!(leader.committed_end()? > 1024);
+        assert!(replicator.ship(&leader)?);
+        let since = leader.committed_end()?;
+        insert_range(&mut leader, 100, 300)?;
+        assert!(leader.committed_end()? - since > 1024);
+        assert!(replicator.ship(&leader)?);
+        assert_eq!(follower.join().unwrap()?, [100, 300]);
+        Ok(())
+    }
+
+    #[test]
+    fn increment_parts() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut leader = Storage::new(file(&dir.path().join("leader"))?, &params(), all_cmp())?;
+        insert_range(&mut leader, 0, 10)?;
+        let (mut leader_end, follower_end) = channel();
+        let mut out = IncrementWriter::new(&mut leader_end);
+        out.piece = 7;
+        leader.backup_incremental(0, &mut out)?;
+        out.finish()?;
+
+        let mut follower = Follower {
+            transport: Box::new(follower_end),
+            storage: Storage::new(file(&dir.path().join("follower"))?, &params(), all_cmp())?,
+            applied: 0,
+        };
+        let mut parts = Vec::new();
+        while let Some(msg) = follower.transport.recv(false)? {
+            assert!(msg.len() <= 8);
+            parts.push(msg);
+        }
+        assert_eq!(parts[0][0], MSG_INCREMENT);
+        assert!(parts[1..].iter().all(|p| p[0] == MSG_PART));
+
+        // a broken transfer is not applied.
+        for p in parts[..parts.len() - 1].iter() {
+            leader_end.send(p)?;
+        }
+        leader_end.send(&parts[0])?;
+        assert!(matches!(follower.wait(), Err(crate::Error::Corrupted(_))));
+        assert_eq!(follower.applied_offset(), 0);
+        for p in parts.iter() {
+            leader_end.send(p)?;
+        }
+        leader_end.send(&[MSG_PART, 0])?;
+        follower.wait()?;
+        assert_eq!(follower.applied_offset(), leader.committed_end()?);
+        assert_eq!(count(&mut follower)?, 10);
+        assert!(follower.storage.check()?.is_empty());
+        assert!(matches!(follower.poll(), Err(crate::Error::Corrupted(_))));
+
+        let mut long = vec![MSG_INCREMENT];
+        for p in parts.iter() {
+            long.extend_from_slice(&p[1..]);
+        }
+        long.push(0);
+        assert!(matches!(
+            follower.apply(long),
+            Err(crate::Error::Corrupted(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn tcp_frame_limit() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut raw = TcpStream::connect(listener.local_addr()?)?;
+        let (stream, _) = listener.accept()?;
+        let mut receiver = TcpTransport::new(stream).with_max_frame(16);
+        let mut sender = TcpTransport::new(raw.try_clone()?).with_max_frame(16);
+
+        assert!(matches!(
+            sender.send(&[0; 17]),
+            Err(crate::Error::InvalidParams(_))
+        ));
+        sender.send(&[1; 16])?;
+        assert_eq!(receiver.recv(true)?, Some(vec![1; 16]));
+
+        // the length of a frame is not trusted.
+        raw.write_all(&u32::MAX.to_le_bytes())?;
+        assert!(matches!(
+            receiver.recv(true),
+            Err(crate::Error::Corrupted(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::{tree::nodestorage::NodeStorage, Result};
+
+use super::{flat_storage::FlatStorage, node_storage::StorageNodeStorage, U32SZ};
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeStats {
+    pub tree_id: u32,
+    pub height: usize,
+    pub nodes: usize,
+    pub leafs: usize,
+    pub keys: usize,
+    /// average fill of the nodes on each level, from the root.
+    pub fill: Vec<f32>,
+    /// nodes loaded in memory.
+    pub cached_nodes: usize,
+    /// nodes written to the file.
+    pub disk_nodes: usize,
+    pub kv_bytes: usize,
+    pub node_bytes: usize,
+    pub transaction_bytes: usize,
+}
+
+impl TreeStats {
+    pub fn live_bytes(&self) -> usize {
+        self.kv_bytes + self.node_bytes + self.transaction_bytes
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FileStats {
+    pub total_bytes: usize,
+    pub kv_bytes: usize,
+    pub node_bytes: usize,
+    /// tree blocks and the transaction list.
+    pub transaction_bytes: usize,
+    pub live_bytes: usize,
+    /// old versions of nodes and transaction lists, removed records and old headers.
+    pub garbage_bytes: usize,
+    pub trees: Vec<TreeStats>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Capacity {
+    /// 0 - unlimited.
+    pub max_file_size: u64,
+    pub used_bytes: usize,
+    /// None for an unlimited file.
+    pub remaining_bytes: Option<usize>,
+    /// bytes, which a compaction would free.
+    pub reclaimable_bytes: usize,
+}
+
+fn kv_size(store: &dyn FlatStorage, offset: usize) -> Result<usize> {
+    let key_len = store.read_u32(offset)? as usize;
+    let data_len = store.read_u32(offset + U32SZ + key_len)? as usize;
+    Ok(U32SZ + key_len + U32SZ + data_len)
+}
+
+pub(super) fn tree_stats(
+    tree_id: u32,
+    storage: &StorageNodeStorage,
+    store: &dyn FlatStorage,
+) -> Result<TreeStats> {
+    let mut result = TreeStats {
+        tree_id,
+        cached_nodes: storage.nodes.borrow().len(),
+        disk_nodes: storage.nodes_to_offset.borrow().len(),
+        ..Default::default()
+    };
+    if storage.offset != 0 {
+        let count = store.read_u32(storage.offset as usize + 2 * U32SZ)? as usize;
+        result.transaction_bytes = 3 * U32SZ + count * U32SZ;
+    }
+
+    let capacity = storage.get_params().get_keys_count() as f32;
+    let mut level = match storage.get_root() {
+        Some(root) => vec![root],
+        None => Vec::new(),
+    };
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        let mut fill = 0f32;
+        for node in level.iter() {
+            let node_ref = node.borrow();
+            result.nodes += 1;
+            fill += node_ref.data_count as f32 / capacity;
+            if storage.get_node_offset(node_ref.id).is_some() {
+                result.node_bytes += StorageNodeStorage::node_size(&node_ref);
+            }
+            if node_ref.is_leaf {
+                result.leafs += 1;
+                result.keys += node_ref.data_count;
+                for d in node_ref.data_iter() {
+                    result.kv_bytes += kv_size(store, d.into_u32() as usize)?;
+                }
+            } else {
+                for d in node_ref.data_iter() {
+                    next.push(storage.peek_node(d.into_id())?);
+                }
+            }
+        }
+        result.fill.push(fill / level.len() as f32);
+        result.height += 1;
+        level = next;
+    }
+    Ok(result)
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tree {}:", self.tree_id)?;
+        writeln!(f, " height: {}", self.height)?;
+        writeln!(f, " nodes: {} leafs: {}", self.nodes, self.leafs)?;
+        writeln!(f, " keys: {}", self.keys)?;
+        for (i, fill) in self.fill.iter().enumerate() {
+            writeln!(f, " fill[{}]: {:.1}%", i, fill * 100f32)?;
+        }
+        writeln!(
+            f,
+            " cached nodes: {} disk nodes: {}",
+            self.cached_nodes, self.disk_nodes
+        )?;
+        writeln!(f, " kv bytes: {}", self.kv_bytes)?;
+        writeln!(f, " node bytes: {}", self.node_bytes)?;
+        write!(f, " transaction bytes: {}", self.transaction_bytes)
+    }
+}
+
+impl fmt::Display for FileStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "file:")?;
+        writeln!(f, " total bytes: {}", self.total_bytes)?;
+        writeln!(f, " kv bytes: {}", self.kv_bytes)?;
+        writeln!(f, " node bytes: {}", self.node_bytes)?;
+        writeln!(f, " transaction bytes: {}", self.transaction_bytes)?;
+        writeln!(f, " live bytes: {}", self.live_bytes)?;
+        write!(f, " garbage bytes: {}", self.garbage_bytes)?;
+        for t in self.trees.iter() {
+            write!(f, "\n{}", t)?;
+        }
+        Ok(())
+    }
+}
@@ -1,23 +1,39 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
-use crate::tree::node::Node;
+use crate::tree::node::{Node, RcNode};
 use crate::tree::nodestorage::NodeStorage;
+use crate::tree::record::Record;
+use crate::tree::verify::Violation;
 use crate::tree::TreeParams;
 use crate::types::Id;
 use crate::Result;
 
-use super::cmp::StorageKeyCmpRef;
+use super::aggregate::StorageNodeAggregate;
+use super::backup::{apply_increment, commit_digest, committed_end, copy_image, write_increment};
+use super::batch::{BatchOp, WriteBatch};
+use super::bulk_load::{merge_runs, BulkLoadParams, ExternalSorter};
+use super::changes::{
+    decode_change, encode_change, is_seq_marker, log_key, log_seq, Change, ChangeOp, Commit,
+    CommitObserver, CHANGES_TREE_ID, SEQ_MARKER,
+};
 use super::cmp::StorageNodeCmp;
+use super::cmp::{
+    prefix_position, prefix_upper_bound, range_position, CmpErrorRc, StorageKeyCmpRef,
+};
 use super::flat_storage::FlatStorage;
+use super::index::{entry_key, entry_position, split_entry, IndexDef, IndexEntryCmp};
 use super::node_storage::{StorageNodeStorage, StorageNodeStorageRc};
+use super::stats::{tree_stats, Capacity, FileStats, TreeStats};
 use super::MAGIC_HEADER;
 use super::MAGIC_TRANSACTION;
 use super::MAGIC_TRANSACTION_LIST;
 use super::U32SZ;
 use super::U8SZ;
-use super::{KeyCmp, StorageParams};
+use super::{Aggregate, AggregateRc, BytewiseKeyCmp, KeyCmp, StorageParams};
+use super::{FORMAT_VERSION, MAGIC_PARAMS};
 
 /*
 params:.... key+data.... [node] tree [links to node]  TRANSLIST [links to tree]
@@ -25,12 +41,14 @@ params:.... key+data.... [node] tree [links to node]  TRANSLIST [links to tree]
 
 pub struct Tr {
     tree_storages: HashMap<u32, StorageNodeStorageRc>,
+    changes: Vec<Change>,
 }
 
 impl Tr {
     fn new() -> Self {
         Tr {
             tree_storages: HashMap::new(),
+            changes: Vec::new(),
         }
     }
 
@@ -60,8 +78,16 @@ pub struct Storage {
     params: StorageParams,
     header: StorageHeader,
     cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
+    default_cmp: Option<Rc<RefCell<dyn KeyCmp>>>,
+    aggregates: HashMap<u32, Rc<RefCell<dyn Aggregate>>>,
+    indexes: HashMap<u32, Vec<IndexDef>>,
+    duplicates: HashSet<u32>,
+    change_feed: bool,
+    observers: Vec<Rc<RefCell<dyn CommitObserver>>>,
+    commit_seq: Option<u64>,
     tree_storages: HashMap<u32, StorageNodeStorageRc>,
     t: HashMap<u64, Rc<RefCell<Tr>>>,
+    cmp_error: CmpErrorRc,
 }
 
 impl Storage {
@@ -70,7 +96,16 @@ impl Storage {
         params: &StorageParams,
         cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
     ) -> Result<Self> {
-        let p = params.clone();
+        let tp = &params.tree_params;
+        if tp.t < 2 || tp.min_size_leaf > tp.t || tp.min_size_node > tp.t {
+            return Err(crate::Error::InvalidParams(format!(
+                "t={} min_size_leaf={} min_size_node={}",
+                tp.t, tp.min_size_leaf, tp.min_size_node
+            )));
+        }
+        let mut p = *params;
+        p.magic = MAGIC_PARAMS;
+        p.format_version = FORMAT_VERSION;
         s.borrow_mut().params_write(&p)?;
 
         let h = StorageHeader {
@@ -85,8 +120,16 @@ impl Storage {
             params: p,
             header: h,
             cmp: cmp,
+            default_cmp: None,
+            aggregates: HashMap::new(),
+            indexes: HashMap::new(),
+            duplicates: HashSet::new(),
+            change_feed: false,
+            observers: Vec::new(),
+            commit_seq: None,
             tree_storages: HashMap::new(),
             t: HashMap::new(),
+            cmp_error: Rc::new(RefCell::new(None)),
         })
     }
 
@@ -94,31 +137,219 @@ impl Storage {
         s: Rc<RefCell<dyn FlatStorage>>,
         cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
     ) -> Result<Self> {
-        let params = s.borrow().params_read()?;
-        let header = s.borrow().header_read()?;
+        let mut header = s.borrow().header_read()?;
+        let params = Self::read_params(&*s.borrow())?;
+        if params.format_version != FORMAT_VERSION {
+            return Err(crate::Error::UnsupportedVersion(params.format_version));
+        }
 
         if header.magic != MAGIC_HEADER {
-            todo!("fsck")
+            // a crash in a commit leaves a part of it after the last header.
+            header = match Self::find_last_header(&*s.borrow())? {
+                Some(h) => h,
+                None => {
+                    return Err(crate::Error::Corrupted(format!(
+                        "bad header magic {:#x}",
+                        header.magic
+                    )))
+                }
+            };
         }
 
         Ok(Storage {
             transaction: 0,
             store: s,
             cmp: cmp,
+            default_cmp: None,
+            aggregates: HashMap::new(),
+            indexes: HashMap::new(),
+            duplicates: HashSet::new(),
+            change_feed: false,
+            observers: Vec::new(),
+            commit_seq: None,
             params: params,
             header: header,
             tree_storages: HashMap::new(),
             t: HashMap::new(),
+            cmp_error: Rc::new(RefCell::new(None)),
         })
     }
 
+    fn read_params(s: &dyn FlatStorage) -> Result<StorageParams> {
+        let size = std::cmp::min(s.size(), std::mem::size_of::<StorageParams>());
+        let mut bytes = Vec::with_capacity(size);
+        for i in 0..size {
+            bytes.push(s.read_u8(i)?);
+        }
+        StorageParams::from_bytes(&bytes)
+    }
+
+    /// the last header in the data, which follows its transaction list.
+    fn find_last_header(s: &dyn FlatStorage) -> Result<Option<StorageHeader>> {
+        const HEADER_SIZE: usize = std::mem::size_of::<StorageHeader>();
+        let first = std::mem::size_of::<StorageParams>();
+        let mut pos = s.size().saturating_sub(HEADER_SIZE);
+        while pos >= first {
+            if s.read_u32(pos)? == MAGIC_HEADER {
+                let mut bytes = [0u8; HEADER_SIZE];
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    *b = s.read_u8(pos + i)?;
+                }
+                let h = unsafe { (bytes.as_ptr() as *const StorageHeader).read_unaligned() };
+                let list = h.offset as usize;
+                let follows = if list == 0 {
+                    pos == first
+                } else {
+                    list + 2 * U32SZ <= pos
+                        && s.read_u32(list)? == MAGIC_TRANSACTION_LIST
+                        && list + (2 + s.read_u32(list + U32SZ)? as usize) * U32SZ == pos
+                };
+                if h.is_closed <= 1 && follows {
+                    return Ok(Some(h));
+                }
+            }
+            pos -= 1;
+        }
+        Ok(None)
+    }
+
+    /// sets the aggregate of the tree. must be called before the first write to the tree.
+    pub fn with_aggregate(mut self, tree_id: u32, a: Rc<RefCell<dyn Aggregate>>) -> Self {
+        self.aggregates.insert(tree_id, a);
+        self
+    }
+
+    /// sets the comparator of the tree.
+    pub fn with_cmp(mut self, tree_id: u32, cmp: Rc<RefCell<dyn KeyCmp>>) -> Self {
+        self.cmp.insert(tree_id, cmp);
+        self
+    }
+
+    /// sets the comparator of trees without their own comparator.
+    pub fn with_default_cmp(mut self, cmp: Rc<RefCell<dyn KeyCmp>>) -> Self {
+        self.default_cmp = Some(cmp);
+        self
+    }
+
+    /// adds the secondary index of the tree. must be called before the first write to the tree.
+    /// keys of an indexed tree are unique: insert replaces the record with the same key.
+    pub fn with_index(mut self, tree_id: u32, index: IndexDef) -> Self {
+        self.indexes.entry(tree_id).or_default().push(index);
+        self
+    }
+
+    /// keeps duplicates of a key in the insertion order. must be set before the first write to the tree.
+    pub fn with_duplicates(mut self, tree_id: u32) -> Self {
+        self.duplicates.insert(tree_id);
+        self
+    }
+
+    /// keeps changes of commits in the tree `CHANGES_TREE_ID`, see `changes_after`.
+    pub fn with_change_feed(mut self) -> Self {
+        self.change_feed = true;
+        self
+    }
+
+    /// takes aggregates, indexes, duplicates and the change feed setting of the storage.
+    pub(crate) fn with_config_of(mut self, other: &Storage) -> Self {
+        self.aggregates = other.aggregates.clone();
+        self.indexes = other.indexes.clone();
+        self.duplicates = other.duplicates.clone();
+        self.change_feed = other.change_feed;
+        self
+    }
+
+    /// the observer is called after each commit with changes.
+    /// bulk loads are not reported.
+    pub fn subscribe(&mut self, observer: Rc<RefCell<dyn CommitObserver>>) {
+        self.observers.push(observer);
+    }
+
+    pub fn params(&self) -> StorageParams {
+        self.params
+    }
+
+    /// end of the last committed transaction list in the file.
+    pub(super) fn committed_end(&self) -> Result<usize> {
+        committed_end(&*self.store.borrow(), &self.header)
+    }
+
+    /// offset of the last committed transaction list. 0 - there are no commits.
+    pub(super) fn header_offset(&self) -> usize {
+        self.header.offset as usize
+    }
+
+    /// see `backup::commit_digest`. `end` must not be after the committed end.
+    pub(super) fn commit_digest(&self, list: usize, end: usize) -> Result<Option<u64>> {
+        commit_digest(&*self.store.borrow(), list, end)
+    }
+
+    /// appends the backup increment and rereads the header. open transactions are lost.
+    pub(super) fn apply_increment(&mut self, input: &mut dyn std::io::Read) -> Result<usize> {
+        let end = apply_increment(&*self.store.borrow(), input)?;
+        self.header = self.store.borrow().header_read()?;
+        self.tree_storages.clear();
+        self.t.clear();
+        self.commit_seq = None;
+        Ok(end)
+    }
+
     pub fn close(&mut self) -> Result<()> {
         self.header.is_closed = 1;
         self.store.borrow_mut().header_write(&self.header)?;
         Ok(())
     }
 
+    /// copies the last committed state to the empty target, while transactions stay open.
+    /// the target must be of the same kind as the storage. returns the offset for `backup_incremental`.
+    pub fn backup_to(&self, target: &mut dyn FlatStorage) -> Result<usize> {
+        copy_image(&*self.store.borrow(), &self.header, target)
+    }
+
+    /// writes records committed after `since_offset` to `out`.
+    /// returns the offset for the next increment.
+    pub fn backup_incremental(
+        &self,
+        since_offset: usize,
+        out: &mut dyn std::io::Write,
+    ) -> Result<usize> {
+        write_increment(&*self.store.borrow(), &self.header, since_offset, out)
+    }
+
+    /// restores the full backup with its increments in the order they were made to the empty target
+    /// and checks the trees of the result.
+    pub fn restore(
+        target: Rc<RefCell<dyn FlatStorage>>,
+        full: &dyn FlatStorage,
+        increments: &mut [&mut dyn std::io::Read],
+        cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
+    ) -> Result<Self> {
+        {
+            let t = target.borrow();
+            copy_image(full, &full.header_read()?, &*t)?;
+            for i in increments.iter_mut() {
+                apply_increment(&*t, *i)?;
+            }
+        }
+        let mut result = Storage::open(target, cmp)?;
+        let violations = result.check()?;
+        if let Some((tree_id, v)) = violations.first() {
+            return Err(crate::Error::Corrupted(format!(
+                "restored tree {}: {:?}",
+                tree_id, v
+            )));
+        }
+        Ok(result)
+    }
+
     pub(super) fn insert_kv(store: &dyn FlatStorage, key: &[u8], data: &[u8]) -> Result<u32> {
+        let offset = Self::write_kv(store, key, data)?;
+        //TODO read from buffer;
+        store.flush()?;
+        Ok(offset)
+    }
+
+    pub(super) fn write_kv(store: &dyn FlatStorage, key: &[u8], data: &[u8]) -> Result<u32> {
         //TODO Result<u32> => Result<u64>
         let offset = store.size();
         store.write_u32(key.len() as u32)?;
@@ -129,8 +360,6 @@ impl Storage {
         for i in data.iter() {
             store.write_u8(*i)?;
         }
-        //TODO read from buffer;
-        store.flush()?;
         return Ok(offset as u32);
     }
 
@@ -188,21 +417,98 @@ impl Storage {
         return Ok(data);
     }
 
-    fn get_tree_cmp(&self, tree_id: u32) -> Rc<RefCell<StorageNodeCmp>> {
+    fn get_key_cmp(&self, tree_id: u32) -> Result<Rc<RefCell<dyn KeyCmp>>> {
+        if let Some((primary, index)) = self.index_by_tree(tree_id) {
+            return Ok(Rc::new(RefCell::new(IndexEntryCmp {
+                index: index.cmp.clone(),
+                primary: self.get_key_cmp(primary)?,
+            })));
+        }
+        if tree_id == CHANGES_TREE_ID {
+            return Ok(Rc::new(RefCell::new(BytewiseKeyCmp {})));
+        }
+        match self.cmp.get(&tree_id).or(self.default_cmp.as_ref()) {
+            Some(c) => Ok(c.clone()),
+            None => Err(crate::Error::MissingComparator(tree_id)),
+        }
+    }
+
+    /// true, if the tree keeps entries of a secondary index.
+    pub fn is_index_tree(&self, tree_id: u32) -> bool {
+        self.index_by_tree(tree_id).is_some()
+    }
+
+    /// name of the comparator of the tree.
+    pub fn cmp_name(&self, tree_id: u32) -> Result<String> {
+        Ok(self.get_key_cmp(tree_id)?.borrow().name().to_owned())
+    }
+
+    /// primary tree and definition of the index stored in the tree.
+    fn index_by_tree(&self, tree_id: u32) -> Option<(u32, &IndexDef)> {
+        self.indexes.iter().find_map(|(primary, defs)| {
+            defs.iter()
+                .find(|d| d.tree_id == tree_id && tree_id != CHANGES_TREE_ID)
+                .map(|d| (*primary, d))
+        })
+    }
+
+    /// the change feed tree and index trees on it are written by the storage only.
+    fn check_tree_id(&self, tree_id: u32) -> Result<()> {
+        let reserved = tree_id == CHANGES_TREE_ID
+            || self
+                .indexes
+                .get(&tree_id)
+                .is_some_and(|defs| defs.iter().any(|d| d.tree_id == CHANGES_TREE_ID));
+        if reserved {
+            return Err(crate::Error::InvalidParams(format!(
+                "tree {} is reserved for the change feed",
+                CHANGES_TREE_ID
+            )));
+        }
+        Ok(())
+    }
+
+    fn tree_indexes(&self, tree_id: u32) -> Vec<IndexDef> {
+        self.indexes.get(&tree_id).cloned().unwrap_or_default()
+    }
+
+    fn get_tree_cmp(&self, tree_id: u32) -> Result<Rc<RefCell<StorageNodeCmp>>> {
         let cmp = Rc::new(RefCell::new(StorageNodeCmp {
             store: self.store.clone(),
-            cmp: self.cmp.get(&tree_id).unwrap().clone(),
+            cmp: self.get_key_cmp(tree_id)?,
+            error: self.cmp_error.clone(),
+            by_offset: self.duplicates.contains(&tree_id),
         }));
-        return cmp;
+        Ok(cmp)
+    }
+
+    /// returns the first error of reading keys in comparators since the last call.
+    fn take_cmp_error(&self) -> Result<()> {
+        match self.cmp_error.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn get_tree_aggregate(&self, tree_id: u32) -> Option<AggregateRc> {
+        match self.aggregates.get(&tree_id) {
+            Some(a) => Some(Rc::new(RefCell::new(StorageNodeAggregate {
+                store: self.store.clone(),
+                aggregate: a.clone(),
+            }))),
+            None => None,
+        }
     }
 
-    fn make_cmp(&self, tree_id: u32, key: &[u8]) -> Rc<RefCell<StorageKeyCmpRef>> {
+    fn make_cmp(&self, tree_id: u32, key: &[u8]) -> Result<Rc<RefCell<StorageKeyCmpRef>>> {
         let cmp = Rc::new(RefCell::new(StorageKeyCmpRef {
             store: self.store.clone(),
             user_key: key.to_vec(),
-            cmp: self.cmp.get(&tree_id).unwrap().clone(),
+            cmp: self.get_key_cmp(tree_id)?,
+            error: self.cmp_error.clone(),
+            by_offset: self.duplicates.contains(&tree_id),
         }));
-        return cmp;
+        Ok(cmp)
     }
 
     pub fn dump_tree(&self, tree_id: u32, name: String) -> String {
@@ -220,26 +526,136 @@ impl Storage {
         );
     }
 
-    fn save_trees(&mut self) -> Result<()> {
-        let mut trans_list = Vec::new();
-        let flat_store = self.store.borrow_mut();
+    /// statistics of the tree: shape, fill and bytes used in the file.
+    pub fn stats(&mut self, tree_id: u32) -> Result<TreeStats> {
+        if self.header.offset != 0 {
+            self.load_trees()?;
+        }
+        match self.tree_storages.get(&tree_id) {
+            Some(s) => tree_stats(tree_id, &s.borrow(), &*self.store.borrow()),
+            None => Err(crate::Error::UnknownTree(tree_id)),
+        }
+    }
+
+    /// statistics of all trees and live and garbage bytes of the file.
+    pub fn file_stats(&mut self) -> Result<FileStats> {
+        let mut result = FileStats {
+            total_bytes: self.store.borrow().size(),
+            ..Default::default()
+        };
+        if self.header.offset == 0 {
+            result.garbage_bytes = result.total_bytes;
+            return Ok(result);
+        }
+        self.load_trees()?;
+
+        let store = self.store.borrow();
+        let trees_count = store.read_u32(self.header.offset as usize + U32SZ)? as usize;
+        result.transaction_bytes = 2 * U32SZ + trees_count * U32SZ;
+
+        let mut ids: Vec<u32> = self.tree_storages.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let s = self.tree_storages.get(&id).unwrap();
+            let t = tree_stats(id, &s.borrow(), &*store)?;
+            result.kv_bytes += t.kv_bytes;
+            result.node_bytes += t.node_bytes;
+            result.transaction_bytes += t.transaction_bytes;
+            result.trees.push(t);
+        }
+        result.live_bytes = result.kv_bytes + result.node_bytes + result.transaction_bytes;
+        result.garbage_bytes = result.total_bytes.saturating_sub(result.live_bytes);
+        Ok(result)
+    }
+
+    /// checks the invariants of all trees. returns the found violations with the tree id.
+    pub fn check(&mut self) -> Result<Vec<(u32, Violation)>> {
+        let mut result = Vec::new();
+        if self.header.offset == 0 {
+            return Ok(result);
+        }
+        self.load_trees()?;
+
+        let mut ids: Vec<u32> = self.tree_storages.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let storage = self.tree_storages.get(&id).unwrap().clone();
+            storage.borrow_mut().set_cmp(self.get_tree_cmp(id)?);
+            let root = storage.borrow().get_root();
+            if let Some(root) = root {
+                let mut a = storage.borrow_mut();
+                let violations = crate::tree::verify::check(&mut *a, &root)?;
+                self.take_cmp_error()?;
+                for v in violations {
+                    result.push((id, v));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// bytes, which `save_trees` will write for the trees.
+    fn commit_size(trees: &HashMap<u32, StorageNodeStorageRc>) -> usize {
+        let mut result = 2 * U32SZ + std::mem::size_of::<StorageHeader>();
+        for s in trees.values() {
+            result += U32SZ + s.borrow().save_size();
+        }
+        result
+    }
 
-        for ns in self.tree_storages.iter() {
-            let mut cur_store = ns.1.borrow_mut();
-            let cur_store_offset = cur_store.save(*ns.0, &*flat_store)?;
-            trans_list.push(cur_store_offset);
+    /// fails with `IsFull`, if `bytes` more do not fit in the max file size.
+    fn check_capacity(&self, bytes: usize) -> Result<()> {
+        let max = self.params.max_file_size;
+        if max != 0 && (self.store.borrow().size() + bytes) as u64 > max {
+            return Err(crate::Error::IsFull);
         }
+        Ok(())
+    }
+
+    /// used and remaining bytes of the file.
+    pub fn capacity(&mut self) -> Result<Capacity> {
+        let stats = self.file_stats()?;
+        let max = self.params.max_file_size;
+        Ok(Capacity {
+            max_file_size: max,
+            used_bytes: stats.total_bytes,
+            remaining_bytes: if max == 0 {
+                None
+            } else {
+                Some((max as usize).saturating_sub(stats.total_bytes))
+            },
+            reclaimable_bytes: stats.garbage_bytes,
+        })
+    }
 
-        self.header.offset = flat_store.size() as u32;
+    /// writes the trees and makes them the committed trees. if the write fails, the committed
+    /// trees and the header stay as they were, so `trees` must not share changed storages with them.
+    fn save_trees_with(&mut self, trees: HashMap<u32, StorageNodeStorageRc>) -> Result<()> {
+        self.check_capacity(Self::commit_size(&trees))?;
+        let mut trans_list = Vec::new();
+        let mut header = self.header;
+        {
+            let flat_store = self.store.borrow_mut();
+
+            for ns in trees.iter() {
+                let mut cur_store = ns.1.borrow_mut();
+                let cur_store_offset = cur_store.save(*ns.0, &*flat_store)?;
+                trans_list.push(cur_store_offset);
+            }
 
-        flat_store.write_u32(MAGIC_TRANSACTION_LIST)?;
-        flat_store.write_u32(trans_list.len() as u32)?;
-        for i in trans_list {
-            flat_store.write_u32(i)?;
+            header.offset = flat_store.size() as u32;
+
+            flat_store.write_u32(MAGIC_TRANSACTION_LIST)?;
+            flat_store.write_u32(trans_list.len() as u32)?;
+            for i in trans_list {
+                flat_store.write_u32(i)?;
+            }
+            flat_store.flush()?;
+            flat_store.header_write(&header)?;
+            flat_store.flush()?;
         }
-        flat_store.flush()?;
-        flat_store.header_write(&self.header)?;
-        flat_store.flush()?;
+        self.header = header;
+        self.tree_storages = trees;
         Ok(())
     }
 
@@ -249,9 +665,10 @@ impl Storage {
         }
         self.tree_storages.clear();
         let store = self.store.borrow();
-        let hdr = store.header_read()?;
+        // the header in the file is followed by records of open transactions.
+        let hdr = self.header;
         if hdr.offset == 0 {
-            panic!();
+            return Ok(());
         }
 
         let mut trees_offsets = Vec::new();
@@ -261,7 +678,10 @@ impl Storage {
             let magic_lst = store.read_u32(offset)?;
             offset += U32SZ;
             if magic_lst != MAGIC_TRANSACTION_LIST {
-                panic!();
+                return Err(crate::Error::Corrupted(format!(
+                    "bad transaction list magic {:#x} at {}",
+                    magic_lst, hdr.offset
+                )));
             }
             let storages_count = store.read_u32(offset)?;
             offset += U32SZ;
@@ -280,17 +700,22 @@ impl Storage {
             offset += U32SZ;
 
             if magic != MAGIC_TRANSACTION {
-                panic!();
+                return Err(crate::Error::Corrupted(format!(
+                    "bad tree magic {:#x} at {}",
+                    magic, start
+                )));
             }
 
             let tree_id = store.read_u32(offset)?;
 
             let s = StorageNodeStorage::new(
                 start as u32,
-                self.get_tree_cmp(tree_id),
+                self.get_tree_cmp(tree_id)?,
                 self.store.clone(),
                 self.params.tree_params,
             );
+            s.borrow_mut()
+                .set_aggregate(self.get_tree_aggregate(tree_id));
             self.tree_storages.insert(tree_id, s.clone());
 
             s.borrow_mut().load(start as usize)?;
@@ -314,12 +739,24 @@ impl Storage {
         transaction: u64,
         tree_id: u32,
     ) -> Result<Rc<RefCell<StorageNodeStorage>>> {
-        let target_trans = self.t.get(&transaction).unwrap().clone();
+        let target_trans = match self.t.get(&transaction) {
+            Some(t) => t.clone(),
+            None => return Err(crate::Error::TransactionNotFound),
+        };
         if let Some(tree) = target_trans.borrow_mut().try_get(tree_id) {
             return Ok(tree);
         }
 
-        let tcmp = self.get_tree_cmp(tree_id);
+        let target_storage = self.copy_storage_for_tree(tree_id)?;
+        target_trans
+            .borrow_mut()
+            .add_tree(tree_id, target_storage.clone());
+        Ok(target_storage)
+    }
+
+    /// a copy of the committed tree or a new tree to change.
+    fn copy_storage_for_tree(&self, tree_id: u32) -> Result<StorageNodeStorageRc> {
+        let tcmp = self.get_tree_cmp(tree_id)?;
         let target_storage = if let Some(t) = self.tree_storages.get(&tree_id) {
             let c = t.borrow().clone();
             c.borrow_mut().set_offset(0).set_cmp(tcmp);
@@ -327,11 +764,10 @@ impl Storage {
         } else {
             let s =
                 StorageNodeStorage::new(0u32, tcmp, self.store.clone(), self.params.tree_params);
-            s.clone()
+            s.borrow_mut()
+                .set_aggregate(self.get_tree_aggregate(tree_id));
+            s
         };
-        target_trans
-            .borrow_mut()
-            .add_tree(tree_id, target_storage.clone());
         Ok(target_storage)
     }
 
@@ -343,7 +779,15 @@ impl Storage {
         tparams: TreeParams,
     ) -> Result<()> {
         let target_storage = self.get_or_create_storage_for_tree(transaction, tree_id)?;
+        self.insert_to_storage(&target_storage, key_offset, tparams)
+    }
 
+    fn insert_to_storage(
+        &self,
+        target_storage: &StorageNodeStorageRc,
+        key_offset: u32,
+        tparams: TreeParams,
+    ) -> Result<()> {
         let mut storage_ref = (*target_storage).borrow_mut();
 
         let root = if let Some(t) = storage_ref.get_root() {
@@ -360,7 +804,7 @@ impl Storage {
             key_offset,
             &crate::tree::record::Record::from_u32(key_offset),
         )?;
-        Ok(())
+        self.take_cmp_error()
     }
 
     pub fn begin_transaction(&mut self) -> Result<u64> {
@@ -376,13 +820,163 @@ impl Storage {
             //TODO test
             return Err(crate::Error::TransactionNotFound);
         }
-        let targetrc = res.unwrap();
-        self.commit(targetrc.clone());
+        let targetrc = res.unwrap().clone();
+
+        let changes = std::mem::take(&mut targetrc.borrow_mut().changes);
+        let seq = self.last_commit_seq()? + 1;
+        let mut log = Vec::new();
+        if self.change_feed {
+            for (n, c) in changes.iter().enumerate() {
+                log.push((log_key(seq, n as u32), encode_change(c)));
+            }
+        }
+        let log_bytes: usize = log.iter().map(|(k, v)| 2 * U32SZ + k.len() + v.len()).sum();
+
+        // the transaction stays open, if the commit fails. its trees are saved from copies.
+        let mut trees = self.tree_storages.clone();
+        for (id, s) in targetrc.borrow().tree_storages.iter() {
+            // a read or a delete in a missing tree does not create it.
+            if s.borrow().get_root().is_none() && !trees.contains_key(id) {
+                continue;
+            }
+            trees.insert(*id, s.borrow().clone());
+        }
+        if let Err(e) = self.save_commit(trees, &log, log_bytes) {
+            targetrc.borrow_mut().changes = changes;
+            return Err(e);
+        }
         self.t.remove(&t);
-        self.save_trees()?;
+
+        if !changes.is_empty() {
+            self.commit_seq = Some(seq);
+            let commit = Commit { seq, changes };
+            for o in self.observers.iter() {
+                o.borrow_mut().on_commit(&commit);
+            }
+        }
         Ok(())
     }
 
+    /// sequence number of the last commit with changes, 0 - no commits.
+    /// without the change feed, numbers start from 0 after each open.
+    pub fn last_commit_seq(&mut self) -> Result<u64> {
+        if let Some(seq) = self.commit_seq {
+            return Ok(seq);
+        }
+        self.load_trees()?;
+        let seq = match self.last(CHANGES_TREE_ID)? {
+            Some((key, _)) => log_seq(&key)?,
+            None => 0,
+        };
+        self.commit_seq = Some(seq);
+        Ok(seq)
+    }
+
+    /// commits of the change feed after the sequence number, in the commit order.
+    pub fn changes_after(&mut self, seq: u64) -> Result<Vec<Commit>> {
+        self.load_trees()?;
+        let from = log_key(seq.saturating_add(1), 0);
+        let position = self.key_position(CHANGES_TREE_ID, |cmp, k| {
+            if cmp.compare(k, &from).is_lt() {
+                Ordering::Less
+            } else {
+                Ordering::Equal
+            }
+        })?;
+
+        let storage = match self.get_exist_storage_for_tree(CHANGES_TREE_ID)? {
+            Some(x) => x,
+            None => return Ok(Vec::new()),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(Vec::new()),
+        };
+        let mut offsets = Vec::new();
+        crate::tree::read::map_by(&mut *storage.borrow_mut(), &root, &position, &mut |k, _| {
+            offsets.push(k)
+        })?;
+        self.take_cmp_error()?;
+
+        let store = self.store.borrow();
+        let mut result: Vec<Commit> = Vec::new();
+        for offset in offsets {
+            let key = Self::read_key(&*store, offset as usize)?;
+            if is_seq_marker(&key) {
+                continue;
+            }
+            let seq = log_seq(&key)?;
+            let change = decode_change(&Self::read_kdata(&*store, offset as usize)?)?;
+            match result.last_mut() {
+                Some(last) if last.seq == seq => last.changes.push(change),
+                _ => result.push(Commit {
+                    seq,
+                    changes: vec![change],
+                }),
+            }
+        }
+        Ok(result)
+    }
+
+    /// removes the commits up to the sequence number from the change feed.
+    /// if the last commit is removed, a marker keeps its sequence number for `last_commit_seq`.
+    /// returns the count of removed changes.
+    pub fn trim_changes(&mut self, up_to: u64) -> Result<usize> {
+        self.load_trees()?;
+        let last = self.last_commit_seq()?;
+        // a marker is written after all other records are trimmed, so it is the first one.
+        let has_marker = match self.first(CHANGES_TREE_ID)? {
+            Some((key, _)) => is_seq_marker(&key) && log_seq(&key)? <= up_to,
+            None => false,
+        };
+        let tr = self.begin_transaction()?;
+        let res = self.trim_changes_in(tr, up_to, last);
+        if res.is_err() {
+            self.t.remove(&tr);
+            return res;
+        }
+        self.commit_transaction(tr)?;
+        res.map(|removed| removed - has_marker as usize)
+    }
+
+    fn trim_changes_in(&mut self, transaction: u64, up_to: u64, last: u64) -> Result<usize> {
+        let removed = self.delete_range_in(
+            transaction,
+            CHANGES_TREE_ID,
+            &log_key(0, 0),
+            &log_key(up_to, u32::MAX),
+        )?;
+        if removed > 0 && up_to >= last {
+            let marker = log_key(last, SEQ_MARKER);
+            self.check_capacity(2 * U32SZ + marker.len())?;
+            let offset = Self::insert_kv(&*self.store.borrow_mut(), &marker, &[])?;
+            let tparams = self.params.tree_params;
+            self.insert_to_tree(transaction, CHANGES_TREE_ID, offset, tparams)?;
+        }
+        Ok(removed)
+    }
+
+    fn records_changes(&self) -> bool {
+        self.change_feed || !self.observers.is_empty()
+    }
+
+    fn record_change(&self, transaction: u64, tree_id: u32, key: &[u8], value: Option<&[u8]>) {
+        if !self.records_changes() || tree_id == CHANGES_TREE_ID {
+            return;
+        }
+        if let Some(t) = self.t.get(&transaction) {
+            t.borrow_mut().changes.push(Change {
+                tree_id,
+                key: key.to_vec(),
+                op: match value {
+                    Some(_) => ChangeOp::Put,
+                    None => ChangeOp::Delete,
+                },
+                value: value.map(|v| v.to_vec()),
+            });
+        }
+    }
+
     pub fn rollback_transaction(&mut self, t: u64) -> Result<()> {
         let res = self.t.get(&t);
         if res.is_none() {
@@ -393,10 +987,31 @@ impl Storage {
         Ok(())
     }
 
-    fn commit(&mut self, target: Rc<RefCell<Tr>>) {
-        for kv in target.borrow().tree_storages.iter() {
-            self.tree_storages.insert(*kv.0, kv.1.clone());
+    fn save_commit(
+        &mut self,
+        mut trees: HashMap<u32, StorageNodeStorageRc>,
+        log: &[(Vec<u8>, Vec<u8>)],
+        log_bytes: usize,
+    ) -> Result<()> {
+        self.check_capacity(Self::commit_size(&trees) + log_bytes)?;
+        if !log.is_empty() {
+            // the committed tree may be in `trees`, so it is copied too.
+            let changes_tree = match trees.get(&CHANGES_TREE_ID) {
+                Some(s) => {
+                    let c = s.borrow().clone();
+                    c.borrow_mut().set_cmp(self.get_tree_cmp(CHANGES_TREE_ID)?);
+                    c
+                }
+                None => self.copy_storage_for_tree(CHANGES_TREE_ID)?,
+            };
+            let tparams = self.params.tree_params;
+            for (key, value) in log.iter() {
+                let offset = Self::insert_kv(&*self.store.borrow_mut(), key, value)?;
+                self.insert_to_storage(&changes_tree, offset, tparams)?;
+            }
+            trees.insert(CHANGES_TREE_ID, changes_tree);
         }
+        self.save_trees_with(trees)
     }
 
     pub fn insert(
@@ -406,19 +1021,156 @@ impl Storage {
         key: &[u8],
         data: &[u8],
     ) -> Result<()> {
+        self.check_tree_id(tree_id)?;
         let tparams = self.params.tree_params.clone();
 
+        self.replace_indexed(transaction, tree_id, key, data)?;
+        self.check_capacity(2 * U32SZ + key.len() + data.len())?;
         let key_offset = Self::insert_kv(&*self.store.borrow_mut(), key, data)?;
         self.insert_to_tree(transaction, tree_id, key_offset, tparams)?;
+        self.insert_to_indexes(transaction, tree_id, key, data)?;
+        self.record_change(transaction, tree_id, key, Some(data));
+        Ok(())
+    }
+
+    /// checks unique indexes and removes the old record with the key, if the tree is indexed.
+    fn replace_indexed(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        if !self.indexes.contains_key(&tree_id) {
+            return Ok(());
+        }
+        let primary_cmp = self.get_key_cmp(tree_id)?;
+        for index in self.tree_indexes(tree_id) {
+            if !index.unique {
+                continue;
+            }
+            let index_key = match (index.key)(value) {
+                Some(k) => k,
+                None => continue,
+            };
+            for entry in self.index_entries(Some(transaction), &index, &index_key)? {
+                if primary_cmp
+                    .borrow()
+                    .compare(split_entry(&entry).1, key)
+                    .is_ne()
+                {
+                    return Err(crate::Error::UniqueViolation(index.tree_id));
+                }
+            }
+        }
+        self.delete(transaction, tree_id, key)?;
+        Ok(())
+    }
+
+    fn insert_to_indexes(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        let tparams = self.params.tree_params;
+        for index in self.tree_indexes(tree_id) {
+            if let Some(index_key) = (index.key)(value) {
+                let entry = entry_key(&index_key, key);
+                self.check_capacity(2 * U32SZ + entry.len())?;
+                let offset = Self::insert_kv(&*self.store.borrow_mut(), &entry, &[])?;
+                self.insert_to_tree(transaction, index.tree_id, offset, tparams)?;
+            }
+        }
+        Ok(())
+    }
 
+    fn remove_from_indexes(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        for index in self.tree_indexes(tree_id) {
+            if let Some(index_key) = (index.key)(value) {
+                self.remove_from_tree(transaction, index.tree_id, &entry_key(&index_key, key))?;
+            }
+        }
         Ok(())
     }
 
+    /// keys of the index entries with the index key. without the transaction, committed entries are read.
+    fn index_entries(
+        &mut self,
+        transaction: Option<u64>,
+        index: &IndexDef,
+        index_key: &[u8],
+    ) -> Result<Vec<Vec<u8>>> {
+        let storage = match transaction {
+            Some(t) => self.get_or_create_storage_for_tree(t, index.tree_id)?,
+            None => match self.get_exist_storage_for_tree(index.tree_id)? {
+                Some(x) => x,
+                None => return Ok(Vec::new()),
+            },
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(Vec::new()),
+        };
+        let store = self.store.clone();
+        let cmp = index.cmp.clone();
+        let position = move |offset: u32| {
+            let entry = Self::read_key(&*store.borrow(), offset as usize)?;
+            Ok(entry_position(&*cmp.borrow(), &entry, index_key))
+        };
+        let mut offsets = Vec::new();
+        crate::tree::read::map_by(&mut *storage.borrow_mut(), &root, &position, &mut |k, _| {
+            offsets.push(k)
+        })?;
+        self.take_cmp_error()?;
+
+        let store = self.store.borrow();
+        let mut result = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            result.push(Self::read_key(&*store, offset as usize)?);
+        }
+        Ok(result)
+    }
+
+    /// primary records with the key in the index stored in the tree, in the primary key order.
+    pub fn find_by_index(
+        &mut self,
+        index_tree_id: u32,
+        index_key: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.load_trees()?;
+        let (primary, index) = match self.index_by_tree(index_tree_id) {
+            Some((p, i)) => (p, i.clone()),
+            None => return Err(crate::Error::UnknownTree(index_tree_id)),
+        };
+        let mut result = Vec::new();
+        for entry in self.index_entries(None, &index, index_key)? {
+            let primary_key = split_entry(&entry).1;
+            match self.find(primary, primary_key)? {
+                Some(value) => result.push((primary_key.to_vec(), value)),
+                None => {
+                    return Err(crate::Error::Corrupted(format!(
+                        "index {} refers to a missing record",
+                        index_tree_id
+                    )))
+                }
+            }
+        }
+        Ok(result)
+    }
+
     pub fn find(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.load_trees()?;
 
         let storage = if let Some(x) = self.get_exist_storage_for_tree(tree_id)? {
-            x.borrow_mut().set_cmp(self.make_cmp(tree_id, key));
+            x.borrow_mut().set_cmp(self.make_cmp(tree_id, key)?);
             x
         } else {
             return Ok(None);
@@ -431,6 +1183,7 @@ impl Storage {
 
         let mut a = storage.borrow_mut();
         let find_res = crate::tree::read::find(&mut *a, &root.unwrap().clone(), std::u32::MAX)?;
+        self.take_cmp_error()?;
         if find_res.is_none() {
             return Ok(None);
         }
@@ -440,181 +1193,825 @@ impl Storage {
         return Ok(Some(d));
     }
 
-    pub fn remove(&mut self, tree_id: u32, key: &[u8]) -> Result<()> {
+    /// count of keys less than `key`.
+    pub fn rank(&mut self, tree_id: u32, key: &[u8]) -> Result<usize> {
         self.load_trees()?;
 
-        if let Some(t) = self.tree_storages.get(&tree_id) {
-            let storage = t.clone();
-            storage
-                .borrow_mut()
-                .set_offset(0)
-                .set_cmp(self.make_cmp(tree_id, key));
-            let root = storage.borrow().get_root();
-            if root.is_none() {
-                return Ok(());
-            }
-
-            let mut a = storage.borrow_mut();
-            crate::tree::remove::remove_key(&mut *a, &root.unwrap().clone(), std::u32::MAX)?;
-        } else {
-            return Ok(());
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(0),
         };
-
-        self.save_trees()?;
-        Ok(())
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let mut a = storage.borrow_mut();
+        a.set_cmp(self.make_cmp(tree_id, key)?);
+        let result = crate::tree::order_stat::rank(&mut *a, &root, u32::MAX)?;
+        self.take_cmp_error()?;
+        Ok(result)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::{cell::RefCell, rc::Rc};
 
-    use super::*;
-    use crate::{types::SingleElementStore, utils::any_as_u8_slice, Result};
+    /// count of keys in [from, to].
+    pub fn count_range(&mut self, tree_id: u32, from: &[u8], to: &[u8]) -> Result<usize> {
+        self.load_trees()?;
 
-    struct MockStorageKeyCmp {}
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let mut a = storage.borrow_mut();
+        a.set_cmp(self.make_cmp(tree_id, to)?);
+        let to_rank = crate::tree::order_stat::rank_le(&mut *a, &root, u32::MAX)?;
+        a.set_cmp(self.make_cmp(tree_id, from)?);
+        let from_rank = crate::tree::order_stat::rank(&mut *a, &root, u32::MAX)?;
+        self.take_cmp_error()?;
+        Ok(to_rank.saturating_sub(from_rank))
+    }
 
-    impl MockStorageKeyCmp {
-        fn new() -> MockStorageKeyCmp {
-            MockStorageKeyCmp {}
+    /// key and value of the record, found by `f` in the tree.
+    /// `key` is compared with the records as `u32::MAX`.
+    fn lookup<F>(
+        &mut self,
+        tree_id: u32,
+        key: Option<&[u8]>,
+        f: F,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>>
+    where
+        F: FnOnce(&mut StorageNodeStorage, &RcNode) -> Result<Option<(u32, Record)>>,
+    {
+        self.load_trees()?;
+
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let mut a = storage.borrow_mut();
+        if let Some(key) = key {
+            a.set_cmp(self.make_cmp(tree_id, key)?);
+        }
+        let found = f(&mut a, &root)?;
+        self.take_cmp_error()?;
+        match found {
+            Some((offset, _)) => {
+                let store = self.store.borrow();
+                let key = Self::read_key(&*store, offset as usize)?;
+                let value = Self::read_kdata(&*store, offset as usize)?;
+                Ok(Some((key, value)))
+            }
+            None => Ok(None),
         }
     }
 
-    impl KeyCmp for MockStorageKeyCmp {
-        fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
-            key1.cmp(key2)
+    /// n-th (from zero) key and value of the tree in the key order.
+    pub fn select(&mut self, tree_id: u32, n: usize) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, None, |s, root| {
+            crate::tree::order_stat::select(s, root, n)
+        })
+    }
+
+    /// the greatest key less than or equal to `key` with its value.
+    pub fn floor(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, Some(key), |s, root| {
+            crate::tree::read::floor(s, root, u32::MAX)
+        })
+    }
+
+    /// the greatest key less than `key` with its value.
+    pub fn lower(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, Some(key), |s, root| {
+            crate::tree::read::lower(s, root, u32::MAX)
+        })
+    }
+
+    /// the smallest key greater than or equal to `key` with its value.
+    pub fn ceiling(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, Some(key), |s, root| {
+            crate::tree::read::ceiling(s, root, u32::MAX)
+        })
+    }
+
+    /// the smallest key greater than `key` with its value.
+    pub fn higher(&mut self, tree_id: u32, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, Some(key), |s, root| {
+            crate::tree::read::higher(s, root, u32::MAX)
+        })
+    }
+
+    /// the smallest key of the tree with its value.
+    pub fn first(&mut self, tree_id: u32) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, None, crate::tree::read::first)
+    }
+
+    /// the greatest key of the tree with its value.
+    pub fn last(&mut self, tree_id: u32) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.lookup(tree_id, None, crate::tree::read::last)
+    }
+
+    /// combined summary of the values with keys in [from, to].
+    pub fn aggregate_range(
+        &mut self,
+        tree_id: u32,
+        from: &[u8],
+        to: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        self.load_trees()?;
+
+        if !self.aggregates.contains_key(&tree_id) {
+            return Err(crate::Error::InvalidParams(format!(
+                "aggregate for tree {} is not set",
+                tree_id
+            )));
         }
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        let position = self.key_position(tree_id, |cmp, key| range_position(cmp, key, from, to))?;
+        let mut a = storage.borrow_mut();
+        let result = crate::tree::aggregate::aggregate_range_by(&mut *a, &root, &position)?;
+        self.take_cmp_error()?;
+        Ok(result)
     }
 
-    struct MockPageStorage {
-        hdr: RefCell<SingleElementStore<StorageHeader>>,
-        params: RefCell<SingleElementStore<StorageParams>>,
-        space: RefCell<Vec<u8>>,
+    pub fn remove(&mut self, tree_id: u32, key: &[u8]) -> Result<()> {
+        self.check_tree_id(tree_id)?;
+        self.load_trees()?;
+
+        if self.indexes.contains_key(&tree_id) || self.records_changes() {
+            let tr = self.begin_transaction()?;
+            if let Err(e) = self.delete(tr, tree_id, key) {
+                self.t.remove(&tr);
+                return Err(e);
+            }
+            return self.commit_transaction(tr);
+        }
+
+        if !self.tree_storages.contains_key(&tree_id) {
+            return Ok(());
+        }
+        // the committed tree is changed only by a successful save.
+        self.check_capacity(Self::commit_size(&self.tree_storages))?;
+        let storage = self.copy_storage_for_tree(tree_id)?;
+        storage.borrow_mut().set_cmp(self.make_cmp(tree_id, key)?);
+        let root = storage.borrow().get_root();
+        let root = match root {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        {
+            let mut a = storage.borrow_mut();
+            crate::tree::remove::remove_key(&mut *a, &root, u32::MAX)?;
+        }
+        self.take_cmp_error()?;
+
+        let mut trees = self.tree_storages.clone();
+        trees.insert(tree_id, storage);
+        self.save_trees_with(trees)
     }
 
-    impl MockPageStorage {
-        pub fn new() -> MockPageStorage {
-            MockPageStorage {
-                params: RefCell::new(SingleElementStore::new()),
-                hdr: RefCell::new(SingleElementStore::new()),
-                space: RefCell::new(Vec::with_capacity(1024 * 1024 * 5)),
+    /// removes the record with the key in the transaction. returns false, if there is no such record.
+    pub fn delete(&mut self, transaction: u64, tree_id: u32, key: &[u8]) -> Result<bool> {
+        self.check_tree_id(tree_id)?;
+        self.load_trees()?;
+        if !self.indexes.contains_key(&tree_id) {
+            let removed = self.remove_from_tree(transaction, tree_id, key)?;
+            if removed {
+                self.record_change(transaction, tree_id, key, None);
             }
+            return Ok(removed);
         }
+        let value = match self.find_in(transaction, tree_id, key)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        self.remove_from_tree(transaction, tree_id, key)?;
+        self.remove_from_indexes(transaction, tree_id, key, &value)?;
+        self.record_change(transaction, tree_id, key, None);
+        Ok(true)
+    }
 
-        pub fn size(&self) -> usize {
-            self.space.borrow().len()
+    /// value of the key in the trees of the transaction.
+    fn find_in(&mut self, transaction: u64, tree_id: u32, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let target_storage = self.get_or_create_storage_for_tree(transaction, tree_id)?;
+        let mut storage_ref = target_storage.borrow_mut();
+        storage_ref.set_cmp(self.make_cmp(tree_id, key)?);
+
+        let root = match storage_ref.get_root() {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let found = crate::tree::read::find(&mut *storage_ref, &root, u32::MAX)?;
+        self.take_cmp_error()?;
+        match found {
+            Some(r) => Ok(Some(Self::read_kdata(
+                &*self.store.borrow(),
+                r.into_u32() as usize,
+            )?)),
+            None => Ok(None),
         }
     }
 
-    impl FlatStorage for MockPageStorage {
-        fn flush(&self) -> Result<()> {
-            Ok(())
+    fn remove_from_tree(&mut self, transaction: u64, tree_id: u32, key: &[u8]) -> Result<bool> {
+        let target_storage = self.get_or_create_storage_for_tree(transaction, tree_id)?;
+        let mut storage_ref = target_storage.borrow_mut();
+        storage_ref.set_cmp(self.make_cmp(tree_id, key)?);
+
+        let root = match storage_ref.get_root() {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        let found = crate::tree::read::find(&mut *storage_ref, &root, u32::MAX)?;
+        self.take_cmp_error()?;
+        if found.is_none() {
+            return Ok(false);
         }
-        fn close(&self) -> Result<()> {
-            Ok(())
+        crate::tree::remove::remove_key(&mut *storage_ref, &root, u32::MAX)?;
+        self.take_cmp_error()?;
+        Ok(true)
+    }
+
+    /// position of the kv record at the offset, computed by `position` from its key.
+    fn key_position<P>(&self, tree_id: u32, position: P) -> Result<impl Fn(u32) -> Result<Ordering>>
+    where
+        P: Fn(&dyn KeyCmp, &[u8]) -> Ordering,
+    {
+        let key_cmp = self.get_key_cmp(tree_id)?;
+        let store = self.store.clone();
+        Ok(move |offset: u32| {
+            let key = Self::read_key(&*store.borrow(), offset as usize)?;
+            let cmp = key_cmp.borrow();
+            Ok(position(&*cmp, &key))
+        })
+    }
+
+    fn prefix_key_position<'a>(
+        &self,
+        tree_id: u32,
+        prefix: &'a [u8],
+    ) -> Result<impl Fn(u32) -> Result<Ordering> + 'a> {
+        let upper = prefix_upper_bound(prefix);
+        self.key_position(tree_id, move |cmp, key| {
+            prefix_position(cmp, key, prefix, upper.as_deref())
+        })
+    }
+
+    fn delete_by(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        position: &dyn Fn(u32) -> Result<Ordering>,
+    ) -> Result<usize> {
+        let target_storage = self.get_or_create_storage_for_tree(transaction, tree_id)?;
+        let mut storage_ref = target_storage.borrow_mut();
+        storage_ref.set_cmp(self.get_tree_cmp(tree_id)?);
+        let root = match storage_ref.get_root() {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let mut rows = Vec::new();
+        if self.indexes.contains_key(&tree_id) || self.records_changes() {
+            crate::tree::read::map_by(&mut *storage_ref, &root, position, &mut |k, _| {
+                rows.push(k)
+            })?;
+        }
+        let (removed, _) =
+            crate::tree::remove::delete_range_by(&mut *storage_ref, &root, position)?;
+        self.take_cmp_error()?;
+        drop(storage_ref);
+
+        for offset in rows {
+            let (key, value) = {
+                let store = self.store.borrow();
+                (
+                    Self::read_key(&*store, offset as usize)?,
+                    Self::read_kdata(&*store, offset as usize)?,
+                )
+            };
+            self.remove_from_indexes(transaction, tree_id, &key, &value)?;
+            self.record_change(transaction, tree_id, &key, None);
         }
+        Ok(removed)
+    }
+
+    /// removes the records with keys in [from, to] in the transaction.
+    /// subtrees inside the range are unlinked as a whole. returns the count of removed records.
+    pub fn delete_range(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        from: &[u8],
+        to: &[u8],
+    ) -> Result<usize> {
+        self.check_tree_id(tree_id)?;
+        self.delete_range_in(transaction, tree_id, from, to)
+    }
 
-        fn params_write(&self, h: &StorageParams) -> Result<()> {
-            self.params.borrow_mut().replace(h.clone());
-            Ok(())
+    fn delete_range_in(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        from: &[u8],
+        to: &[u8],
+    ) -> Result<usize> {
+        let position = self.key_position(tree_id, |cmp, key| range_position(cmp, key, from, to))?;
+        self.delete_by(transaction, tree_id, &position)
+    }
+
+    /// removes the records with keys with the prefix in the transaction.
+    /// returns the count of removed records.
+    pub fn delete_prefix(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        prefix: &[u8],
+    ) -> Result<usize> {
+        self.check_tree_id(tree_id)?;
+        let position = self.prefix_key_position(tree_id, prefix)?;
+        self.delete_by(transaction, tree_id, &position)
+    }
+
+    /// keys and values of the records with keys with the prefix, in the key order.
+    pub fn scan_prefix(&mut self, tree_id: u32, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.load_trees()?;
+        let position = self.prefix_key_position(tree_id, prefix)?;
+
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(Vec::new()),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(Vec::new()),
+        };
+        let mut offsets = Vec::new();
+        crate::tree::read::map_by(&mut *storage.borrow_mut(), &root, &position, &mut |k, _| {
+            offsets.push(k)
+        })?;
+        self.take_cmp_error()?;
+
+        let store = self.store.borrow();
+        let mut result = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            let key = Self::read_key(&*store, offset as usize)?;
+            let value = Self::read_kdata(&*store, offset as usize)?;
+            result.push((key, value));
         }
+        Ok(result)
+    }
+
+    /// count of keys with the prefix.
+    pub fn count_prefix(&mut self, tree_id: u32, prefix: &[u8]) -> Result<usize> {
+        self.load_trees()?;
+        let position = self.prefix_key_position(tree_id, prefix)?;
+
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let mut a = storage.borrow_mut();
+        let result = crate::tree::order_stat::count_by(&mut *a, &root, &position)?;
+        self.take_cmp_error()?;
+        Ok(result)
+    }
+
+    /// ids of the committed trees in ascending order. the change feed tree is not listed.
+    pub fn tree_ids(&mut self) -> Result<Vec<u32>> {
+        self.load_trees()?;
+        let mut result: Vec<u32> = self
+            .tree_storages
+            .keys()
+            .copied()
+            .filter(|id| *id != CHANGES_TREE_ID)
+            .collect();
+        result.sort();
+        Ok(result)
+    }
+
+    /// true, if the change feed tree is committed.
+    pub(crate) fn has_changes(&mut self) -> Result<bool> {
+        self.load_trees()?;
+        Ok(self.tree_storages.contains_key(&CHANGES_TREE_ID))
+    }
 
-        fn params_read(&self) -> Result<StorageParams> {
-            if !self.params.borrow().is_empty() {
-                let rf = self.params.borrow_mut();
-                let value = rf.as_value();
-                return Ok(value);
+    /// calls `f` for all records of the tree in the key order.
+    pub fn for_each<F>(&mut self, tree_id: u32, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        self.for_each_by(tree_id, &|_| Ok(Ordering::Equal), |key, value| {
+            f(key, value).map(|_| true)
+        })
+    }
+
+    /// calls `f` for records with keys in [from, to] in the key order, until it returns false.
+    /// None - the range is open from that side.
+    pub fn scan_range<F>(
+        &mut self,
+        tree_id: u32,
+        from: Option<&[u8]>,
+        to: Option<&[u8]>,
+        f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        let position = self.key_position(tree_id, |cmp, key| {
+            if from.is_some_and(|from| cmp.compare(key, from).is_lt()) {
+                Ordering::Less
+            } else if to.is_some_and(|to| cmp.compare(key, to).is_gt()) {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
             }
-            panic!();
+        })?;
+        self.for_each_by(tree_id, &position, f)
+    }
+
+    fn for_each_by<F>(
+        &mut self,
+        tree_id: u32,
+        position: &dyn Fn(u32) -> Result<Ordering>,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        self.load_trees()?;
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let store = self.store.clone();
+        let mut error = None;
+        // the walk ends at the next key after `f` stops it or fails.
+        let stop = std::cell::Cell::new(false);
+        let bounded = |offset: u32| match stop.get() {
+            true => Ok(Ordering::Greater),
+            false => position(offset),
+        };
+        crate::tree::read::map_by(
+            &mut *storage.borrow_mut(),
+            &root,
+            &bounded,
+            &mut |offset, _| {
+                let res = {
+                    let store = store.borrow();
+                    Self::read_key(&*store, offset as usize).and_then(|key| {
+                        let value = Self::read_kdata(&*store, offset as usize)?;
+                        Ok((key, value))
+                    })
+                };
+                match res.and_then(|(key, value)| f(&key, &value)) {
+                    Ok(true) => {}
+                    Ok(false) => stop.set(true),
+                    Err(e) => {
+                        error = Some(e);
+                        stop.set(true);
+                    }
+                }
+            },
+        )?;
+        self.take_cmp_error()?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
+    }
+
+    /// values of all records with the key. in trees with duplicates they go in the insertion order.
+    pub fn get_all(&mut self, tree_id: u32, key: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.load_trees()?;
+        let position = self.key_position(tree_id, |cmp, k| range_position(cmp, k, key, key))?;
+
+        let storage = match self.get_exist_storage_for_tree(tree_id)? {
+            Some(x) => x,
+            None => return Ok(Vec::new()),
+        };
+        let root = match storage.borrow().get_root() {
+            Some(r) => r,
+            None => return Ok(Vec::new()),
+        };
+        let mut offsets = Vec::new();
+        crate::tree::read::map_by(&mut *storage.borrow_mut(), &root, &position, &mut |k, _| {
+            offsets.push(k)
+        })?;
+        self.take_cmp_error()?;
 
-        fn header_write(&self, h: &StorageHeader) -> Result<()> {
-            self.hdr.borrow_mut().replace(h.clone());
-            Ok(())
+        let store = self.store.borrow();
+        let mut result = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            result.push(Self::read_kdata(&*store, offset as usize)?);
         }
+        Ok(result)
+    }
+
+    /// count of records with the key.
+    pub fn count(&mut self, tree_id: u32, key: &[u8]) -> Result<usize> {
+        self.count_range(tree_id, key, key)
+    }
 
-        fn header_read(&self) -> Result<StorageHeader> {
-            if !self.hdr.borrow().is_empty() {
-                let rf = self.hdr.borrow_mut();
-                let value = rf.as_value();
-                return Ok(value);
+    /// removes the first record with the key and the value in the transaction.
+    /// returns false, if there is no such record. the tree must keep duplicates.
+    pub fn remove_one(
+        &mut self,
+        transaction: u64,
+        tree_id: u32,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool> {
+        self.check_tree_id(tree_id)?;
+        if !self.duplicates.contains(&tree_id) {
+            return Err(crate::Error::InvalidParams(format!(
+                "tree {} does not keep duplicates",
+                tree_id
+            )));
+        }
+        self.load_trees()?;
+        let position = self.key_position(tree_id, |cmp, k| range_position(cmp, k, key, key))?;
+
+        let target_storage = self.get_or_create_storage_for_tree(transaction, tree_id)?;
+        let mut storage_ref = target_storage.borrow_mut();
+        let root = match storage_ref.get_root() {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        let mut offsets = Vec::new();
+        crate::tree::read::map_by(&mut *storage_ref, &root, &position, &mut |k, _| {
+            offsets.push(k)
+        })?;
+
+        let mut target = None;
+        for offset in offsets {
+            if Self::read_kdata(&*self.store.borrow(), offset as usize)? == value {
+                target = Some(offset);
+                break;
             }
-            panic!();
         }
+        let offset = match target {
+            Some(o) => o,
+            None => return Ok(false),
+        };
+        // offsets of duplicates are unique keys of the tree.
+        storage_ref.set_cmp(self.get_tree_cmp(tree_id)?);
+        crate::tree::remove::remove_key(&mut *storage_ref, &root, offset)?;
+        self.take_cmp_error()?;
+        self.record_change(transaction, tree_id, key, None);
+        Ok(true)
+    }
 
-        fn size(&self) -> usize {
-            self.space.borrow_mut().len()
+    /// removes all records with the key in the transaction. returns the count of removed records.
+    pub fn remove_all(&mut self, transaction: u64, tree_id: u32, key: &[u8]) -> Result<usize> {
+        self.delete_range(transaction, tree_id, key, key)
+    }
+
+    /// applies all operations of the batch atomically with one commit.
+    /// observers and the change feed get the changes in the order of the batch.
+    pub fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
         }
 
-        fn write_id(&self, v: Id) -> Result<()> {
-            return self.write_u32(v.0);
+        let mut per_tree: BTreeMap<u32, Vec<(usize, BatchOp)>> = BTreeMap::new();
+        for (n, op) in batch.ops.into_iter().enumerate() {
+            per_tree.entry(op.tree_id()).or_default().push((n, op));
+        }
+        for tree_id in per_tree.keys() {
+            self.check_tree_id(*tree_id)?;
         }
 
-        fn write_bool(&self, v: bool) -> Result<()> {
-            if v {
-                self.space.borrow_mut().push(1u8)
-            } else {
-                self.space.borrow_mut().push(0u8)
+        // range deletes are barriers: operations are sorted only between them,
+        // so a put followed by a covering delete_range is still deleted.
+        for (tree_id, ops) in per_tree.iter_mut() {
+            let key_cmp = self.get_key_cmp(*tree_id)?;
+            let key_cmp = key_cmp.borrow();
+            for segment in ops.split_mut(|(_, op)| matches!(op, BatchOp::DeleteRange { .. })) {
+                segment.sort_by(|(_, a), (_, b)| key_cmp.compare(a.sort_key(), b.sort_key()));
             }
-            Ok(())
         }
 
-        fn write_u8(&self, v: u8) -> Result<()> {
-            self.space.borrow_mut().push(v);
-            Ok(())
+        if self.header.offset != 0 {
+            self.load_trees()?;
         }
 
-        fn write_u16(&self, v: u16) -> Result<()> {
-            let sl = unsafe { any_as_u8_slice(&v) };
-            for i in sl.iter() {
-                self.write_u8(*i)?;
-            }
-            Ok(())
+        let tr = self.begin_transaction()?;
+        let res = self.apply_batch(tr, &per_tree);
+        if res.is_err() {
+            self.t.remove(&tr);
+            return res;
         }
+        self.commit_transaction(tr)
+    }
 
-        fn write_u32(&self, v: u32) -> Result<()> {
-            let sl = unsafe { any_as_u8_slice(&v) };
-            for i in sl.iter() {
-                self.write_u8(*i)?;
+    /// loads records into an empty tree, building nodes bottom-up with one commit.
+    /// if the input is not sorted, it is sorted by an external merge sort.
+    pub fn bulk_load<I>(&mut self, tree_id: u32, items: I, params: &BulkLoadParams) -> Result<usize>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        self.check_tree_id(tree_id)?;
+        if self.indexes.contains_key(&tree_id) {
+            return Err(crate::Error::InvalidParams(format!(
+                "tree {} has indexes",
+                tree_id
+            )));
+        }
+        self.bulk_load_tree(tree_id, items, params)
+    }
+
+    /// `bulk_load` without checks of reserved and indexed trees, to copy trees with their
+    /// index trees or to copy and rebuild the change feed.
+    pub(crate) fn bulk_load_tree<I>(
+        &mut self,
+        tree_id: u32,
+        items: I,
+        params: &BulkLoadParams,
+    ) -> Result<usize>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        if !(params.fill_factor > 0.0 && params.fill_factor <= 1.0) || params.run_size == 0 {
+            return Err(crate::Error::InvalidParams(format!(
+                "fill_factor={} run_size={}",
+                params.fill_factor, params.run_size
+            )));
+        }
+        let key_cmp = self.get_key_cmp(tree_id)?;
+        if self.header.offset != 0 {
+            self.load_trees()?;
+        }
+        if let Some(exists) = self.tree_storages.get(&tree_id) {
+            if let Some(root) = exists.borrow().get_root() {
+                if !root.borrow().is_empty() {
+                    return Err(crate::Error::Fail(format!("tree {} is not empty", tree_id)));
+                }
             }
-            Ok(())
         }
 
-        fn write_u64(&self, v: u64) -> Result<()> {
-            let sl = unsafe { any_as_u8_slice(&v) };
-            for i in sl.iter() {
-                self.write_u8(*i)?;
+        let mut offsets = Vec::new();
+        {
+            let key_cmp = key_cmp.borrow();
+            let flat_store = self.store.borrow();
+            let mut sorter: Option<ExternalSorter> = None;
+            let mut prev_key: Option<Vec<u8>> = None;
+            // records in the sorter are written after the input ends.
+            let mut sorted_bytes = 0;
+            for (key, value) in items {
+                sorted_bytes += 2 * U32SZ + key.len() + value.len();
+                self.check_capacity(sorted_bytes)?;
+                if let Some(s) = sorter.as_mut() {
+                    s.push(key, value, &*key_cmp)?;
+                    continue;
+                }
+                if let Some(prev) = prev_key.as_ref() {
+                    if key_cmp.compare(prev, &key).is_gt() {
+                        let mut s = ExternalSorter::new(params.run_size);
+                        s.push(key, value, &*key_cmp)?;
+                        sorter = Some(s);
+                        continue;
+                    }
+                }
+                offsets.push(Self::write_kv(&*flat_store, &key, &value)?);
+                sorted_bytes = 0;
+                prev_key = Some(key);
+            }
+
+            if let Some(s) = sorter {
+                let runs = s.finish(&*key_cmp)?;
+                offsets = merge_runs(&*flat_store, &*key_cmp, offsets, runs)?;
             }
-            Ok(())
+            flat_store.flush()?;
         }
 
-        fn read_id(&self, seek: usize) -> Result<Id> {
-            let v = self.read_u32(seek)?;
-            Ok(Id(v))
+        let count = offsets.len();
+        let target = StorageNodeStorage::new(
+            0u32,
+            self.get_tree_cmp(tree_id)?,
+            self.store.clone(),
+            self.params.tree_params,
+        );
+        target
+            .borrow_mut()
+            .set_aggregate(self.get_tree_aggregate(tree_id));
+        let root = crate::tree::bulk::build(
+            &mut *target.borrow_mut(),
+            offsets.into_iter().map(|o| (o, Record::from_u32(o))),
+            params.fill_factor,
+        )?;
+        if root.is_none() {
+            return Ok(0);
         }
+        let mut trees = self.tree_storages.clone();
+        trees.insert(tree_id, target);
+        self.save_trees_with(trees)?;
+        Ok(count)
+    }
 
-        fn read_bool(&self, seek: usize) -> Result<bool> {
-            let v = self.read_u8(seek)?;
-            return Ok(if v == 1 { true } else { false });
+    fn apply_batch(
+        &mut self,
+        transaction: u64,
+        per_tree: &BTreeMap<u32, Vec<(usize, BatchOp)>>,
+    ) -> Result<()> {
+        // all records are written before the trees are changed, so only one flush is needed.
+        let mut kv_bytes = 0;
+        for ops in per_tree.values() {
+            for (_, op) in ops.iter() {
+                if let BatchOp::Put { key, value, .. } = op {
+                    kv_bytes += 2 * U32SZ + key.len() + value.len();
+                }
+            }
         }
+        self.check_capacity(kv_bytes)?;
 
-        fn read_u8(&self, seek: usize) -> Result<u8> {
-            Ok(self.space.borrow()[seek])
+        let mut kv_offsets = Vec::new();
+        {
+            let flat_store = self.store.borrow();
+            for ops in per_tree.values() {
+                for (_, op) in ops.iter() {
+                    if let BatchOp::Put { key, value, .. } = op {
+                        kv_offsets.push(Self::write_kv(&*flat_store, key, value)?);
+                    }
+                }
+            }
+            flat_store.flush()?;
         }
 
-        fn read_u16(&self, seek: usize) -> Result<u16> {
-            let readed = unsafe { (self.space.borrow().as_ptr().add(seek) as *const u16).read() };
-            Ok(readed)
+        let tparams = self.params.tree_params;
+        let mut kv_offsets = kv_offsets.into_iter();
+        // changes of each operation are kept by its number in the batch.
+        let mut changes: Vec<Vec<Change>> = vec![Vec::new(); per_tree.values().map(Vec::len).sum()];
+        for (tree_id, ops) in per_tree.iter() {
+            for (n, op) in ops.iter() {
+                match op {
+                    BatchOp::Put { key, value, .. } => {
+                        let key_offset = kv_offsets.next().unwrap();
+                        self.replace_indexed(transaction, *tree_id, key, value)?;
+                        self.insert_to_tree(transaction, *tree_id, key_offset, tparams)?;
+                        self.insert_to_indexes(transaction, *tree_id, key, value)?;
+                        self.record_change(transaction, *tree_id, key, Some(value));
+                    }
+                    BatchOp::Delete { key, .. } => {
+                        self.delete(transaction, *tree_id, key)?;
+                    }
+                    BatchOp::DeleteRange { from, to, .. } => {
+                        self.delete_range(transaction, *tree_id, from, to)?;
+                    }
+                }
+                if let Some(t) = self.t.get(&transaction) {
+                    changes[*n] = std::mem::take(&mut t.borrow_mut().changes);
+                }
+            }
         }
+        if let Some(t) = self.t.get(&transaction) {
+            t.borrow_mut().changes = changes.into_iter().flatten().collect();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        storage::{index::IndexDef, memory_storage::MemoryStorage},
+        utils::any_as_u8_slice,
+        Result,
+    };
 
-        fn read_u32(&self, seek: usize) -> Result<u32> {
-            let readed = unsafe { (self.space.borrow().as_ptr().add(seek) as *const u32).read() };
-            Ok(readed)
+    struct MockStorageKeyCmp {}
+
+    impl MockStorageKeyCmp {
+        fn new() -> MockStorageKeyCmp {
+            MockStorageKeyCmp {}
         }
+    }
 
-        fn read_u64(&self, seek: usize) -> Result<u64> {
-            let readed = unsafe { (self.space.borrow().as_ptr().add(seek) as *const u64).read() };
-            Ok(readed)
+    impl KeyCmp for MockStorageKeyCmp {
+        fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
+            key1.cmp(key2)
         }
     }
 
@@ -624,7 +2021,7 @@ mod tests {
         let cmp = Rc::new(RefCell::new(MockStorageKeyCmp::new()));
         all_cmp.insert(1u32, cmp);
 
-        let fstore = Rc::new(RefCell::new(MockPageStorage::new()));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
         let params = StorageParams::default();
         let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
         let max_key = 400;
@@ -707,7 +2104,7 @@ mod tests {
         let cmp = Rc::new(RefCell::new(MockStorageKeyCmp::new()));
         all_cmp.insert(1u32, cmp);
 
-        let fstore = Rc::new(RefCell::new(MockPageStorage::new()));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
         let params = StorageParams::default();
         let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
         let max_key = 400;
@@ -783,7 +2180,7 @@ mod tests {
         all_cmp.insert(1u32, cmp1);
         all_cmp.insert(2u32, cmp2);
 
-        let fstore = Rc::new(RefCell::new(MockPageStorage::new()));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
         let params = StorageParams::default();
         let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
         let max_key = 400;
@@ -853,6 +2250,7 @@ mod tests {
                 assert_eq!(value, key_sl)
             }
         }
+        assert!(storage.check()?.is_empty());
 
         let mut hdr = fstore.borrow().header_read()?;
         assert!(hdr.is_closed == 0);
@@ -869,7 +2267,7 @@ mod tests {
         let cmp = Rc::new(RefCell::new(MockStorageKeyCmp::new()));
         all_cmp.insert(1u32, cmp);
 
-        let fstore = Rc::new(RefCell::new(MockPageStorage::new()));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
         let params = StorageParams::default();
         let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
         let max_key = 100;
@@ -920,7 +2318,8 @@ mod tests {
             }
         }
 
-        let mut hdr = fstore.borrow().header_read()?;
+        // the rolled back records follow the last header.
+        let mut hdr = Storage::find_last_header(&*fstore.borrow())?.unwrap();
         assert!(hdr.is_closed == 0);
         storage.close()?;
         hdr = fstore.borrow().header_read()?;
@@ -928,4 +2327,1191 @@ mod tests {
         println!("size: {}kb", fstore.borrow().size() as f32 / 1024f32);
         Ok(())
     }
+
+    #[test]
+    fn db_write_batch() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let params = StorageParams::default();
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+
+        let max_key = 300u32;
+        let mut batch = WriteBatch::new();
+        for key in (0..max_key).rev() {
+            let tree_id = if key % 2 == 0 { 1 } else { 2 };
+            batch.put(tree_id, &key.to_be_bytes(), &key.to_le_bytes());
+        }
+        storage.write(batch)?;
+
+        for key in 0..max_key {
+            let tree_id = if key % 2 == 0 { 1 } else { 2 };
+            let find_res = storage.find(tree_id, &key.to_be_bytes())?;
+            assert!(find_res.is_some());
+            assert_eq!(&find_res.unwrap()[..], key.to_le_bytes());
+        }
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(1, &1000u32.to_be_bytes(), &1000u32.to_le_bytes())
+            .delete(1, &0u32.to_be_bytes())
+            .delete(2, &1u32.to_be_bytes())
+            .delete(2, &9999u32.to_be_bytes())
+            .delete_range(1, &100u32.to_be_bytes(), &200u32.to_be_bytes());
+        storage.write(batch)?;
+
+        for key in 0..max_key {
+            let tree_id = if key % 2 == 0 { 1 } else { 2 };
+            let removed = key <= 1 || (tree_id == 1 && (100..=200).contains(&key));
+            let find_res = storage.find(tree_id, &key.to_be_bytes())?;
+            assert_eq!(find_res.is_none(), removed);
+        }
+        assert!(storage.find(1, &1000u32.to_be_bytes())?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn db_write_batch_order() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let params = StorageParams::default();
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(1, &5u32.to_be_bytes(), &[1])
+            .put(1, &7u32.to_be_bytes(), &[1])
+            .delete_range(1, &0u32.to_be_bytes(), &6u32.to_be_bytes())
+            .put(1, &3u32.to_be_bytes(), &[2]);
+        storage.write(batch)?;
+
+        assert!(storage.find(1, &5u32.to_be_bytes())?.is_none());
+        assert!(storage.find(1, &7u32.to_be_bytes())?.is_some());
+        assert!(storage.find(1, &3u32.to_be_bytes())?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn db_write_batch_is_atomic() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let params = StorageParams::default();
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+
+        let mut batch = WriteBatch::new();
+        batch.put(1, &1u32.to_be_bytes(), &[1]);
+        storage.write(batch)?;
+        let hdr_before = fstore.borrow().header_read()?;
+
+        // tree 2 has no comparator.
+        let mut batch = WriteBatch::new();
+        batch
+            .put(1, &2u32.to_be_bytes(), &[2])
+            .put(2, &2u32.to_be_bytes(), &[2]);
+        assert!(storage.write(batch).is_err());
+
+        let hdr_after = fstore.borrow().header_read()?;
+        assert_eq!(hdr_before.offset, hdr_after.offset);
+        assert!(storage.find(1, &1u32.to_be_bytes())?.is_some());
+        assert!(storage.find(1, &2u32.to_be_bytes())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn db_stats() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+        let empty = storage.file_stats()?;
+        assert_eq!(empty.live_bytes, 0);
+        assert!(empty.trees.is_empty());
+        assert!(storage.stats(1).is_err());
+
+        // one commit: only the params and the headers are not live.
+        let items = (0..300u32).map(|k| (k.to_be_bytes().to_vec(), k.to_le_bytes().to_vec()));
+        storage.bulk_load(1, items, &BulkLoadParams::default())?;
+        let stats = storage.file_stats()?;
+        let overhead =
+            std::mem::size_of::<StorageParams>() + 2 * std::mem::size_of::<StorageHeader>();
+        assert_eq!(stats.garbage_bytes, overhead);
+        assert_eq!(stats.live_bytes + overhead, stats.total_bytes);
+        assert_eq!(stats.kv_bytes, 300 * 16);
+
+        let tree = &stats.trees[0];
+        assert_eq!(tree.keys, 300);
+        assert!(tree.height > 2);
+        assert_eq!(tree.fill.len(), tree.height);
+        assert!(tree.leafs < tree.nodes);
+        assert_eq!(tree.disk_nodes, tree.nodes);
+        assert_eq!(tree.transaction_bytes, 3 * U32SZ + tree.nodes * U32SZ);
+
+        for k in 300..400u32 {
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1, &k.to_be_bytes(), &k.to_le_bytes())?;
+            storage.commit_transaction(tr)?;
+        }
+        let stats = storage.file_stats()?;
+        assert!(stats.garbage_bytes > 0);
+        assert_eq!(stats.live_bytes + stats.garbage_bytes, stats.total_bytes);
+        assert_eq!(stats.kv_bytes, 400 * 16);
+
+        storage.close()?;
+        let mut storage = Storage::open(fstore.clone(), all_cmp)?;
+        let tree = storage.stats(1)?;
+        assert_eq!(tree.keys, 400);
+        assert_eq!(tree.cached_nodes, 1);
+        assert!(storage.check()?.is_empty());
+        assert_eq!(storage.file_stats()?.live_bytes, stats.live_bytes);
+        Ok(())
+    }
+
+    struct SumAggregate {}
+
+    impl Aggregate for SumAggregate {
+        fn summarize(&self, _key: &[u8], value: &[u8]) -> Vec<u8> {
+            value.to_vec()
+        }
+
+        fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+            let l = u64::from_le_bytes(left.try_into().unwrap());
+            let r = u64::from_le_bytes(right.try_into().unwrap());
+            (l + r).to_le_bytes().to_vec()
+        }
+    }
+
+    #[test]
+    fn db_aggregate_range() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let sum = Rc::new(RefCell::new(SumAggregate {}));
+        let mut storage =
+            Storage::new(fstore.clone(), &params, all_cmp.clone())?.with_aggregate(1, sum.clone());
+
+        let mut keys: Vec<u32> = (0..200u32).map(|i| (i * 37) % 200).collect();
+        for key in keys.iter() {
+            let tr = storage.begin_transaction()?;
+            let value = (*key as u64 * 10).to_le_bytes();
+            storage.insert(tr, 1, &key.to_be_bytes(), &value)?;
+            storage.insert(tr, 2, &key.to_be_bytes(), &value)?;
+            storage.commit_transaction(tr)?;
+        }
+        for key in keys.iter().filter(|k| *k % 3 == 0) {
+            storage.remove(1, &key.to_be_bytes())?;
+        }
+        keys.retain(|k| k % 3 != 0);
+        storage.close()?;
+
+        let mut storage = Storage::open(fstore.clone(), all_cmp)?.with_aggregate(1, sum);
+        for from in (0..210u32).step_by(13) {
+            for to in (from..210u32).step_by(11) {
+                let expected: u64 = keys
+                    .iter()
+                    .filter(|k| **k >= from && **k <= to)
+                    .map(|k| *k as u64 * 10)
+                    .sum();
+                let res = storage.aggregate_range(1, &from.to_be_bytes(), &to.to_be_bytes())?;
+                let value = match res {
+                    Some(v) => u64::from_le_bytes(v.try_into().unwrap()),
+                    None => 0,
+                };
+                assert_eq!(value, expected);
+            }
+        }
+        let res = storage.aggregate_range(2, &0u32.to_be_bytes(), &10u32.to_be_bytes());
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn db_order_stat() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+
+        // even keys, to query the gaps between them.
+        let mut keys: Vec<u32> = (0..200u32).map(|i| ((i * 37) % 200) * 2).collect();
+        for key in keys.iter() {
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1, &key.to_be_bytes(), &key.to_le_bytes())?;
+            storage.commit_transaction(tr)?;
+        }
+        keys.sort();
+        for key in keys.iter().filter(|k| *k % 3 == 0) {
+            storage.remove(1, &key.to_be_bytes())?;
+        }
+        keys.retain(|k| k % 3 != 0);
+        storage.close()?;
+
+        let mut storage = Storage::open(fstore.clone(), all_cmp)?;
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(storage.rank(1, &key.to_be_bytes())?, i);
+            assert_eq!(storage.rank(1, &(key + 1).to_be_bytes())?, i + 1);
+            let (k, v) = storage.select(1, i)?.unwrap();
+            assert_eq!(k, key.to_be_bytes());
+            assert_eq!(v, key.to_le_bytes());
+        }
+        assert!(storage.select(1, keys.len())?.is_none());
+        assert_eq!(storage.rank(2, &1u32.to_be_bytes())?, 0);
+
+        let from = keys[10];
+        let to = keys[keys.len() - 10];
+        let expected = keys.len() - 19;
+        assert_eq!(
+            storage.count_range(1, &from.to_be_bytes(), &to.to_be_bytes())?,
+            expected
+        );
+        let from = from - 1;
+        let to = to + 1;
+        assert_eq!(
+            storage.count_range(1, &from.to_be_bytes(), &to.to_be_bytes())?,
+            expected
+        );
+        assert_eq!(
+            storage.count_range(1, &to.to_be_bytes(), &from.to_be_bytes())?,
+            0
+        );
+        Ok(())
+    }
+
+    fn check_bulk_load(keys: Vec<u32>, run_size: usize) -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let params = StorageParams::default();
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+
+        let items = keys
+            .iter()
+            .map(|k| (k.to_be_bytes().to_vec(), k.to_le_bytes().to_vec()));
+        let load_params = BulkLoadParams::default().with_run_size(run_size);
+        let loaded = storage.bulk_load(1, items, &load_params)?;
+        assert_eq!(loaded, keys.len());
+
+        for key in keys.iter() {
+            let find_res = storage.find(1, &key.to_be_bytes())?;
+            assert_eq!(&find_res.unwrap()[..], key.to_le_bytes());
+        }
+
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &100000u32.to_be_bytes(), &[1])?;
+        storage.commit_transaction(tr)?;
+        assert!(storage.find(1, &100000u32.to_be_bytes())?.is_some());
+
+        for key in keys.iter() {
+            storage.remove(1, &key.to_be_bytes())?;
+            assert!(storage.find(1, &key.to_be_bytes())?.is_none());
+        }
+        assert!(storage.find(1, &100000u32.to_be_bytes())?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn db_bulk_load_sorted() -> Result<()> {
+        check_bulk_load((0..2000).collect(), 100)
+    }
+
+    #[test]
+    fn db_bulk_load_unsorted() -> Result<()> {
+        let keys = (0..2000u32).map(|i| (i * 7919) % 2000).collect();
+        check_bulk_load(keys, 64)
+    }
+
+    #[test]
+    fn db_bulk_load_not_empty() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let params = StorageParams::default();
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp)?;
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[1], &[1])?;
+        storage.commit_transaction(tr)?;
+
+        let items = vec![(vec![2u8], vec![2u8])];
+        assert!(storage
+            .bulk_load(1, items, &BulkLoadParams::default())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn db_open_old_version() -> Result<()> {
+        // version 1: the tree params are followed by the first header, nodes have no
+        // counts and aggregates.
+        let tp = TreeParams::default_with_t(3);
+        let header = StorageHeader {
+            magic: MAGIC_HEADER,
+            offset: 0,
+            is_closed: 0,
+        };
+        let mut bytes = unsafe { any_as_u8_slice(&tp) }.to_vec();
+        bytes.extend_from_slice(unsafe { any_as_u8_slice(&header) });
+        let v1 = MemoryStorage::from_bytes(bytes.clone());
+        Storage::insert_kv(&v1, b"key", b"value")?;
+        v1.header_write(&header)?;
+        let err = Storage::open(Rc::new(RefCell::new(v1)), HashMap::new()).err();
+        assert!(matches!(err, Some(crate::Error::UnsupportedVersion(1))));
+        assert!(matches!(
+            crate::tools::inspect(&bytes),
+            Err(crate::Error::UnsupportedVersion(1))
+        ));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        Storage::new(fstore.clone(), &StorageParams::default(), HashMap::new())?;
+        let mut params = fstore.borrow().params_read()?;
+        assert_eq!(params.format_version(), FORMAT_VERSION);
+        params.format_version = FORMAT_VERSION + 1;
+        let next = MemoryStorage::new();
+        next.params_write(&params)?;
+        next.header_write(&fstore.borrow().header_read()?)?;
+        let err = Storage::open(Rc::new(RefCell::new(next)), HashMap::new()).err();
+        assert!(
+            matches!(err, Some(crate::Error::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1)
+        );
+
+        let garbage = MemoryStorage::from_bytes(vec![0xff; 64]);
+        let err = Storage::open(Rc::new(RefCell::new(garbage)), HashMap::new()).err();
+        assert!(matches!(err, Some(crate::Error::Corrupted(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn db_scans_take_cmp_error() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut storage = Storage::new(fstore, &StorageParams::default(), all_cmp)?
+            .with_aggregate(1, Rc::new(RefCell::new(SumAggregate {})));
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[1], &1u64.to_le_bytes())?;
+        storage.commit_transaction(tr)?;
+
+        let fail = |s: &Storage| {
+            s.cmp_error
+                .borrow_mut()
+                .replace(crate::Error::Corrupted("cmp".to_owned()));
+        };
+        fail(&storage);
+        assert!(storage.scan_prefix(1, &[]).is_err());
+        fail(&storage);
+        assert!(storage.count_prefix(1, &[]).is_err());
+        fail(&storage);
+        assert!(storage.get_all(1, &[1]).is_err());
+        fail(&storage);
+        assert!(storage.for_each(1, |_, _| Ok(())).is_err());
+        fail(&storage);
+        assert!(storage.aggregate_range(1, &[0], &[2]).is_err());
+        // the error is not left for the next call.
+        assert_eq!(storage.scan_prefix(1, &[])?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn db_corrupted_node() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+        let tr = storage.begin_transaction()?;
+        for k in 0..100u32 {
+            storage.insert(tr, 1, &k.to_be_bytes(), &[1])?;
+        }
+        storage.commit_transaction(tr)?;
+
+        // list -> tree block -> the root node.
+        let store = fstore.borrow();
+        let list = store.header_read()?.offset as usize;
+        let tree = store.read_u32(list + 2 * U32SZ)? as usize;
+        let root = store.read_u32(tree + 3 * U32SZ)? as usize;
+        let keys_count = root + U32SZ + U8SZ + 3 * U32SZ;
+        for (pos, value) in [(keys_count, 0xffff_0000u32), (keys_count + U32SZ, 7)] {
+            let mut bytes = store.to_bytes();
+            bytes[pos..pos + U32SZ].copy_from_slice(&value.to_ne_bytes());
+            let broken = Rc::new(RefCell::new(MemoryStorage::from_bytes(bytes)));
+            let mut storage = Storage::open(broken, all_cmp.clone())?;
+            assert!(matches!(
+                storage.find(1, &5u32.to_be_bytes()),
+                Err(crate::Error::Corrupted(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn db_errors() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(1);
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        assert!(matches!(
+            Storage::new(fstore, &params, all_cmp.clone()),
+            Err(crate::Error::InvalidParams(_))
+        ));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+        assert!(storage.find(1, &[1])?.is_none());
+        assert!(matches!(
+            storage.insert(100, 1, &[1], &[1]),
+            Err(crate::Error::TransactionNotFound)
+        ));
+        let tr = storage.begin_transaction()?;
+        assert!(matches!(
+            storage.insert(tr, 2, &[1], &[1]),
+            Err(crate::Error::MissingComparator(2))
+        ));
+        storage.insert(tr, 1, &[1], &[1])?;
+        storage.commit_transaction(tr)?;
+        assert!(matches!(
+            storage.stats(2),
+            Err(crate::Error::UnknownTree(2))
+        ));
+        assert!(matches!(
+            storage.bulk_load(
+                3,
+                Vec::new(),
+                &BulkLoadParams::default().with_fill_factor(0.0)
+            ),
+            Err(crate::Error::InvalidParams(_))
+        ));
+
+        // the tree list must point to a transaction list.
+        let mut hdr = fstore.borrow().header_read()?;
+        hdr.offset = fstore.borrow().size() as u32 - 4 * U32SZ as u32;
+        fstore.borrow().header_write(&hdr)?;
+        assert!(matches!(
+            Storage::open(fstore.clone(), all_cmp.clone())?.find(1, &[1]),
+            Err(crate::Error::Corrupted(_))
+        ));
+
+        // without a whole header before the bad one.
+        hdr.magic = 0;
+        let broken = MemoryStorage::new();
+        broken.params_write(&fstore.borrow().params_read()?)?;
+        broken.header_write(&hdr)?;
+        let err = Storage::open(Rc::new(RefCell::new(broken)), all_cmp)
+            .err()
+            .unwrap();
+        assert!(matches!(err, crate::Error::Corrupted(_)));
+        assert!(std::error::Error::source(&err).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn db_max_file_size_of_old_params() -> Result<()> {
+        // in version 1 the bytes after the tree params are the header, not a size limit.
+        let header = StorageHeader {
+            magic: MAGIC_HEADER,
+            offset: 0,
+            is_closed: 0,
+        };
+        let mut bytes = unsafe { any_as_u8_slice(&TreeParams::default_with_t(3)) }.to_vec();
+        bytes.extend_from_slice(unsafe { any_as_u8_slice(&header) });
+        bytes.extend_from_slice(&[0xff; 64]);
+        let params = StorageParams::from_bytes(&bytes)?;
+        assert_eq!(params.format_version(), 1);
+        assert_eq!(params.max_file_size, 0);
+        assert_eq!(params.tree_params.t, 3);
+
+        let fstore = MemoryStorage::new();
+        fstore.params_write(&StorageParams::default().with_max_file_size(1 << 20))?;
+        let params = StorageParams::from_bytes(&fstore.to_bytes())?;
+        assert_eq!(params.format_version(), FORMAT_VERSION);
+        assert_eq!(params.max_file_size, 1 << 20);
+        Ok(())
+    }
+
+    #[test]
+    fn db_max_file_size() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default().with_max_file_size(16 * 1024);
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+
+        // large values fail on insert, small ones - on commit.
+        let mut committed = Vec::new();
+        let mut full = 0;
+        for key in 0..10000u32 {
+            let value = vec![1u8; if key % 2 == 0 { 8 } else { 100 }];
+            let tr = storage.begin_transaction()?;
+            let res = storage.insert(tr, 1, &key.to_be_bytes(), &value);
+            let res = res.and_then(|_| storage.commit_transaction(tr));
+            match res {
+                Ok(()) => committed.push(key),
+                Err(crate::Error::IsFull) => {
+                    storage.rollback_transaction(tr)?;
+                    full += 1;
+                    if full == 10 {
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        assert_eq!(full, 10);
+        assert!(fstore.borrow().size() as u64 <= params.max_file_size);
+
+        let capacity = storage.capacity()?;
+        assert_eq!(capacity.max_file_size, params.max_file_size);
+        assert_eq!(capacity.used_bytes, fstore.borrow().size());
+        assert!(capacity.remaining_bytes.unwrap() < 1024);
+        assert!(capacity.reclaimable_bytes > 0);
+
+        let mut storage = Storage::open(fstore.clone(), all_cmp)?;
+        assert_eq!(storage.capacity()?.max_file_size, params.max_file_size);
+        for key in committed.iter() {
+            assert!(storage.find(1, &key.to_be_bytes())?.is_some());
+        }
+        assert_eq!(storage.stats(1)?.keys, committed.len());
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn db_delete_range() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(4);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+        let tr = storage.begin_transaction()?;
+        for key in 0..1000u32 {
+            storage.insert(tr, 1, &key.to_be_bytes(), &key.to_le_bytes())?;
+        }
+        storage.commit_transaction(tr)?;
+
+        let tr = storage.begin_transaction()?;
+        let from = 100u32.to_be_bytes();
+        let to = 700u32.to_be_bytes();
+        assert_eq!(storage.delete_range(tr, 1, &from, &to)?, 601);
+        assert_eq!(storage.delete_range(tr, 1, &from, &to)?, 0);
+        storage.rollback_transaction(tr)?;
+        assert_eq!(storage.count_range(1, &from, &to)?, 601);
+
+        let tr = storage.begin_transaction()?;
+        assert_eq!(storage.delete_range(tr, 1, &from, &to)?, 601);
+        assert_eq!(
+            storage.delete_range(tr, 1, &950u32.to_be_bytes(), &[0xff; 4])?,
+            50
+        );
+        storage.commit_transaction(tr)?;
+
+        let mut storage = Storage::open(fstore, all_cmp)?;
+        for key in 0..1000u32 {
+            let expected = key < 100 || (key > 700 && key < 950);
+            assert_eq!(storage.find(1, &key.to_be_bytes())?.is_some(), expected);
+        }
+        assert_eq!(storage.stats(1)?.keys, 100 + 249);
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn db_neighbour_lookups() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore, &params, all_cmp)?;
+        assert!(storage.first(1)?.is_none());
+        assert!(storage.floor(1, &[5])?.is_none());
+
+        let tr = storage.begin_transaction()?;
+        for key in (10..=100u8).step_by(10) {
+            storage.insert(tr, 1, &[key], &[key + 1])?;
+        }
+        storage.commit_transaction(tr)?;
+
+        let key_of = |r: Option<(Vec<u8>, Vec<u8>)>| {
+            r.map(|(k, v)| {
+                assert_eq!(v[0], k[0] + 1);
+                k[0]
+            })
+        };
+        assert_eq!(key_of(storage.first(1)?), Some(10));
+        assert_eq!(key_of(storage.last(1)?), Some(100));
+        assert_eq!(key_of(storage.floor(1, &[35])?), Some(30));
+        assert_eq!(key_of(storage.floor(1, &[30])?), Some(30));
+        assert_eq!(key_of(storage.floor(1, &[5])?), None);
+        assert_eq!(key_of(storage.lower(1, &[30])?), Some(20));
+        assert_eq!(key_of(storage.lower(1, &[10])?), None);
+        assert_eq!(key_of(storage.ceiling(1, &[35])?), Some(40));
+        assert_eq!(key_of(storage.ceiling(1, &[40])?), Some(40));
+        assert_eq!(key_of(storage.ceiling(1, &[101])?), None);
+        assert_eq!(key_of(storage.higher(1, &[40])?), Some(50));
+        assert_eq!(key_of(storage.higher(1, &[100])?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn db_scan_range() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore, &params, all_cmp)?;
+        let tr = storage.begin_transaction()?;
+        for key in 0..200u32 {
+            storage.insert(tr, 1, &(key * 2).to_be_bytes(), &key.to_le_bytes())?;
+        }
+        storage.commit_transaction(tr)?;
+
+        let mut scan = |from: Option<u32>, to: Option<u32>, limit: usize| -> Result<Vec<u32>> {
+            let mut result = Vec::new();
+            let from = from.map(|k| k.to_be_bytes());
+            let to = to.map(|k| k.to_be_bytes());
+            storage.scan_range(
+                1,
+                from.as_ref().map(|k| &k[..]),
+                to.as_ref().map(|k| &k[..]),
+                |key, _| {
+                    result.push(u32::from_be_bytes(key.try_into().unwrap()));
+                    Ok(result.len() < limit)
+                },
+            )?;
+            Ok(result)
+        };
+        assert_eq!(scan(None, None, usize::MAX)?.len(), 200);
+        assert_eq!(
+            scan(Some(101), Some(110), usize::MAX)?,
+            [102, 104, 106, 108, 110]
+        );
+        assert_eq!(
+            scan(Some(390), None, usize::MAX)?,
+            [390, 392, 394, 396, 398]
+        );
+        assert_eq!(scan(None, Some(5), usize::MAX)?, [0, 2, 4]);
+        assert_eq!(scan(Some(100), None, 3)?, [100, 102, 104]);
+        assert!(scan(Some(7), Some(5), usize::MAX)?.is_empty());
+
+        let res = storage.scan_range(1, None, None, |_, _| {
+            Err(crate::Error::Fail("stop".to_owned()))
+        });
+        assert!(matches!(res, Err(crate::Error::Fail(_))));
+        Ok(())
+    }
+
+    struct CaseInsensitiveCmp {}
+
+    impl KeyCmp for CaseInsensitiveCmp {
+        fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
+            key1.to_ascii_lowercase().cmp(&key2.to_ascii_lowercase())
+        }
+
+        fn has_prefix(&self, key: &[u8], prefix: &[u8]) -> bool {
+            key.len() >= prefix.len() && key[..prefix.len()].eq_ignore_ascii_case(prefix)
+        }
+    }
+
+    #[test]
+    fn db_prefix() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(
+            1u32,
+            Rc::new(RefCell::new(crate::storage::BytewiseKeyCmp {})),
+        );
+        all_cmp.insert(2u32, Rc::new(RefCell::new(CaseInsensitiveCmp {})));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(fstore.clone(), &params, all_cmp.clone())?;
+
+        let mut keys = Vec::new();
+        for tenant in 0..5 {
+            for user in 0..6 {
+                for item in 0..7 {
+                    keys.push(format!("t{}/u{}/i{}", tenant, user, item).into_bytes());
+                }
+            }
+        }
+        keys.push(vec![0xff, 0xff]);
+        keys.push(vec![0xff, 0xff, 1]);
+        let tr = storage.begin_transaction()?;
+        for k in keys.iter().rev() {
+            storage.insert(tr, 1, k, &[1])?;
+            storage.insert(tr, 2, &k.to_ascii_uppercase(), &[2])?;
+        }
+        storage.commit_transaction(tr)?;
+        keys.sort();
+
+        let prefixes: Vec<&[u8]> = vec![b"t1/", b"t2/u3/", b"t4/u5/i6", b"t9", b"", &[0xff, 0xff]];
+        for prefix in prefixes.iter() {
+            let expected: Vec<&Vec<u8>> = keys.iter().filter(|k| k.starts_with(prefix)).collect();
+            let found = storage.scan_prefix(1, prefix)?;
+            assert_eq!(found.iter().map(|kv| &kv.0).collect::<Vec<_>>(), expected);
+            assert_eq!(storage.count_prefix(1, prefix)?, expected.len());
+
+            let lower = prefix.to_ascii_lowercase();
+            let found = storage.scan_prefix(2, &lower)?;
+            assert_eq!(found.len(), expected.len());
+            assert!(found
+                .iter()
+                .all(|(k, v)| k.starts_with(&prefix.to_ascii_uppercase()) && v[0] == 2));
+            assert_eq!(storage.count_prefix(2, &lower)?, expected.len());
+        }
+
+        let tr = storage.begin_transaction()?;
+        assert_eq!(storage.delete_prefix(tr, 1, b"t2/")?, 42);
+        assert_eq!(storage.delete_prefix(tr, 2, b"t3/u1")?, 7);
+        storage.commit_transaction(tr)?;
+
+        let mut storage = Storage::open(fstore, all_cmp)?;
+        assert_eq!(storage.count_prefix(1, b"t2/")?, 0);
+        assert_eq!(storage.count_prefix(1, b"t")?, 4 * 42);
+        assert_eq!(storage.count_prefix(2, b"T3/")?, 35);
+        assert!(storage.scan_prefix(2, b"t3/u1/")?.is_empty());
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    fn users_with_indexes(storage: Storage) -> Storage {
+        // value: [city][email...]
+        storage
+            .with_index(1, IndexDef::new(10, |v| v.first().map(|c| vec![*c])))
+            .with_index(
+                1,
+                IndexDef::new(11, |v| Some(v[1..].to_vec())).with_unique(true),
+            )
+    }
+
+    fn user(city: u8, email: &str) -> Vec<u8> {
+        let mut result = vec![city];
+        result.extend_from_slice(email.as_bytes());
+        result
+    }
+
+    fn city_users(storage: &mut Storage, city: u8) -> Result<Vec<u32>> {
+        let rows = storage.find_by_index(10, &[city])?;
+        for (_, value) in rows.iter() {
+            assert_eq!(value[0], city);
+        }
+        Ok(rows
+            .iter()
+            .map(|(k, _)| u32::from_be_bytes(k[..].try_into().unwrap()))
+            .collect())
+    }
+
+    #[test]
+    fn db_index() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage =
+            users_with_indexes(Storage::new(fstore.clone(), &params, all_cmp.clone())?);
+
+        let tr = storage.begin_transaction()?;
+        for id in 0..100u32 {
+            let email = format!("user{}@mail", id);
+            storage.insert(tr, 1, &id.to_be_bytes(), &user((id % 7) as u8, &email))?;
+        }
+        storage.commit_transaction(tr)?;
+        let expected: Vec<u32> = (0..100).filter(|id| id % 7 == 3).collect();
+        assert_eq!(city_users(&mut storage, 3)?, expected);
+        let found = storage.find_by_index(11, b"user42@mail")?;
+        assert_eq!(
+            found,
+            vec![(42u32.to_be_bytes().to_vec(), user(0, "user42@mail"))]
+        );
+        assert!(storage.find_by_index(11, b"nobody")?.is_empty());
+        assert!(matches!(
+            storage.find_by_index(12, b""),
+            Err(crate::Error::UnknownTree(12))
+        ));
+
+        // the email of another user.
+        let tr = storage.begin_transaction()?;
+        let err = storage.insert(tr, 1, &500u32.to_be_bytes(), &user(1, "user5@mail"));
+        assert!(matches!(err, Err(crate::Error::UniqueViolation(11))));
+        // the same user keeps its email and moves to another city.
+        storage.insert(tr, 1, &5u32.to_be_bytes(), &user(3, "user5@mail"))?;
+        assert!(storage.delete(tr, 1, &10u32.to_be_bytes())?);
+        assert!(!storage.delete(tr, 1, &1000u32.to_be_bytes())?);
+        assert_eq!(
+            storage.delete_range(tr, 1, &20u32.to_be_bytes(), &39u32.to_be_bytes())?,
+            20
+        );
+        storage.commit_transaction(tr)?;
+        storage.remove(1, &24u32.to_be_bytes())?;
+        storage.remove(1, &45u32.to_be_bytes())?;
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put(1, &200u32.to_be_bytes(), &user(3, "user10@mail"))
+            .delete(1, &52u32.to_be_bytes());
+        storage.write(batch)?;
+
+        let mut storage = users_with_indexes(Storage::open(fstore, all_cmp)?);
+        let mut expected: Vec<u32> = (0..100)
+            .filter(|id| id % 7 == 3 && !(20..=39).contains(id) && ![10, 45, 52].contains(id))
+            .collect();
+        expected.extend([5, 200]);
+        expected.sort();
+        assert_eq!(city_users(&mut storage, 3)?, expected);
+        assert!(!city_users(&mut storage, 5)?.contains(&5));
+        assert!(storage.find_by_index(11, b"user24@mail")?.is_empty());
+        assert!(storage.find_by_index(11, b"user52@mail")?.is_empty());
+        assert_eq!(storage.find_by_index(11, b"user10@mail")?.len(), 1);
+
+        let mut total = 0;
+        for city in 0..7 {
+            total += city_users(&mut storage, city)?.len();
+        }
+        assert_eq!(total, 100 - 1 - 20 - 1 - 1 + 1);
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn db_duplicates() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage =
+            Storage::new(fstore.clone(), &params, all_cmp.clone())?.with_duplicates(1);
+
+        let mut expected: Vec<Vec<Vec<u8>>> = vec![Vec::new(); 20];
+        for round in 0..5u8 {
+            let tr = storage.begin_transaction()?;
+            for key in (0..20u32).rev() {
+                // values go in the reverse order, so the insertion order differs from it.
+                let value = vec![10 - round, key as u8];
+                storage.insert(tr, 1, &key.to_be_bytes(), &value)?;
+                expected[key as usize].push(value);
+            }
+            if round % 2 == 0 {
+                storage.insert(tr, 1, &7u32.to_be_bytes(), &[0])?;
+                expected[7].push(vec![0]);
+            }
+            storage.commit_transaction(tr)?;
+        }
+        for key in 0..20u32 {
+            assert_eq!(
+                storage.get_all(1, &key.to_be_bytes())?,
+                expected[key as usize]
+            );
+            assert_eq!(
+                storage.count(1, &key.to_be_bytes())?,
+                expected[key as usize].len()
+            );
+        }
+        assert!(storage.get_all(1, &100u32.to_be_bytes())?.is_empty());
+
+        let tr = storage.begin_transaction()?;
+        let key = 7u32.to_be_bytes();
+        assert!(storage.remove_one(tr, 1, &key, &[8, 7])?);
+        assert!(!storage.remove_one(tr, 1, &key, &[8, 7])?);
+        assert!(storage.remove_one(tr, 1, &key, &[0])?);
+        assert_eq!(storage.remove_all(tr, 1, &5u32.to_be_bytes())?, 5);
+        assert!(matches!(
+            storage.remove_one(tr, 2, &key, &[0]),
+            Err(crate::Error::InvalidParams(_))
+        ));
+        storage.commit_transaction(tr)?;
+        expected[7].retain(|v| v[..] != [8, 7]);
+        let first_zero = expected[7].iter().position(|v| v[..] == [0]).unwrap();
+        expected[7].remove(first_zero);
+        expected[5].clear();
+
+        let mut storage = Storage::open(fstore, all_cmp)?.with_duplicates(1);
+        for key in 0..20u32 {
+            assert_eq!(
+                storage.get_all(1, &key.to_be_bytes())?,
+                expected[key as usize]
+            );
+        }
+        assert_eq!(storage.count(1, &7u32.to_be_bytes())?, 6);
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    struct CommitLog {
+        commits: Vec<Commit>,
+    }
+
+    impl CommitObserver for CommitLog {
+        fn on_commit(&mut self, commit: &Commit) {
+            self.commits.push(commit.clone());
+        }
+    }
+
+    fn put(tree_id: u32, key: &[u8], value: &[u8]) -> Change {
+        Change {
+            tree_id,
+            key: key.to_vec(),
+            op: ChangeOp::Put,
+            value: Some(value.to_vec()),
+        }
+    }
+
+    fn del(tree_id: u32, key: &[u8]) -> Change {
+        Change {
+            tree_id,
+            key: key.to_vec(),
+            op: ChangeOp::Delete,
+            value: None,
+        }
+    }
+
+    #[test]
+    fn db_change_feed() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage =
+            Storage::new(fstore.clone(), &params, all_cmp.clone())?.with_change_feed();
+        let log = Rc::new(RefCell::new(CommitLog {
+            commits: Vec::new(),
+        }));
+        storage.subscribe(log.clone());
+        assert_eq!(storage.last_commit_seq()?, 0);
+
+        let tr = storage.begin_transaction()?;
+        for key in 0..10u8 {
+            storage.insert(tr, 1, &[key], &[key, 1])?;
+        }
+        storage.insert(tr, 2, b"x", b"y")?;
+        storage.commit_transaction(tr)?;
+
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[100], &[0])?;
+        storage.rollback_transaction(tr)?;
+        let tr = storage.begin_transaction()?;
+        storage.commit_transaction(tr)?;
+
+        let tr = storage.begin_transaction()?;
+        assert!(storage.delete(tr, 1, &[0])?);
+        assert!(!storage.delete(tr, 1, &[50])?);
+        assert_eq!(storage.delete_range(tr, 1, &[3], &[5])?, 3);
+        storage.commit_transaction(tr)?;
+        storage.remove(2, b"x")?;
+        let mut batch = WriteBatch::new();
+        batch.put(1, &[20], &[2]).delete(1, &[9]);
+        storage.write(batch)?;
+
+        let mut expected = vec![Commit {
+            seq: 1,
+            changes: (0..10u8).map(|k| put(1, &[k], &[k, 1])).collect(),
+        }];
+        expected[0].changes.push(put(2, b"x", b"y"));
+        expected.push(Commit {
+            seq: 2,
+            changes: vec![del(1, &[0]), del(1, &[3]), del(1, &[4]), del(1, &[5])],
+        });
+        expected.push(Commit {
+            seq: 3,
+            changes: vec![del(2, b"x")],
+        });
+        expected.push(Commit {
+            seq: 4,
+            changes: vec![put(1, &[20], &[2]), del(1, &[9])],
+        });
+        assert_eq!(log.borrow().commits, expected);
+        assert_eq!(storage.changes_after(0)?, expected);
+
+        // consumers resume from the last seen commit after a restart.
+        let mut storage = Storage::open(fstore, all_cmp)?.with_change_feed();
+        assert_eq!(storage.last_commit_seq()?, 4);
+        assert_eq!(storage.changes_after(2)?, expected[2..].to_vec());
+        assert!(storage.changes_after(4)?.is_empty());
+
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[30], &[3])?;
+        storage.commit_transaction(tr)?;
+        let last = storage.changes_after(4)?;
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].seq, 5);
+
+        assert_eq!(storage.trim_changes(3)?, 16);
+        assert_eq!(storage.changes_after(0)?[0].seq, 4);
+        assert_eq!(storage.last_commit_seq()?, 5);
+        assert_eq!(storage.find(1, &[1])?, Some(vec![1, 1]));
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn db_write_batch_changes() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut storage =
+            Storage::new(fstore, &StorageParams::default(), all_cmp)?.with_change_feed();
+        let log = Rc::new(RefCell::new(CommitLog {
+            commits: Vec::new(),
+        }));
+        storage.subscribe(log.clone());
+        let mut batch = WriteBatch::new();
+        batch.put(1, &[3], &[0]).put(1, &[4], &[0]);
+        storage.write(batch)?;
+
+        // the batch is applied in the key order of each tree, but reported in its own order.
+        let mut batch = WriteBatch::new();
+        batch
+            .put(2, &[9], &[1])
+            .put(1, &[8], &[1])
+            .delete(1, &[4])
+            .delete(1, &[7])
+            .put(1, &[1], &[1])
+            .delete_range(1, &[0], &[3])
+            .put(1, &[2], &[2])
+            .put(2, &[5], &[1]);
+        storage.write(batch)?;
+        let expected = Commit {
+            seq: 2,
+            changes: vec![
+                put(2, &[9], &[1]),
+                put(1, &[8], &[1]),
+                del(1, &[4]),
+                put(1, &[1], &[1]),
+                del(1, &[1]),
+                del(1, &[3]),
+                put(1, &[2], &[2]),
+                put(2, &[5], &[1]),
+            ],
+        };
+        assert_eq!(log.borrow().commits.last(), Some(&expected));
+        assert_eq!(storage.changes_after(1)?, vec![expected]);
+        assert_eq!(storage.find(1, &[2])?, Some(vec![2]));
+        assert_eq!(storage.find(1, &[3])?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn db_trim_all_changes() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage =
+            Storage::new(fstore.clone(), &params, all_cmp.clone())?.with_change_feed();
+        for key in 0..5u8 {
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1, &[key], &[key])?;
+            storage.commit_transaction(tr)?;
+        }
+        assert_eq!(storage.trim_changes(5)?, 5);
+        assert!(storage.changes_after(0)?.is_empty());
+        assert_eq!(storage.trim_changes(5)?, 0);
+
+        // the sequence number goes on after a reopen.
+        let mut storage = Storage::open(fstore.clone(), all_cmp.clone())?.with_change_feed();
+        assert_eq!(storage.last_commit_seq()?, 5);
+        assert!(storage.changes_after(0)?.is_empty());
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[5], &[5])?;
+        storage.commit_transaction(tr)?;
+        assert_eq!(
+            storage.changes_after(5)?,
+            vec![Commit {
+                seq: 6,
+                changes: vec![put(1, &[5], &[5])],
+            }]
+        );
+
+        // the old marker is not counted as a change.
+        assert_eq!(storage.trim_changes(10)?, 1);
+        let mut storage = Storage::open(fstore, all_cmp)?.with_change_feed();
+        assert_eq!(storage.last_commit_seq()?, 6);
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[6], &[6])?;
+        storage.commit_transaction(tr)?;
+        assert_eq!(storage.changes_after(0)?[0].seq, 7);
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn db_reserved_tree_id() -> Result<()> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+        all_cmp.insert(2u32, Rc::new(RefCell::new(MockStorageKeyCmp::new())));
+
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut storage = Storage::new(fstore, &StorageParams::default(), all_cmp)?
+            .with_change_feed()
+            .with_index(2, IndexDef::new(CHANGES_TREE_ID, |v| Some(v.to_vec())));
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, &[1], &[1])?;
+        storage.commit_transaction(tr)?;
+
+        let reserved = |r: Result<usize>| matches!(r, Err(crate::Error::InvalidParams(_)));
+        let tr = storage.begin_transaction()?;
+        let id = CHANGES_TREE_ID;
+        assert!(reserved(storage.insert(tr, id, &[1], &[1]).map(|_| 0)));
+        assert!(reserved(storage.insert(tr, 2, &[1], &[1]).map(|_| 0)));
+        assert!(reserved(storage.delete(tr, id, &[1]).map(|_| 0)));
+        assert!(reserved(storage.delete_range(tr, id, &[0], &[0xff])));
+        assert!(reserved(storage.delete_prefix(tr, id, &[])));
+        assert!(reserved(storage.remove_all(tr, id, &[1])));
+        storage.commit_transaction(tr)?;
+        assert!(reserved(storage.remove(id, &[1]).map(|_| 0)));
+        let mut batch = WriteBatch::new();
+        batch.put(1, &[2], &[2]).put(id, &[1], &[1]);
+        assert!(reserved(storage.write(batch).map(|_| 0)));
+        let items = vec![(vec![1], vec![1])];
+        assert!(reserved(storage.bulk_load(
+            id,
+            items,
+            &BulkLoadParams::default()
+        )));
+
+        assert_eq!(storage.tree_ids()?, [1]);
+        assert!(!storage.is_index_tree(id));
+        assert_eq!(storage.find(1, &[2])?, None);
+        assert_eq!(storage.last_commit_seq()?, 1);
+        assert_eq!(storage.changes_after(0)?.len(), 1);
+        assert!(storage.check()?.is_empty());
+        Ok(())
+    }
 }
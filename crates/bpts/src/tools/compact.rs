@@ -0,0 +1,227 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    storage::{
+        bulk_load::BulkLoadParams, changes::CHANGES_TREE_ID, flat_storage::FlatStorage,
+        store::Storage, KeyCmp,
+    },
+    Result,
+};
+
+/// copies live records of all trees to a new storage with the same params.
+/// old versions of nodes and removed records are left behind. empty trees are not copied.
+/// aggregates, indexes and duplicates of the source are set in the result, index trees
+/// and the change feed are copied as is.
+/// comparators of trees are found by names in `comparators`.
+/// records of one tree are held in memory while it is copied.
+pub fn compact(
+    storage: &mut Storage,
+    store: Rc<RefCell<dyn FlatStorage>>,
+    comparators: &HashMap<String, Rc<RefCell<dyn KeyCmp>>>,
+) -> Result<Storage> {
+    let mut result =
+        Storage::new(store, &storage.params(), HashMap::new())?.with_config_of(storage);
+    let tree_ids = storage.tree_ids()?;
+    // comparators of index trees are made of comparators of their primary trees.
+    for tree_id in tree_ids.iter().copied() {
+        if storage.is_index_tree(tree_id) {
+            continue;
+        }
+        let name = storage.cmp_name(tree_id)?;
+        let cmp = match comparators.get(&name) {
+            Some(c) => c.clone(),
+            None => return Err(crate::Error::NotFound(format!("comparator '{}'", name))),
+        };
+        result = result.with_cmp(tree_id, cmp);
+    }
+
+    let load_params = BulkLoadParams::default();
+    let mut copy_ids = tree_ids;
+    if storage.has_changes()? {
+        copy_ids.push(CHANGES_TREE_ID);
+    }
+    for tree_id in copy_ids {
+        let mut records = Vec::new();
+        storage.for_each(tree_id, |key, value| {
+            records.push((key.to_vec(), value.to_vec()));
+            Ok(())
+        })?;
+        result.bulk_load_tree(tree_id, records, &load_params)?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{index::IndexDef, memory_storage::MemoryStorage, Aggregate, StorageParams},
+        tools::builtin_comparators,
+    };
+
+    #[test]
+    fn compact_file() -> Result<()> {
+        let comparators = builtin_comparators();
+        let mut source = Storage::new(
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_default_cmp(comparators["bytewise"].clone());
+        for i in 0..50u32 {
+            let tr = source.begin_transaction()?;
+            for j in 0..20u32 {
+                source.insert(tr, 1 + j % 2, &(i * 20 + j).to_be_bytes(), b"value")?;
+            }
+            if i > 0 {
+                source.delete(tr, 1, &((i - 1) * 20).to_be_bytes())?;
+            }
+            source.commit_transaction(tr)?;
+        }
+        let tr = source.begin_transaction()?;
+        source.insert(tr, 3, b"empty", b"")?;
+        source.commit_transaction(tr)?;
+        let tr = source.begin_transaction()?;
+        source.delete(tr, 3, b"empty")?;
+        source.commit_transaction(tr)?;
+
+        let mut target = compact(
+            &mut source,
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &comparators,
+        )?;
+        assert!(target.file_stats()?.total_bytes * 4 < source.file_stats()?.total_bytes);
+        assert_eq!(target.tree_ids()?, [1, 2]);
+        for tree_id in 1..=3 {
+            let mut expected = Vec::new();
+            source.for_each(tree_id, |k, v| {
+                expected.push((k.to_vec(), v.to_vec()));
+                Ok(())
+            })?;
+            let mut actual = Vec::new();
+            target.for_each(tree_id, |k, v| {
+                actual.push((k.to_vec(), v.to_vec()));
+                Ok(())
+            })?;
+            assert_eq!(actual, expected);
+        }
+        assert_eq!(target.count_range(1, &[0; 4], &[0xff; 4])?, 451);
+        assert!(target.check()?.is_empty());
+
+        let err = compact(
+            &mut source,
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &HashMap::new(),
+        );
+        assert!(matches!(err, Err(crate::Error::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn compact_change_feed() -> Result<()> {
+        let comparators = builtin_comparators();
+        let mut source = Storage::new(
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_default_cmp(comparators["bytewise"].clone())
+        .with_change_feed();
+        for i in 0..10u32 {
+            let tr = source.begin_transaction()?;
+            source.insert(tr, 1, &i.to_be_bytes(), b"value")?;
+            source.commit_transaction(tr)?;
+        }
+
+        let mut target = compact(
+            &mut source,
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &comparators,
+        )?;
+        assert_eq!(target.tree_ids()?, [1]);
+        assert_eq!(target.last_commit_seq()?, 10);
+        assert_eq!(target.changes_after(0)?, source.changes_after(0)?);
+        assert!(target.check()?.is_empty());
+        Ok(())
+    }
+
+    struct SumAggregate {}
+
+    impl Aggregate for SumAggregate {
+        fn summarize(&self, _key: &[u8], value: &[u8]) -> Vec<u8> {
+            value.to_vec()
+        }
+
+        fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+            let l = u64::from_le_bytes(left.try_into().unwrap());
+            let r = u64::from_le_bytes(right.try_into().unwrap());
+            (l + r).to_le_bytes().to_vec()
+        }
+    }
+
+    fn configured(storage: Storage) -> Storage {
+        storage
+            .with_default_cmp(builtin_comparators()["bytewise"].clone())
+            .with_aggregate(1, Rc::new(RefCell::new(SumAggregate {})))
+            .with_index(2, IndexDef::new(3, |v| v.first().map(|c| vec![*c])))
+            .with_duplicates(4)
+    }
+
+    #[test]
+    fn compact_config() -> Result<()> {
+        let comparators = builtin_comparators();
+        let mut source = configured(Storage::new(
+            Rc::new(RefCell::new(MemoryStorage::new())),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?);
+        for i in 0..500u32 {
+            let tr = source.begin_transaction()?;
+            source.insert(tr, 1, &i.to_be_bytes(), &(i as u64).to_le_bytes())?;
+            source.insert(tr, 2, &i.to_be_bytes(), &[(i % 5) as u8])?;
+            source.insert(tr, 4, &(i % 7).to_be_bytes(), &i.to_be_bytes())?;
+            source.commit_transaction(tr)?;
+        }
+        let sum = |s: &mut Storage| -> Result<u64> {
+            let v = s.aggregate_range(1, &[0; 4], &[0xff; 4])?.unwrap();
+            Ok(u64::from_le_bytes(v.try_into().unwrap()))
+        };
+        assert_eq!(sum(&mut source)?, 124750);
+        assert_eq!(source.find_by_index(3, &[2])?.len(), 100);
+
+        let mem = Rc::new(RefCell::new(MemoryStorage::new()));
+        let mut target = compact(&mut source, mem.clone(), &comparators)?;
+        assert_eq!(target.tree_ids()?, [1, 2, 3, 4]);
+        assert!(target.check()?.is_empty());
+        target.close()?;
+
+        let copy = Rc::new(RefCell::new(MemoryStorage::from_bytes(
+            mem.borrow().to_bytes(),
+        )));
+        let mut target = configured(Storage::open(copy, HashMap::new())?);
+        assert_eq!(sum(&mut target)?, 124750);
+        let from = 100u32.to_be_bytes();
+        let to = 199u32.to_be_bytes();
+        assert_eq!(
+            target.aggregate_range(1, &from, &to)?,
+            source.aggregate_range(1, &from, &to)?
+        );
+        assert_eq!(
+            target.find_by_index(3, &[2])?,
+            source.find_by_index(3, &[2])?
+        );
+        assert_eq!(target.find_by_index(3, &[2])?.len(), 100);
+        assert_eq!(
+            target.get_all(4, &3u32.to_be_bytes())?,
+            source.get_all(4, &3u32.to_be_bytes())?
+        );
+
+        // the copied index is updated by new writes.
+        let tr = target.begin_transaction()?;
+        target.insert(tr, 2, &7u32.to_be_bytes(), &[0])?;
+        target.commit_transaction(tr)?;
+        assert_eq!(target.find_by_index(3, &[2])?.len(), 99);
+        assert_eq!(target.find_by_index(3, &[0])?.len(), 101);
+        Ok(())
+    }
+}
@@ -0,0 +1,565 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::{BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    storage::{
+        bulk_load::BulkLoadParams, flat_storage::FlatStorage, store::Storage, KeyCmp, StorageParams,
+    },
+    Result,
+};
+
+/// keys and values are written as hex strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// `{"tree":1,"cmp":"bytewise"}` for each tree, then
+    /// `{"tree":1,"key":"6b","value":"76"}` for each record.
+    JsonLines,
+    /// `tree,cmp,key,value` rows after the header. empty trees are not written.
+    Csv,
+}
+
+const CSV_HEADER: &str = "tree,cmp,key,value";
+
+/// writes all trees in the tree id order and records in the key order.
+/// index trees are not written. returns the count of records.
+pub fn export(storage: &mut Storage, format: DumpFormat, out: &mut dyn Write) -> Result<usize> {
+    let mut trees = Vec::new();
+    for tree_id in storage.tree_ids()? {
+        if !storage.is_index_tree(tree_id) {
+            trees.push((tree_id, storage.cmp_name(tree_id)?));
+        }
+    }
+
+    match format {
+        DumpFormat::JsonLines => {
+            for (tree_id, cmp) in trees.iter() {
+                writeln!(out, "{{\"tree\":{},\"cmp\":{}}}", tree_id, json_string(cmp))?;
+            }
+        }
+        DumpFormat::Csv => {
+            for (_, cmp) in trees.iter() {
+                if cmp.contains([',', '"', '\n', '\r']) {
+                    return Err(crate::Error::InvalidParams(format!(
+                        "comparator name '{}' can not be written to csv",
+                        cmp
+                    )));
+                }
+            }
+            writeln!(out, "{}", CSV_HEADER)?;
+        }
+    }
+
+    let mut count = 0;
+    for (tree_id, cmp) in trees.iter() {
+        storage.for_each(*tree_id, |key, value| {
+            match format {
+                DumpFormat::JsonLines => writeln!(
+                    out,
+                    "{{\"tree\":{},\"key\":\"{}\",\"value\":\"{}\"}}",
+                    tree_id,
+                    to_hex(key),
+                    to_hex(value)
+                )?,
+                DumpFormat::Csv => {
+                    writeln!(out, "{},{},{},{}", tree_id, cmp, to_hex(key), to_hex(value))?
+                }
+            }
+            count += 1;
+            Ok(())
+        })?;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// loads the dump into a new storage. comparators of trees are found by names in `comparators`.
+pub fn import(
+    input: &mut dyn BufRead,
+    format: DumpFormat,
+    store: Rc<RefCell<dyn FlatStorage>>,
+    params: &StorageParams,
+    comparators: &HashMap<String, Rc<RefCell<dyn KeyCmp>>>,
+) -> Result<Storage> {
+    let mut storage = Storage::new(store, params, HashMap::new())?;
+    let mut reader = DumpReader::new(input, format);
+    let mut known_trees = HashSet::new();
+    let load_params = BulkLoadParams::default();
+
+    while let Some(line) = reader.peek()? {
+        let tree_id = line.tree_id;
+        if let Some(name) = line.cmp.as_ref() {
+            if known_trees.insert(tree_id) {
+                let cmp = match comparators.get(name) {
+                    Some(c) => c.clone(),
+                    None => return Err(crate::Error::NotFound(format!("comparator '{}'", name))),
+                };
+                storage = storage.with_cmp(tree_id, cmp);
+            }
+        }
+        if line.record.is_none() {
+            reader.next()?;
+            continue;
+        }
+        if !known_trees.contains(&tree_id) {
+            return Err(reader.error(&format!("tree {} is not declared", tree_id)));
+        }
+
+        let mut error = None;
+        let records = TreeRecords {
+            reader: &mut reader,
+            tree_id,
+            error: &mut error,
+        };
+        let res = storage.bulk_load(tree_id, records, &load_params);
+        if let Some(e) = error {
+            return Err(e);
+        }
+        res?;
+    }
+    Ok(storage)
+}
+
+struct DumpLine {
+    tree_id: u32,
+    cmp: Option<String>,
+    record: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+struct DumpReader<'a> {
+    input: &'a mut dyn BufRead,
+    format: DumpFormat,
+    line_number: usize,
+    peeked: Option<DumpLine>,
+}
+
+impl<'a> DumpReader<'a> {
+    fn new(input: &'a mut dyn BufRead, format: DumpFormat) -> Self {
+        DumpReader {
+            input,
+            format,
+            line_number: 0,
+            peeked: None,
+        }
+    }
+
+    fn error(&self, msg: &str) -> crate::Error {
+        crate::Error::InvalidParams(format!("dump line {}: {}", self.line_number, msg))
+    }
+
+    fn peek(&mut self) -> Result<Option<&DumpLine>> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_line()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<DumpLine>> {
+        match self.peeked.take() {
+            Some(line) => Ok(Some(line)),
+            None => self.read_line(),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<DumpLine>> {
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                continue;
+            }
+            match self.format {
+                DumpFormat::JsonLines => return self.parse_json(line).map(Some),
+                DumpFormat::Csv => {
+                    if self.line_number == 1 {
+                        if line != CSV_HEADER {
+                            return Err(self.error("bad csv header"));
+                        }
+                        continue;
+                    }
+                    return self.parse_csv(line).map(Some);
+                }
+            }
+        }
+    }
+
+    fn parse_csv(&self, line: &str) -> Result<DumpLine> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 4 {
+            return Err(self.error("expected 4 fields"));
+        }
+        Ok(DumpLine {
+            tree_id: self.parse_tree_id(fields[0])?,
+            cmp: Some(fields[1].to_owned()),
+            record: Some((self.parse_hex(fields[2])?, self.parse_hex(fields[3])?)),
+        })
+    }
+
+    fn parse_json(&self, line: &str) -> Result<DumpLine> {
+        let fields = match parse_json_object(line) {
+            Some(f) => f,
+            None => return Err(self.error("bad json")),
+        };
+        let field = |name: &str| fields.iter().find(|f| f.0 == name).map(|f| &f.1);
+        let tree_id = match field("tree") {
+            Some(JsonValue::Number(n)) => self.parse_tree_id(n)?,
+            _ => return Err(self.error("no tree")),
+        };
+        match (field("cmp"), field("key"), field("value")) {
+            (Some(JsonValue::String(cmp)), None, None) => Ok(DumpLine {
+                tree_id,
+                cmp: Some(cmp.clone()),
+                record: None,
+            }),
+            (None, Some(JsonValue::String(key)), Some(JsonValue::String(value))) => Ok(DumpLine {
+                tree_id,
+                cmp: None,
+                record: Some((self.parse_hex(key)?, self.parse_hex(value)?)),
+            }),
+            _ => Err(self.error("expected cmp or key and value")),
+        }
+    }
+
+    fn parse_tree_id(&self, v: &str) -> Result<u32> {
+        v.parse().map_err(|_| self.error("bad tree id"))
+    }
+
+    fn parse_hex(&self, v: &str) -> Result<Vec<u8>> {
+        match from_hex(v) {
+            Some(bytes) => Ok(bytes),
+            None => Err(self.error("bad hex string")),
+        }
+    }
+}
+
+/// records of one tree from the reader. the first error stops the iteration.
+struct TreeRecords<'a, 'b> {
+    reader: &'a mut DumpReader<'b>,
+    tree_id: u32,
+    error: &'a mut Option<crate::Error>,
+}
+
+impl Iterator for TreeRecords<'_, '_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        let same_tree = match self.reader.peek() {
+            Ok(Some(line)) => line.tree_id == self.tree_id && line.record.is_some(),
+            Ok(None) => false,
+            Err(e) => {
+                *self.error = Some(e);
+                false
+            }
+        };
+        if !same_tree {
+            return None;
+        }
+        match self.reader.next() {
+            Ok(Some(line)) => line.record,
+            Ok(None) => None,
+            Err(e) => {
+                *self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// lowercase hex digits of the bytes.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(2 * bytes.len());
+    for b in bytes {
+        result.push_str(&format!("{:02x}", b));
+    }
+    result
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    let mut result = Vec::with_capacity(s.len() / 2);
+    for i in (0..s.len()).step_by(2) {
+        result.push(u8::from_str_radix(&s[i..i + 2], 16).ok()?);
+    }
+    Some(result)
+}
+
+pub(super) fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+enum JsonValue {
+    String(String),
+    Number(String),
+}
+
+/// parses a flat object with string and integer values.
+fn parse_json_object(line: &str) -> Option<Vec<(String, JsonValue)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut result = Vec::new();
+    if chars.next()? != '{' {
+        return None;
+    }
+    loop {
+        skip_ws(&mut chars);
+        if result.is_empty() && chars.peek() == Some(&'}') {
+            chars.next();
+            break;
+        }
+        let name = parse_json_string(&mut chars)?;
+        skip_ws(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_ws(&mut chars);
+        let value = if chars.peek() == Some(&'"') {
+            JsonValue::String(parse_json_string(&mut chars)?)
+        } else {
+            let mut n = String::new();
+            while let Some(c) = chars.peek() {
+                if !c.is_ascii_digit() && *c != '-' {
+                    break;
+                }
+                n.push(*c);
+                chars.next();
+            }
+            if n.is_empty() {
+                return None;
+            }
+            JsonValue::Number(n)
+        };
+        result.push((name, value));
+        skip_ws(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => break,
+            _ => return None,
+        }
+    }
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(result)
+}
+
+fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_json_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut result = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(result),
+            '\\' => match chars.next()? {
+                '"' => result.push('"'),
+                '\\' => result.push('\\'),
+                '/' => result.push('/'),
+                'n' => result.push('\n'),
+                'r' => result.push('\r'),
+                't' => result.push('\t'),
+                'u' => {
+                    let code: String = (0..4).filter_map(|_| chars.next()).collect();
+                    let code = u32::from_str_radix(&code, 16).ok()?;
+                    result.push(char::from_u32(code)?);
+                }
+                _ => return None,
+            },
+            c => result.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::file_storage::FileStorage, tools::builtin_comparators};
+
+    struct NamedCmp {}
+
+    impl KeyCmp for NamedCmp {
+        fn compare(&self, key1: &[u8], key2: &[u8]) -> std::cmp::Ordering {
+            key2.cmp(key1)
+        }
+
+        fn name(&self) -> &str {
+            "reverse, \"named\""
+        }
+    }
+
+    fn make_source(filename: &str) -> Result<Storage> {
+        let comparators = builtin_comparators();
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(filename)?)),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_cmp(1, comparators["bytewise"].clone())
+        .with_cmp(7, comparators["bytewise"].clone());
+        let tr = storage.begin_transaction()?;
+        for i in 0..300u32 {
+            storage.insert(tr, 1, &i.to_be_bytes(), format!("v{}", i).as_bytes())?;
+        }
+        storage.insert(tr, 7, b"", b"")?;
+        storage.insert(tr, 7, &[0, 0xff], b"\",\n")?;
+        storage.commit_transaction(tr)?;
+        Ok(storage)
+    }
+
+    fn round_trip(format: DumpFormat) -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let source_path = tempdir.path().join("source");
+        let mut source = make_source(source_path.to_str().unwrap())?;
+        let mut dump = Vec::new();
+        assert_eq!(export(&mut source, format, &mut dump)?, 302);
+
+        let target_path = tempdir.path().join("target");
+        let store = Rc::new(RefCell::new(FileStorage::new(
+            target_path.to_str().unwrap(),
+        )?));
+        let mut target = import(
+            &mut &dump[..],
+            format,
+            store,
+            &StorageParams::default(),
+            &builtin_comparators(),
+        )?;
+        assert_eq!(target.tree_ids()?, vec![1, 7]);
+        assert_eq!(target.cmp_name(7)?, "bytewise");
+        assert_eq!(target.find(7, &[0, 0xff])?, Some(b"\",\n".to_vec()));
+        assert_eq!(target.count_range(1, &[0; 4], &[0xff; 4])?, 300);
+
+        let mut again = Vec::new();
+        export(&mut target, format, &mut again)?;
+        assert_eq!(again, dump);
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines() -> Result<()> {
+        round_trip(DumpFormat::JsonLines)
+    }
+
+    #[test]
+    fn csv() -> Result<()> {
+        round_trip(DumpFormat::Csv)
+    }
+
+    #[test]
+    fn comparator_names() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let filename = tempdir.path().join("named");
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(filename.to_str().unwrap())?)),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_cmp(2, Rc::new(RefCell::new(NamedCmp {})));
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 2, b"a", b"1")?;
+        storage.insert(tr, 2, b"b", b"2")?;
+        storage.commit_transaction(tr)?;
+
+        let mut dump = Vec::new();
+        export(&mut storage, DumpFormat::JsonLines, &mut dump)?;
+        assert!(matches!(
+            export(&mut storage, DumpFormat::Csv, &mut Vec::new()),
+            Err(crate::Error::InvalidParams(_))
+        ));
+
+        let target_path = tempdir.path().join("target");
+        let store = Rc::new(RefCell::new(FileStorage::new(
+            target_path.to_str().unwrap(),
+        )?));
+        let err = import(
+            &mut &dump[..],
+            DumpFormat::JsonLines,
+            store.clone(),
+            &StorageParams::default(),
+            &builtin_comparators(),
+        );
+        assert!(matches!(err, Err(crate::Error::NotFound(_))));
+
+        let mut comparators = builtin_comparators();
+        comparators.insert(
+            NamedCmp {}.name().to_owned(),
+            Rc::new(RefCell::new(NamedCmp {})),
+        );
+        let target_path = tempdir.path().join("target2");
+        let store = Rc::new(RefCell::new(FileStorage::new(
+            target_path.to_str().unwrap(),
+        )?));
+        let mut target = import(
+            &mut &dump[..],
+            DumpFormat::JsonLines,
+            store,
+            &StorageParams::default(),
+            &comparators,
+        )?;
+        assert_eq!(target.first(2)?, Some((b"b".to_vec(), b"2".to_vec())));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_dumps() {
+        let bad: Vec<(&str, DumpFormat)> = vec![
+            (
+                "{\"tree\":1,\"key\":\"00\",\"value\":\"00\"}",
+                DumpFormat::JsonLines,
+            ),
+            (
+                "{\"tree\":1,\"cmp\":\"bytewise\"}\n{\"tree\":1,\"key\":\"0\",\"value\":\"\"}",
+                DumpFormat::JsonLines,
+            ),
+            ("{\"tree\":1,\"cmp\":\"bytewise\"", DumpFormat::JsonLines),
+            ("tree,key,value\n", DumpFormat::Csv),
+            ("tree,cmp,key,value\n1,bytewise,00\n", DumpFormat::Csv),
+            ("tree,cmp,key,value\nx,bytewise,00,00\n", DumpFormat::Csv),
+        ];
+        let tempdir = tempfile::tempdir().unwrap();
+        for (i, (dump, format)) in bad.into_iter().enumerate() {
+            let filename = tempdir.path().join(format!("bad{}", i));
+            let store = Rc::new(RefCell::new(
+                FileStorage::new(filename.to_str().unwrap()).unwrap(),
+            ));
+            let res = import(
+                &mut dump.as_bytes(),
+                format,
+                store,
+                &StorageParams::default(),
+                &builtin_comparators(),
+            );
+            assert!(
+                matches!(res, Err(crate::Error::InvalidParams(_))),
+                "{}",
+                dump
+            );
+        }
+    }
+}
@@ -0,0 +1,762 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use super::dump::{json_string, to_hex};
+use crate::{
+    storage::{
+        store::StorageHeader, StorageParams, FORMAT_VERSION, MAGIC_HEADER, MAGIC_TRANSACTION,
+        MAGIC_TRANSACTION_LIST,
+    },
+    Result,
+};
+
+const U32SZ: usize = std::mem::size_of::<u32>();
+const PARAMS_SIZE: usize = std::mem::size_of::<StorageParams>();
+const HEADER_SIZE: usize = std::mem::size_of::<StorageHeader>();
+/// longer keys and values are cut in the text output.
+const TEXT_BYTES: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+    Params {
+        t: usize,
+        min_size_root: usize,
+        min_size_node: usize,
+        min_size_leaf: usize,
+        max_file_size: u64,
+    },
+    Kv {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// `tree_id` is None for nodes, which no tree block lists.
+    Node {
+        tree_id: Option<u32>,
+        id: u32,
+        is_leaf: bool,
+        parent: u32,
+        left: u32,
+        right: u32,
+        /// offsets of kv records.
+        keys: Vec<u32>,
+        /// offsets of kv records in leafs, ids of children in other nodes.
+        links: Vec<u32>,
+    },
+    /// `MAGIC_TRANSACTION` block: offsets of all nodes of the tree version.
+    Tree {
+        tree_id: u32,
+        nodes: Vec<u32>,
+    },
+    /// `MAGIC_TRANSACTION_LIST` block: offsets of tree blocks of the commit.
+    TransactionList {
+        trees: Vec<u32>,
+    },
+    Header {
+        list: u32,
+        is_closed: bool,
+    },
+    /// bytes, which are not a record of the storage.
+    Unreadable {
+        reason: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct LayoutRecord {
+    pub offset: usize,
+    pub size: usize,
+    pub kind: RecordKind,
+    /// a header, a transaction list, a tree block or a node points to the record.
+    pub referenced: bool,
+    /// reachable from the last header.
+    pub live: bool,
+}
+
+/// records of the file in the offset order.
+#[derive(Clone, Debug)]
+pub struct FileLayout {
+    pub size: usize,
+    pub records: Vec<LayoutRecord>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(U32SZ)?)?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32s(data: &[u8], offset: usize, count: usize) -> Option<Vec<u32>> {
+    if count > data.len() / U32SZ {
+        return None;
+    }
+    (0..count)
+        .map(|i| read_u32(data, offset + i * U32SZ))
+        .collect()
+}
+
+fn read_params(data: &[u8]) -> Result<StorageParams> {
+    let params = StorageParams::from_bytes(data)?;
+    if params.format_version() != FORMAT_VERSION {
+        return Err(crate::Error::UnsupportedVersion(params.format_version()));
+    }
+    let tp = &params.tree_params;
+    if tp.t < 2 || tp.min_size_leaf > tp.t || tp.min_size_node > tp.t {
+        return Err(crate::Error::Corrupted("bad params block".to_owned()));
+    }
+    Ok(params)
+}
+
+fn read_header(data: &[u8], offset: usize) -> Option<StorageHeader> {
+    let bytes = data.get(offset..offset + HEADER_SIZE)?;
+    let header = unsafe { (bytes.as_ptr() as *const StorageHeader).read_unaligned() };
+    if header.magic != MAGIC_HEADER || header.is_closed > 1 || header.offset as usize >= offset {
+        return None;
+    }
+    Some(header)
+}
+
+/// [magic][count][offsets]. offsets point before the block.
+fn read_block(data: &[u8], offset: usize, magic: u32, skip: usize) -> Option<(Vec<u32>, usize)> {
+    if read_u32(data, offset)? != magic {
+        return None;
+    }
+    let count = read_u32(data, offset + (1 + skip) * U32SZ)? as usize;
+    let start = offset + (2 + skip) * U32SZ;
+    let offsets = read_u32s(data, start, count)?;
+    if offsets.iter().any(|o| *o as usize >= offset) {
+        return None;
+    }
+    Some((offsets, start + count * U32SZ - offset))
+}
+
+fn read_kv(data: &[u8], offset: usize, end: usize) -> Option<(RecordKind, usize)> {
+    let key_len = read_u32(data, offset)? as usize;
+    let key_end = (offset + U32SZ).checked_add(key_len)?;
+    let value_len = read_u32(data, key_end)? as usize;
+    let value_end = (key_end + U32SZ).checked_add(value_len)?;
+    if value_end > end {
+        return None;
+    }
+    let kind = RecordKind::Kv {
+        key: data[offset + U32SZ..key_end].to_vec(),
+        value: data[key_end + U32SZ..value_end].to_vec(),
+    };
+    Some((kind, value_end - offset))
+}
+
+/// the format of `StorageNodeStorage::save_node`.
+fn read_node(
+    data: &[u8],
+    offset: usize,
+    end: usize,
+    capacity: usize,
+    tree_id: Option<u32>,
+) -> std::result::Result<(RecordKind, usize), String> {
+    let truncated = || "truncated node".to_owned();
+    let id = read_u32(data, offset).ok_or_else(truncated)?;
+    let is_leaf = match data.get(offset + U32SZ) {
+        Some(0) => false,
+        Some(1) => true,
+        Some(v) => return Err(format!("bad leaf flag {}", v)),
+        None => return Err(truncated()),
+    };
+    let mut pos = offset + U32SZ + 1;
+    let links = read_u32s(data, pos, 5).ok_or_else(truncated)?;
+    pos += 5 * U32SZ;
+    let (keys_count, data_count) = (links[3] as usize, links[4] as usize);
+    if keys_count > capacity || data_count > capacity {
+        return Err(format!(
+            "{} keys and {} links in node {}",
+            keys_count, data_count, id
+        ));
+    }
+    let keys = read_u32s(data, pos, keys_count).ok_or_else(truncated)?;
+    pos += keys_count * U32SZ;
+    let children = read_u32s(data, pos, data_count).ok_or_else(truncated)?;
+    pos += data_count * U32SZ;
+    if !is_leaf {
+        // counts, then aggregates.
+        pos += data_count * U32SZ;
+        for _ in 0..data_count {
+            let len = read_u32(data, pos).ok_or_else(truncated)? as usize;
+            pos = pos.checked_add(U32SZ + len).ok_or_else(truncated)?;
+        }
+    }
+    if pos > end {
+        return Err(truncated());
+    }
+    let kind = RecordKind::Node {
+        tree_id,
+        id,
+        is_leaf,
+        parent: links[0],
+        left: links[1],
+        right: links[2],
+        keys,
+        links: children,
+    };
+    Ok((kind, pos - offset))
+}
+
+fn record(offset: usize, size: usize, kind: RecordKind) -> LayoutRecord {
+    LayoutRecord {
+        offset,
+        size,
+        kind,
+        referenced: true,
+        live: false,
+    }
+}
+
+/// kv records and nodes, which nothing points to, are guessed in the order of the file.
+fn guess_records(data: &[u8], mut pos: usize, end: usize, capacity: usize) -> Vec<LayoutRecord> {
+    let mut result = Vec::new();
+    while pos < end {
+        let guess =
+            read_kv(data, pos, end).or_else(|| read_node(data, pos, end, capacity, None).ok());
+        match guess {
+            Some((kind, size)) => {
+                let mut r = record(pos, size, kind);
+                r.referenced = false;
+                result.push(r);
+                pos += size;
+            }
+            None => {
+                let mut r = record(
+                    pos,
+                    end - pos,
+                    RecordKind::Unreadable {
+                        reason: "unknown bytes".to_owned(),
+                    },
+                );
+                r.referenced = false;
+                result.push(r);
+                break;
+            }
+        }
+    }
+    result
+}
+
+/// parses the image of a file storage. blocks with magics are found by a scan of all offsets,
+/// nodes and kv records by links from them. bytes between them are walked sequentially.
+pub fn inspect(data: &[u8]) -> Result<FileLayout> {
+    let params = read_params(data)?;
+    let tp = params.tree_params;
+    let capacity = tp.get_keys_count();
+
+    let mut headers = BTreeMap::new();
+    let mut lists = BTreeMap::new();
+    let mut trees = BTreeMap::new();
+    for pos in PARAMS_SIZE..data.len() {
+        if let Some(h) = read_header(data, pos) {
+            headers.insert(pos, h);
+        }
+        if let Some(block) = read_block(data, pos, MAGIC_TRANSACTION_LIST, 0) {
+            lists.insert(pos, block);
+        }
+        if let Some((nodes, size)) = read_block(data, pos, MAGIC_TRANSACTION, 1) {
+            let tree_id = read_u32(data, pos + U32SZ).unwrap();
+            trees.insert(pos, (tree_id, nodes, size));
+        }
+    }
+    lists.retain(|_, (offsets, _)| offsets.iter().all(|o| trees.contains_key(&(*o as usize))));
+    headers.retain(|_, h| h.offset == 0 || lists.contains_key(&(h.offset as usize)));
+
+    let mut known = BTreeMap::new();
+    let mut referenced = HashSet::new();
+    let mut live = HashSet::new();
+    let last_header = headers.keys().next_back().cloned();
+    for (pos, h) in headers.iter() {
+        referenced.insert(h.offset as usize);
+        if Some(*pos) == last_header {
+            live.insert(*pos);
+            live.insert(h.offset as usize);
+        }
+    }
+    for (pos, (offsets, _)) in lists.iter() {
+        for o in offsets.iter() {
+            referenced.insert(*o as usize);
+            if live.contains(pos) {
+                live.insert(*o as usize);
+            }
+        }
+    }
+
+    // nodes and kv records are found by links only.
+    for (pos, (tree_id, nodes, _)) in trees.iter() {
+        let is_live = live.contains(pos);
+        for n in nodes.iter() {
+            let offset = *n as usize;
+            referenced.insert(offset);
+            if is_live {
+                live.insert(offset);
+            }
+            if known.contains_key(&offset) {
+                continue;
+            }
+            let kind = match read_node(data, offset, *pos, capacity, Some(*tree_id)) {
+                Ok((kind, size)) => {
+                    known.insert(offset, record(offset, size, kind));
+                    continue;
+                }
+                Err(reason) => RecordKind::Unreadable {
+                    reason: format!("node of tree {}: {}", tree_id, reason),
+                },
+            };
+            known.insert(offset, record(offset, 0, kind));
+        }
+    }
+    let mut kvs = BTreeMap::new();
+    for r in known.values() {
+        if let RecordKind::Node {
+            is_leaf,
+            keys,
+            links,
+            ..
+        } = &r.kind
+        {
+            let mut offsets = keys.clone();
+            if *is_leaf {
+                offsets.extend_from_slice(links);
+            }
+            for o in offsets {
+                let o = o as usize;
+                let is_live = live.contains(&r.offset) || kvs.get(&o).cloned().unwrap_or(false);
+                kvs.insert(o, is_live);
+            }
+        }
+    }
+    for (offset, is_live) in kvs {
+        referenced.insert(offset);
+        if is_live {
+            live.insert(offset);
+        }
+        let kind = match read_kv(data, offset, data.len()) {
+            Some((kind, size)) => {
+                known.insert(offset, record(offset, size, kind));
+                continue;
+            }
+            None if offset >= data.len() => RecordKind::Unreadable {
+                reason: "kv record after the end of the file".to_owned(),
+            },
+            None => RecordKind::Unreadable {
+                reason: "bad kv record".to_owned(),
+            },
+        };
+        known.entry(offset).or_insert(record(offset, 0, kind));
+    }
+
+    for (pos, h) in headers.iter() {
+        let kind = RecordKind::Header {
+            list: h.offset,
+            is_closed: h.is_closed != 0,
+        };
+        known.entry(*pos).or_insert(record(*pos, HEADER_SIZE, kind));
+    }
+    for (pos, (trees, size)) in lists {
+        let kind = RecordKind::TransactionList { trees };
+        known.entry(pos).or_insert(record(pos, size, kind));
+    }
+    for (pos, (tree_id, nodes, size)) in trees {
+        let kind = RecordKind::Tree { tree_id, nodes };
+        known.entry(pos).or_insert(record(pos, size, kind));
+    }
+
+    let mut records = vec![record(
+        0,
+        PARAMS_SIZE,
+        RecordKind::Params {
+            t: tp.t,
+            min_size_root: tp.min_size_root,
+            min_size_node: tp.min_size_node,
+            min_size_leaf: tp.min_size_leaf,
+            max_file_size: params.max_file_size,
+        },
+    )];
+    records[0].live = true;
+    let mut pos = PARAMS_SIZE;
+    let offsets: Vec<usize> = known.keys().cloned().collect();
+    for (i, offset) in offsets.iter().enumerate() {
+        let mut r = known.remove(offset).unwrap();
+        if *offset < pos {
+            r.kind = RecordKind::Unreadable {
+                reason: format!("{} inside the previous record", kind_name(&r.kind)),
+            };
+            r.size = 0;
+            records.push(r);
+            continue;
+        }
+        records.extend(guess_records(
+            data,
+            pos,
+            std::cmp::min(*offset, data.len()),
+            capacity,
+        ));
+        if let RecordKind::Unreadable { .. } = r.kind {
+            // the size of a broken record is not known. links after the end have no size.
+            let next = std::cmp::min(*offsets.get(i + 1).unwrap_or(&data.len()), data.len());
+            r.size = next.saturating_sub(*offset);
+        }
+        r.referenced = matches!(r.kind, RecordKind::Header { .. }) || referenced.contains(offset);
+        r.live = live.contains(offset);
+        pos = offset + r.size;
+        records.push(r);
+    }
+    records.extend(guess_records(data, pos, data.len(), capacity));
+    Ok(FileLayout {
+        size: data.len(),
+        records,
+    })
+}
+
+pub fn inspect_file(path: &Path) -> Result<FileLayout> {
+    inspect(&std::fs::read(path)?)
+}
+
+fn kind_name(kind: &RecordKind) -> &'static str {
+    match kind {
+        RecordKind::Params { .. } => "params",
+        RecordKind::Kv { .. } => "kv",
+        RecordKind::Node { .. } => "node",
+        RecordKind::Tree { .. } => "tree",
+        RecordKind::TransactionList { .. } => "list",
+        RecordKind::Header { .. } => "header",
+        RecordKind::Unreadable { .. } => "unreadable",
+    }
+}
+
+fn short_hex(bytes: &[u8]) -> String {
+    if bytes.len() <= TEXT_BYTES {
+        return to_hex(bytes);
+    }
+    format!("{}..({} bytes)", to_hex(&bytes[..TEXT_BYTES]), bytes.len())
+}
+
+fn json_numbers(values: &[u32]) -> String {
+    let items: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl FileLayout {
+    pub fn unreadable_bytes(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|r| matches!(r.kind, RecordKind::Unreadable { .. }))
+            .map(|r| r.size)
+            .sum()
+    }
+
+    /// one line for each record: offset, size, type, fields and flags.
+    pub fn write_text(&self, out: &mut dyn Write) -> Result<()> {
+        for r in self.records.iter() {
+            let details = match &r.kind {
+                RecordKind::Params {
+                    t,
+                    min_size_root,
+                    min_size_node,
+                    min_size_leaf,
+                    max_file_size,
+                } => format!(
+                    "t={} min_size_root={} min_size_node={} min_size_leaf={} max_file_size={}",
+                    t, min_size_root, min_size_node, min_size_leaf, max_file_size
+                ),
+                RecordKind::Kv { key, value } => {
+                    format!("key={} value={}", short_hex(key), short_hex(value))
+                }
+                RecordKind::Node {
+                    tree_id,
+                    id,
+                    is_leaf,
+                    parent,
+                    left,
+                    right,
+                    keys,
+                    links,
+                } => format!(
+                    "tree={} id={} {} parent={} left={} right={} keys={:?} links={:?}",
+                    tree_id.map_or("?".to_owned(), |t| t.to_string()),
+                    id,
+                    if *is_leaf { "leaf" } else { "node" },
+                    parent,
+                    left,
+                    right,
+                    keys,
+                    links
+                ),
+                RecordKind::Tree { tree_id, nodes } => {
+                    format!("tree={} nodes={:?}", tree_id, nodes)
+                }
+                RecordKind::TransactionList { trees } => format!("trees={:?}", trees),
+                RecordKind::Header { list, is_closed } => {
+                    format!("list={}{}", list, if *is_closed { " closed" } else { "" })
+                }
+                RecordKind::Unreadable { reason } => reason.clone(),
+            };
+            let mut flags = String::new();
+            if r.live {
+                flags.push_str(" live");
+            }
+            if !r.referenced {
+                flags.push_str(" unreferenced");
+            }
+            writeln!(
+                out,
+                "{:>10} {:>8} {:<10} {}{}",
+                r.offset,
+                r.size,
+                kind_name(&r.kind),
+                details,
+                flags
+            )?;
+        }
+        writeln!(
+            out,
+            "{} bytes, {} records, {} unreferenced, {} unreadable bytes",
+            self.size,
+            self.records.len(),
+            self.records.iter().filter(|r| !r.referenced).count(),
+            self.unreadable_bytes()
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// `{"size":N,"records":[{"offset":0,"size":40,"type":"params",...},...]}`.
+    /// keys and values are hex strings.
+    pub fn write_json(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "{{\"size\":{},\"records\":[", self.size)?;
+        for (i, r) in self.records.iter().enumerate() {
+            let fields = match &r.kind {
+                RecordKind::Params {
+                    t,
+                    min_size_root,
+                    min_size_node,
+                    min_size_leaf,
+                    max_file_size,
+                } => format!(
+                    "\"t\":{},\"min_size_root\":{},\"min_size_node\":{},\"min_size_leaf\":{},\"max_file_size\":{}",
+                    t, min_size_root, min_size_node, min_size_leaf, max_file_size
+                ),
+                RecordKind::Kv { key, value } => {
+                    format!("\"key\":\"{}\",\"value\":\"{}\"", to_hex(key), to_hex(value))
+                }
+                RecordKind::Node {
+                    tree_id,
+                    id,
+                    is_leaf,
+                    parent,
+                    left,
+                    right,
+                    keys,
+                    links,
+                } => format!(
+                    "\"tree\":{},\"id\":{},\"leaf\":{},\"parent\":{},\"left\":{},\"right\":{},\"keys\":{},\"links\":{}",
+                    tree_id.map_or("null".to_owned(), |t| t.to_string()),
+                    id,
+                    is_leaf,
+                    parent,
+                    left,
+                    right,
+                    json_numbers(keys),
+                    json_numbers(links)
+                ),
+                RecordKind::Tree { tree_id, nodes } => {
+                    format!("\"tree\":{},\"nodes\":{}", tree_id, json_numbers(nodes))
+                }
+                RecordKind::TransactionList { trees } => {
+                    format!("\"trees\":{}", json_numbers(trees))
+                }
+                RecordKind::Header { list, is_closed } => {
+                    format!("\"list\":{},\"closed\":{}", list, is_closed)
+                }
+                RecordKind::Unreadable { reason } => format!("\"reason\":{}", json_string(reason)),
+            };
+            writeln!(
+                out,
+                "{{\"offset\":{},\"size\":{},\"type\":\"{}\",\"referenced\":{},\"live\":{},{}}}{}",
+                r.offset,
+                r.size,
+                kind_name(&r.kind),
+                r.referenced,
+                r.live,
+                fields,
+                if i + 1 < self.records.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(out, "]}}")?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    use super::*;
+    use crate::{
+        storage::{file_storage::FileStorage, store::Storage},
+        tools::builtin_comparators,
+        tree::TreeParams,
+    };
+
+    fn count(layout: &FileLayout, f: impl Fn(&LayoutRecord) -> bool) -> usize {
+        layout.records.iter().filter(|r| f(r)).count()
+    }
+
+    #[test]
+    fn file_layout() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("storage");
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            &params,
+            HashMap::new(),
+        )?
+        .with_default_cmp(builtin_comparators()["bytewise"].clone());
+        for i in 0..4u32 {
+            let tr = storage.begin_transaction()?;
+            for j in 0..10u32 {
+                storage.insert(tr, 1 + j % 2, &(i * 10 + j).to_be_bytes(), b"value")?;
+            }
+            storage.commit_transaction(tr)?;
+        }
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"rolled back", b"")?;
+        storage.rollback_transaction(tr)?;
+        let tr = storage.begin_transaction()?;
+        storage.delete(tr, 1, &0u32.to_be_bytes())?;
+        storage.commit_transaction(tr)?;
+        storage.close()?;
+
+        let layout = inspect_file(&path)?;
+        assert_eq!(layout.size, std::fs::metadata(&path)?.len() as usize);
+        let mut pos = 0;
+        for r in layout.records.iter() {
+            assert_eq!(r.offset, pos);
+            pos += r.size;
+        }
+        assert_eq!(pos, layout.size);
+        assert_eq!(layout.unreadable_bytes(), 0);
+
+        let is_kv = |r: &LayoutRecord| matches!(r.kind, RecordKind::Kv { .. });
+        assert_eq!(count(&layout, |r| is_kv(r)), 41);
+        assert_eq!(count(&layout, |r| is_kv(r) && r.live), 39);
+        let unreferenced: Vec<&LayoutRecord> =
+            layout.records.iter().filter(|r| !r.referenced).collect();
+        assert_eq!(unreferenced.len(), 1);
+        assert_eq!(
+            unreferenced[0].kind,
+            RecordKind::Kv {
+                key: b"rolled back".to_vec(),
+                value: Vec::new()
+            }
+        );
+        // the first header, one for each commit and the close.
+        let headers = count(&layout, |r| matches!(r.kind, RecordKind::Header { .. }));
+        assert_eq!(headers, 7);
+        assert!(matches!(
+            layout.records.last().unwrap().kind,
+            RecordKind::Header {
+                is_closed: true,
+                ..
+            }
+        ));
+        assert_eq!(
+            count(&layout, |r| matches!(
+                r.kind,
+                RecordKind::TransactionList { .. }
+            ) && r.live),
+            1
+        );
+        let live_trees: Vec<u32> = layout
+            .records
+            .iter()
+            .filter_map(|r| match r.kind {
+                RecordKind::Tree { tree_id, .. } if r.live => Some(tree_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(live_trees.len(), 2);
+        assert!(layout.records.iter().all(|r| match r.kind {
+            RecordKind::Node { tree_id, .. } => tree_id == Some(1) || tree_id == Some(2),
+            _ => true,
+        }));
+
+        let mut text = Vec::new();
+        layout.write_text(&mut text)?;
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.lines().count(), layout.records.len() + 1);
+        assert!(text.contains("unreferenced"));
+        let mut json = Vec::new();
+        layout.write_json(&mut json)?;
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"size\":"));
+        assert_eq!(json.matches("\"offset\":").count(), layout.records.len());
+
+        // a damaged node and a truncated tail.
+        let mut data = std::fs::read(&path)?;
+        let node = layout
+            .records
+            .iter()
+            .find(|r| r.live && matches!(r.kind, RecordKind::Node { .. }))
+            .unwrap();
+        data[node.offset + U32SZ] = 7;
+        data.truncate(data.len() - 5);
+        let damaged = inspect(&data)?;
+        assert!(damaged.records.iter().any(|r| r.offset == node.offset
+            && r.size == node.size
+            && matches!(r.kind, RecordKind::Unreadable { .. })));
+        assert!(damaged.unreadable_bytes() >= node.size + HEADER_SIZE - 5);
+
+        assert!(inspect(&data[..10]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn link_past_the_end() -> Result<()> {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("storage");
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_default_cmp(builtin_comparators()["bytewise"].clone());
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"key", b"value")?;
+        storage.commit_transaction(tr)?;
+        storage.close()?;
+
+        let mut data = std::fs::read(&path)?;
+        let layout = inspect(&data)?;
+        let leaf = layout
+            .records
+            .iter()
+            .find(|r| matches!(r.kind, RecordKind::Node { is_leaf: true, .. }))
+            .unwrap();
+        // the first key of the leaf.
+        let key = leaf.offset + U32SZ + 1 + 5 * U32SZ;
+        let past = (data.len() + 100) as u32;
+        data[key..key + U32SZ].copy_from_slice(&past.to_ne_bytes());
+
+        let damaged = inspect(&data)?;
+        let r = damaged
+            .records
+            .iter()
+            .find(|r| r.offset == past as usize)
+            .unwrap();
+        assert!(matches!(r.kind, RecordKind::Unreadable { .. }));
+        assert_eq!(r.size, 0);
+        assert!(damaged
+            .records
+            .iter()
+            .all(|r| r.offset + r.size <= data.len() || r.size == 0));
+        Ok(())
+    }
+}
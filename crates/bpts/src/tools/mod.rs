@@ -0,0 +1,21 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::storage::{BytewiseKeyCmp, KeyCmp};
+
+mod compact;
+mod dump;
+mod inspect;
+mod salvage;
+
+pub use compact::compact;
+pub use dump::{export, from_hex, import, to_hex, DumpFormat};
+pub use inspect::{inspect, inspect_file, FileLayout, LayoutRecord, RecordKind};
+pub use salvage::{salvage, LostEntry, SalvageMode, SalvageReport};
+
+/// comparators of the library by names.
+pub fn builtin_comparators() -> HashMap<String, Rc<RefCell<dyn KeyCmp>>> {
+    let mut result: HashMap<String, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+    let bytewise = BytewiseKeyCmp {};
+    result.insert(bytewise.name().to_owned(), Rc::new(RefCell::new(bytewise)));
+    result
+}
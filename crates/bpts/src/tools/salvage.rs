@@ -0,0 +1,499 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    rc::Rc,
+};
+
+use super::inspect::{inspect, FileLayout, LayoutRecord, RecordKind};
+use crate::{
+    storage::{
+        bulk_load::BulkLoadParams, flat_storage::FlatStorage, store::Storage, KeyCmp, StorageParams,
+    },
+    tree::TreeParams,
+    Result,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SalvageMode {
+    /// records of the leafs of the last commit with a readable transaction list.
+    LastCommit,
+    /// all kv records, which a leaf of any version of a tree points to.
+    /// the record with the latest offset wins for each key, removed keys come back.
+    AllRecords,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LostEntry {
+    pub offset: usize,
+    pub tree_id: Option<u32>,
+    /// None, if the record is unreadable.
+    pub key: Option<Vec<u8>>,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SalvageReport {
+    /// offset of the header of the used commit.
+    pub commit: Option<usize>,
+    /// count of recovered entries by tree.
+    pub recovered: BTreeMap<u32, usize>,
+    /// older records of recovered keys.
+    pub superseded: usize,
+    /// nodes of the used commit, which can not be read. entries of broken leafs are not known.
+    pub broken_nodes: Vec<usize>,
+    pub lost: Vec<LostEntry>,
+}
+
+impl SalvageReport {
+    pub fn recovered_total(&self) -> usize {
+        self.recovered.values().sum()
+    }
+}
+
+impl fmt::Display for SalvageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "salvage:")?;
+        match self.commit {
+            Some(offset) => writeln!(f, " commit: header at {}", offset)?,
+            None => writeln!(f, " commit: none")?,
+        }
+        for (tree_id, count) in self.recovered.iter() {
+            writeln!(f, " tree {}: {} recovered", tree_id, count)?;
+        }
+        writeln!(f, " superseded: {}", self.superseded)?;
+        for offset in self.broken_nodes.iter() {
+            writeln!(f, " broken node at {}", offset)?;
+        }
+        write!(f, " lost: {}", self.lost.len())?;
+        for l in self.lost.iter() {
+            let tree = l.tree_id.map_or("?".to_owned(), |t| t.to_string());
+            let key = match &l.key {
+                Some(k) => super::to_hex(k),
+                None => "?".to_owned(),
+            };
+            write!(
+                f,
+                "\n  {} tree {} key {}: {}",
+                l.offset, tree, key, l.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type KeyValue = (Vec<u8>, Vec<u8>);
+
+struct Entry {
+    tree_id: u32,
+    offset: usize,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+fn leaf_links(r: &LayoutRecord) -> Option<&Vec<u32>> {
+    match &r.kind {
+        RecordKind::Node {
+            is_leaf: true,
+            links,
+            ..
+        } => Some(links),
+        _ => None,
+    }
+}
+
+/// kv records of the leafs of the last commit, which has a readable transaction list.
+fn last_commit(
+    layout: &FileLayout,
+    by_offset: &HashMap<usize, &LayoutRecord>,
+    report: &mut SalvageReport,
+) -> Vec<(u32, usize)> {
+    let mut result = Vec::new();
+    for r in layout.records.iter().rev() {
+        let list = match r.kind {
+            RecordKind::Header { list, .. } if list != 0 => list as usize,
+            _ => continue,
+        };
+        let trees = match by_offset.get(&list).map(|l| &l.kind) {
+            Some(RecordKind::TransactionList { trees }) => trees,
+            _ => continue,
+        };
+        report.commit = Some(r.offset);
+        for t in trees.iter() {
+            let (tree_id, nodes) = match by_offset.get(&(*t as usize)).map(|b| &b.kind) {
+                Some(RecordKind::Tree { tree_id, nodes }) => (*tree_id, nodes),
+                _ => continue,
+            };
+            for n in nodes.iter() {
+                match by_offset.get(&(*n as usize)) {
+                    Some(node) if !matches!(node.kind, RecordKind::Unreadable { .. }) => {
+                        if let Some(links) = leaf_links(node) {
+                            result.extend(links.iter().map(|o| (tree_id, *o as usize)));
+                        }
+                    }
+                    _ => report.broken_nodes.push(*n as usize),
+                }
+            }
+        }
+        break;
+    }
+    result
+}
+
+/// kv records of leafs of all versions of trees.
+fn all_records(layout: &FileLayout, report: &mut SalvageReport) -> Vec<(u32, usize)> {
+    let mut result = Vec::new();
+    let mut owners = HashMap::new();
+    for r in layout.records.iter() {
+        if let RecordKind::Node {
+            tree_id: Some(tree_id),
+            ..
+        } = r.kind
+        {
+            if let Some(links) = leaf_links(r) {
+                for o in links.iter() {
+                    owners.insert(*o as usize, tree_id);
+                }
+            }
+        }
+    }
+    for r in layout.records.iter() {
+        match (&r.kind, owners.get(&r.offset)) {
+            (RecordKind::Kv { .. }, Some(tree_id)) => result.push((*tree_id, r.offset)),
+            (RecordKind::Kv { key, .. }, None) => report.lost.push(LostEntry {
+                offset: r.offset,
+                tree_id: None,
+                key: Some(key.clone()),
+                reason: "no leaf points to the record".to_owned(),
+            }),
+            (RecordKind::Unreadable { .. }, Some(tree_id)) => result.push((*tree_id, r.offset)),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// rebuilds trees from kv records of the damaged file image into a new storage.
+/// the file does not keep comparators, trees are sorted by `cmp` or by `default_cmp`.
+pub fn salvage(
+    data: &[u8],
+    mode: SalvageMode,
+    target: Rc<RefCell<dyn FlatStorage>>,
+    cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>>,
+    default_cmp: Option<Rc<RefCell<dyn KeyCmp>>>,
+) -> Result<(Storage, SalvageReport)> {
+    let layout = inspect(data)?;
+    let mut report = SalvageReport::default();
+    let by_offset: HashMap<usize, &LayoutRecord> =
+        layout.records.iter().map(|r| (r.offset, r)).collect();
+
+    let found = match mode {
+        SalvageMode::LastCommit => last_commit(&layout, &by_offset, &mut report),
+        SalvageMode::AllRecords => all_records(&layout, &mut report),
+    };
+
+    // the latest record of each key wins.
+    let mut entries: HashMap<(u32, Vec<u8>), Entry> = HashMap::new();
+    for (tree_id, offset) in found {
+        let (key, value) = match by_offset.get(&offset).map(|r| &r.kind) {
+            Some(RecordKind::Kv { key, value }) => (key.clone(), value.clone()),
+            _ => {
+                report.lost.push(LostEntry {
+                    offset,
+                    tree_id: Some(tree_id),
+                    key: None,
+                    reason: "unreadable kv record".to_owned(),
+                });
+                continue;
+            }
+        };
+        let entry = Entry {
+            tree_id,
+            offset,
+            key: key.clone(),
+            value,
+        };
+        match entries.get_mut(&(tree_id, key.clone())) {
+            Some(e) => {
+                report.superseded += 1;
+                if e.offset < offset {
+                    *e = entry;
+                }
+            }
+            None => {
+                entries.insert((tree_id, key), entry);
+            }
+        }
+    }
+
+    let params = match layout.records[0].kind {
+        RecordKind::Params {
+            t,
+            min_size_root,
+            min_size_node,
+            min_size_leaf,
+            max_file_size,
+        } => StorageParams {
+            tree_params: TreeParams {
+                t,
+                min_size_root,
+                min_size_node,
+                min_size_leaf,
+            },
+            max_file_size,
+            ..StorageParams::default()
+        },
+        _ => unreachable!(),
+    };
+    let mut storage = Storage::new(target, &params, cmp)?;
+    if let Some(c) = default_cmp {
+        storage = storage.with_default_cmp(c);
+    }
+
+    let mut trees: BTreeMap<u32, Vec<KeyValue>> = BTreeMap::new();
+    for e in entries.into_values() {
+        trees.entry(e.tree_id).or_default().push((e.key, e.value));
+    }
+    let load_params = BulkLoadParams::default();
+    for (tree_id, records) in trees {
+        // the change feed is rebuilt as the other trees.
+        let count = storage.bulk_load_tree(tree_id, records, &load_params)?;
+        report.recovered.insert(tree_id, count);
+    }
+    report.lost.sort_by_key(|l| l.offset);
+    Ok((storage, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{file_storage::FileStorage, U32SZ},
+        tools::{builtin_comparators, inspect_file},
+    };
+
+    type Records = Vec<KeyValue>;
+
+    fn records(storage: &mut Storage, tree_id: u32) -> Result<Records> {
+        let mut result = Vec::new();
+        storage.for_each(tree_id, |k, v| {
+            result.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    fn run(
+        dir: &tempfile::TempDir,
+        name: &str,
+        data: &[u8],
+        mode: SalvageMode,
+    ) -> Result<(Storage, SalvageReport)> {
+        let path = dir.path().join(name);
+        salvage(
+            data,
+            mode,
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            HashMap::new(),
+            Some(builtin_comparators()["bytewise"].clone()),
+        )
+    }
+
+    #[test]
+    fn salvage_file() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source");
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            &params,
+            HashMap::new(),
+        )?
+        .with_default_cmp(builtin_comparators()["bytewise"].clone());
+        for i in 0..3u32 {
+            let tr = storage.begin_transaction()?;
+            for j in 0..30u32 {
+                let key = (i * 30 + j).to_be_bytes();
+                storage.insert(tr, 1 + j % 2, &key, format!("v{}", i).as_bytes())?;
+            }
+            storage.commit_transaction(tr)?;
+        }
+        let before_last = records(&mut storage, 1)?;
+        let tr = storage.begin_transaction()?;
+        storage.delete(tr, 1, &0u32.to_be_bytes())?;
+        storage.delete(tr, 1, &2u32.to_be_bytes())?;
+        storage.insert(tr, 1, &100u32.to_be_bytes(), b"new")?;
+        storage.commit_transaction(tr)?;
+        let tr = storage.begin_transaction()?;
+        storage.insert(tr, 1, b"open", b"")?;
+        let expected = [records(&mut storage, 1)?, records(&mut storage, 2)?];
+        drop(storage);
+
+        let data = std::fs::read(&path)?;
+        let (mut target, report) = run(&dir, "intact", &data, SalvageMode::LastCommit)?;
+        assert_eq!(records(&mut target, 1)?, expected[0]);
+        assert_eq!(records(&mut target, 2)?, expected[1]);
+        assert_eq!(report.recovered_total(), 89);
+        assert!(report.lost.is_empty() && report.broken_nodes.is_empty());
+        assert!(target.check()?.is_empty());
+
+        // all records bring removed keys back. the open transaction is lost.
+        let (mut target, report) = run(&dir, "all", &data, SalvageMode::AllRecords)?;
+        assert_eq!(records(&mut target, 1)?.len(), 46);
+        assert_eq!(records(&mut target, 2)?, expected[1]);
+        assert_eq!(report.superseded, 0);
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(report.lost[0].key, Some(b"open".to_vec()));
+        assert!(report.to_string().contains("no leaf points to the record"));
+
+        // the last transaction list is broken.
+        let layout = inspect_file(&path)?;
+        let list = layout
+            .records
+            .iter()
+            .rev()
+            .find(|r| matches!(r.kind, RecordKind::TransactionList { .. }))
+            .unwrap();
+        let mut damaged = data.clone();
+        damaged[list.offset] ^= 0xff;
+        let (mut target, report) = run(&dir, "list", &damaged, SalvageMode::LastCommit)?;
+        assert_eq!(records(&mut target, 1)?, before_last);
+        assert!(report.commit.unwrap() < list.offset);
+
+        // a leaf of the last commit is broken.
+        let leaf = layout
+            .records
+            .iter()
+            .find(|r| {
+                r.live
+                    && leaf_links(r).is_some()
+                    && matches!(
+                        r.kind,
+                        RecordKind::Node {
+                            tree_id: Some(1),
+                            ..
+                        }
+                    )
+            })
+            .unwrap();
+        let keys = leaf_links(leaf).unwrap().len();
+        let mut damaged = data.clone();
+        damaged[leaf.offset + 4] = 9;
+        let (mut target, report) = run(&dir, "leaf", &damaged, SalvageMode::LastCommit)?;
+        assert_eq!(report.broken_nodes, [leaf.offset]);
+        assert_eq!(records(&mut target, 1)?.len(), expected[0].len() - keys);
+        // records of the leaf, which no older version has, are lost with the open transaction.
+        let (mut target, report) = run(&dir, "leaf_all", &damaged, SalvageMode::AllRecords)?;
+        assert_eq!(records(&mut target, 1)?.len() + report.lost.len() - 1, 46);
+
+        // a kv record of the last commit is broken.
+        let kv = layout
+            .records
+            .iter()
+            .find(|r| r.live && matches!(r.kind, RecordKind::Kv { .. }))
+            .unwrap();
+        let mut damaged = data.clone();
+        damaged[kv.offset..kv.offset + 4].copy_from_slice(&[0xff; 4]);
+        let (mut target, report) = run(&dir, "kv", &damaged, SalvageMode::LastCommit)?;
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(report.lost[0].offset, kv.offset);
+        assert_eq!(report.recovered_total(), 88);
+        assert!(target.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn salvage_change_feed() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source");
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            &StorageParams::default(),
+            HashMap::new(),
+        )?
+        .with_default_cmp(builtin_comparators()["bytewise"].clone())
+        .with_change_feed();
+        for i in 0..5u32 {
+            let tr = storage.begin_transaction()?;
+            storage.insert(tr, 1, &i.to_be_bytes(), b"value")?;
+            storage.commit_transaction(tr)?;
+        }
+        let expected = storage.changes_after(0)?;
+        drop(storage);
+
+        let data = std::fs::read(&path)?;
+        let (mut target, _) = run(&dir, "target", &data, SalvageMode::LastCommit)?;
+        assert_eq!(target.tree_ids()?, [1]);
+        assert_eq!(target.changes_after(0)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn salvage_damaged_tail() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source");
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let mut storage = Storage::new(
+            Rc::new(RefCell::new(FileStorage::new(path.to_str().unwrap())?)),
+            &params,
+            HashMap::new(),
+        )?
+        .with_default_cmp(builtin_comparators()["bytewise"].clone());
+        for i in 0..3u32 {
+            let tr = storage.begin_transaction()?;
+            for j in 0..10u32 {
+                storage.insert(tr, 1, &(i * 10 + j).to_be_bytes(), b"value")?;
+            }
+            storage.commit_transaction(tr)?;
+        }
+        drop(storage);
+        let data = std::fs::read(&path)?;
+
+        // garbage after the last commit is not a commit.
+        let mut damaged = data.clone();
+        damaged.extend((0..200u32).map(|i| (i * 37 % 251) as u8));
+        damaged.extend_from_slice(&[0xff; 64]);
+        for mode in [SalvageMode::LastCommit, SalvageMode::AllRecords] {
+            let (mut target, _) = run(&dir, "garbage", &damaged, mode)?;
+            assert_eq!(records(&mut target, 1)?.len(), 30);
+            drop(target);
+            std::fs::remove_file(dir.path().join("garbage"))?;
+        }
+
+        // a link of the last leaf points after the end of the file.
+        let layout = inspect(&data)?;
+        let leaf = layout
+            .records
+            .iter()
+            .rev()
+            .find(|r| r.live && leaf_links(r).is_some())
+            .unwrap();
+        let keys = match &leaf.kind {
+            RecordKind::Node { keys, .. } => keys.len(),
+            _ => unreachable!(),
+        };
+        let link = leaf.offset + U32SZ + 1 + (5 + keys) * U32SZ;
+        let mut damaged = data.clone();
+        let past = damaged.len() + 100;
+        damaged[link..link + U32SZ].copy_from_slice(&(past as u32).to_ne_bytes());
+        let (_, report) = run(&dir, "past", &damaged, SalvageMode::LastCommit)?;
+        assert!(report.lost.iter().any(|l| l.offset == past));
+
+        // any truncated tail, the records of the remaining commits are found.
+        for (i, len) in (std::mem::size_of::<StorageParams>()..data.len())
+            .step_by(13)
+            .enumerate()
+        {
+            let name = format!("truncated{}", i);
+            for mode in [SalvageMode::LastCommit, SalvageMode::AllRecords] {
+                if let Ok((mut target, _)) = run(&dir, &name, &data[..len], mode) {
+                    assert!(records(&mut target, 1)?.len() <= 30);
+                }
+                let _ = std::fs::remove_file(dir.path().join(&name));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use std::cmp::Ordering;
+
+use crate::Result;
+
+use super::{
+    node::{Node, RcNode},
+    nodestorage::NodeStorage,
+    record::Record,
+};
+
+/// summary of records (min, max, sum...), stored for each child of internal nodes.
+/// an empty summary stands for an empty subtree.
+pub trait NodeAggregate {
+    fn summarize(&self, key: u32, value: &Record) -> Result<Vec<u8>>;
+    fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8>;
+}
+
+fn merge(aggregate: &dyn NodeAggregate, left: Vec<u8>, right: &[u8]) -> Vec<u8> {
+    if left.is_empty() {
+        right.to_vec()
+    } else if right.is_empty() {
+        left
+    } else {
+        aggregate.combine(&left, right)
+    }
+}
+
+/// summary of all records in the subtree of the node.
+pub fn node_summary(aggregate: &dyn NodeAggregate, node: &Node) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    if node.is_leaf {
+        for (k, v) in node.key_iter().zip(node.data_iter()) {
+            let summary = aggregate.summarize(*k, v)?;
+            result = merge(aggregate, result, &summary);
+        }
+    } else {
+        for summary in node.aggregates.iter().take(node.data_count) {
+            result = merge(aggregate, result, summary);
+        }
+    }
+    Ok(result)
+}
+
+/// `position` returns Less for keys before the range, Greater for keys after the range
+/// and Equal for keys in the range. `left_in` and `right_in` are true, if all keys of the node
+/// are known to be not before or not after the range.
+fn range_summary<Storage: NodeStorage>(
+    storage: &Storage,
+    aggregate: &dyn NodeAggregate,
+    node: &RcNode,
+    position: &dyn Fn(u32) -> Result<Ordering>,
+    left_in: bool,
+    right_in: bool,
+) -> Result<Vec<u8>> {
+    let node_ref = node.borrow();
+    let mut result = Vec::new();
+    if node_ref.is_leaf {
+        for (k, v) in node_ref.key_iter().zip(node_ref.data_iter()) {
+            match position(*k)? {
+                Ordering::Less => continue,
+                Ordering::Greater => break,
+                Ordering::Equal => {
+                    let summary = aggregate.summarize(*k, v)?;
+                    result = merge(aggregate, result, &summary);
+                }
+            }
+        }
+        return Ok(result);
+    }
+
+    // keys of the child `i` are in [keys[i-1], keys[i]].
+    let last = node_ref.data_count - 1;
+    let mut low_in = left_in;
+    for i in 0..node_ref.data_count {
+        let separator = if i < last {
+            Some(position(node_ref.keys[i])?)
+        } else {
+            None
+        };
+        let high_in = match separator {
+            // the whole child is before the range.
+            Some(Ordering::Less) => continue,
+            Some(Ordering::Equal) => true,
+            Some(Ordering::Greater) => false,
+            None => right_in,
+        };
+
+        if low_in && high_in {
+            result = merge(aggregate, result, &node_ref.aggregates[i]);
+        } else {
+            let child = storage.get_node(node_ref.data[i].into_id())?;
+            let summary = range_summary(storage, aggregate, &child, position, low_in, high_in)?;
+            result = merge(aggregate, result, &summary);
+        }
+
+        if separator == Some(Ordering::Greater) {
+            // the next children are after the range.
+            break;
+        }
+        low_in = true;
+    }
+    Ok(result)
+}
+
+fn aggregate_with<Storage: NodeStorage>(
+    storage: &Storage,
+    root: &RcNode,
+    position: &dyn Fn(u32) -> Result<Ordering>,
+) -> Result<Option<Vec<u8>>> {
+    let aggregate = match storage.get_aggregate() {
+        Some(a) => a,
+        None => return Err(crate::Error::Fail("aggregate is not set".to_owned())),
+    };
+    let result = range_summary(storage, aggregate, root, position, false, false)?;
+    if result.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(result))
+}
+
+/// combined summary of the records, for which `position` returns Equal.
+/// keys before the range must be Less, keys after the range - Greater.
+pub fn aggregate_range_by<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    position: &dyn Fn(u32) -> Result<Ordering>,
+) -> Result<Option<Vec<u8>>> {
+    aggregate_with(storage, root, position)
+}
+
+/// combined summary of the records with keys in [from, to].
+pub fn aggregate_range<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    from: u32,
+    to: u32,
+) -> Result<Option<Vec<u8>>> {
+    let storage: &Storage = storage;
+    let cmp = storage.get_cmp();
+    let position = |k: u32| {
+        if cmp.compare(k, from).is_lt() {
+            Ok(Ordering::Less)
+        } else if cmp.compare(k, to).is_gt() {
+            Ok(Ordering::Greater)
+        } else {
+            Ok(Ordering::Equal)
+        }
+    };
+    aggregate_with(storage, root, &position)
+}
+
+fn check_node<Storage: NodeStorage>(
+    storage: &Storage,
+    aggregate: &dyn NodeAggregate,
+    node: &RcNode,
+) -> Result<Vec<u8>> {
+    let node_ref = node.borrow();
+    if !node_ref.is_leaf {
+        for i in 0..node_ref.data_count {
+            let child = storage.get_node(node_ref.data[i].into_id())?;
+            let summary = check_node(storage, aggregate, &child)?;
+            if summary != node_ref.aggregates[i] {
+                return Err(crate::Error::Fail(format!(
+                    "wrong summary of child {:?} in {:?}",
+                    node_ref.data[i].into_id(),
+                    node_ref.id
+                )));
+            }
+        }
+    }
+    node_summary(aggregate, &node_ref)
+}
+
+/// checks, that the summaries of all internal nodes match their subtrees.
+pub fn check_aggregates<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+) -> Result<Vec<u8>> {
+    let storage: &Storage = storage;
+    match storage.get_aggregate() {
+        Some(aggregate) => check_node(storage, aggregate, root),
+        None => Err(crate::Error::Fail("aggregate is not set".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{
+        bulk,
+        insert::insert,
+        mocks::{MockNodeStorage, MockSumAggregate},
+        node::Node,
+        remove::remove_key,
+        TreeParams,
+    };
+    use crate::types::Id;
+
+    fn sum(summary: Option<Vec<u8>>) -> u64 {
+        match summary {
+            Some(s) => u64::from_le_bytes(s.try_into().unwrap()),
+            None => 0,
+        }
+    }
+
+    fn check_ranges(storage: &mut MockNodeStorage, root: &RcNode, keys: &[u32]) -> Result<()> {
+        let total: u64 = keys.iter().map(|k| *k as u64).sum();
+        let summary = check_aggregates(storage, root)?;
+        assert_eq!(
+            sum(if summary.is_empty() {
+                None
+            } else {
+                Some(summary)
+            }),
+            total
+        );
+
+        let max = keys.iter().max().cloned().unwrap_or(0) + 2;
+        for from in (0..max).step_by(7) {
+            for to in (from..max).step_by(5) {
+                let expected: u64 = keys
+                    .iter()
+                    .filter(|k| **k >= from && **k <= to)
+                    .map(|k| *k as u64)
+                    .sum();
+                assert_eq!(sum(aggregate_range(storage, root, from, to)?), expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_insert_remove() -> Result<()> {
+        let t = 3;
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        storage.set_aggregate(Box::new(MockSumAggregate {}));
+        let mut root = Node::new_leaf_with_size(Id(1), t);
+        storage.add_node(&root);
+        assert!(aggregate_range(&mut storage, &root, 0, 100)?.is_none());
+
+        let count = 120;
+        let mut keys = Vec::new();
+        for i in 1..=count {
+            let key = ((i * 7) % count + 1) * 2;
+            root = insert(&mut storage, &root, key, &Record::from_u32(key))?;
+            keys.push(key);
+        }
+        check_ranges(&mut storage, &root, &keys)?;
+
+        for i in 1..=count {
+            let key = ((i * 13) % count + 1) * 2;
+            root = remove_key(&mut storage, &root, key)?;
+            keys.retain(|k| *k != key);
+            if i % 10 == 0 {
+                check_ranges(&mut storage, &root, &keys)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn aggregate_bulk() -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(4));
+        storage.set_aggregate(Box::new(MockSumAggregate {}));
+        let keys: Vec<u32> = (1..=300).collect();
+        let items = keys.iter().map(|k| (*k, Record::from_u32(*k)));
+        let root = bulk::build(&mut storage, items, 0.7)?.unwrap();
+        check_ranges(&mut storage, &root, &keys)
+    }
+
+    #[test]
+    fn aggregate_not_set() {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(3));
+        let root = Node::new_leaf_with_size(Id(1), 3);
+        storage.add_node(&root);
+        assert!(aggregate_range(&mut storage, &root, 0, 100).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{types::Id, Result};
+
+use super::{
+    node::{Node, RcNode},
+    nodestorage::NodeStorage,
+    record::Record,
+    stats::update_child,
+};
+
+/// number of records per node for the fill factor, clamped to the tree limits.
+pub fn fill_count(min_size: usize, t: usize, fill_factor: f32) -> usize {
+    let max_size = 2 * t - 1;
+    let fill = (max_size as f32 * fill_factor).round() as usize;
+    fill.clamp(std::cmp::max(min_size, 2), max_size)
+}
+
+struct LevelBuilder {
+    min_size: usize,
+    nodes: Vec<(RcNode, u32)>,
+}
+
+impl LevelBuilder {
+    fn new(min_size: usize) -> Self {
+        LevelBuilder {
+            min_size,
+            nodes: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, node: RcNode, first_key: u32) {
+        if let Some(last) = self.nodes.last() {
+            let mut last_ref = last.0.borrow_mut();
+            last_ref.right = node.borrow().id;
+            node.borrow_mut().left = last_ref.id;
+        }
+        self.nodes.push((node, first_key));
+    }
+
+    /// fixes the last node, if it is smaller than the minimal size: it is merged into
+    /// the previous node or takes the tail of it.
+    fn balance_tail<Storage: NodeStorage>(
+        &mut self,
+        storage: &mut Storage,
+        max_size: usize,
+    ) -> Result<()> {
+        let count = self.nodes.len();
+        if count < 2 || self.nodes[count - 1].0.borrow().data_count >= self.min_size {
+            return Ok(());
+        }
+        let (last, last_first_key) = self.nodes.pop().unwrap();
+        let prev = self.nodes[count - 2].0.clone();
+        let mut prev_ref = prev.borrow_mut();
+        let mut last_ref = last.borrow_mut();
+        let is_leaf = last_ref.is_leaf;
+
+        // all records of both nodes in order, with the separator between them.
+        let mut keys: Vec<u32> = prev_ref.key_iter().cloned().collect();
+        if !is_leaf {
+            keys.push(last_first_key);
+        }
+        keys.extend(last_ref.key_iter());
+        let mut data: Vec<Record> = prev_ref.data_iter().cloned().collect();
+        data.extend(last_ref.data_iter().cloned());
+        let mut counts = Vec::new();
+        let mut aggregates = Vec::new();
+        if !is_leaf {
+            counts.extend_from_slice(&prev_ref.counts[..prev_ref.data_count]);
+            counts.extend_from_slice(&last_ref.counts[..last_ref.data_count]);
+            aggregates.extend_from_slice(&prev_ref.aggregates[..prev_ref.data_count]);
+            aggregates.extend_from_slice(&last_ref.aggregates[..last_ref.data_count]);
+        }
+
+        let total = data.len();
+        let prev_size = if total <= max_size {
+            total
+        } else {
+            total - total / 2
+        };
+        let prev_keys = if is_leaf { prev_size } else { prev_size - 1 };
+
+        prev_ref.keys_count = prev_keys;
+        prev_ref.data_count = prev_size;
+        prev_ref.keys[..prev_keys].copy_from_slice(&keys[..prev_keys]);
+        prev_ref.data[..prev_size].clone_from_slice(&data[..prev_size]);
+        if !is_leaf {
+            prev_ref.counts[..prev_size].copy_from_slice(&counts[..prev_size]);
+            prev_ref.aggregates[..prev_size].clone_from_slice(&aggregates[..prev_size]);
+        }
+        storage.mark_as_changed(prev_ref.id);
+
+        if prev_size == total {
+            prev_ref.right.clear();
+            if !is_leaf {
+                for d in data.iter() {
+                    let child = storage.get_node(d.into_id())?;
+                    child.borrow_mut().parent = prev_ref.id;
+                }
+            }
+            storage.erase_node(&last_ref.id);
+            return Ok(());
+        }
+
+        // the first key of the last node goes up as separator for internal nodes.
+        let first_key = keys[prev_keys];
+        let last_keys = if is_leaf { prev_keys } else { prev_keys + 1 };
+        last_ref.keys_count = keys.len() - last_keys;
+        last_ref.data_count = total - prev_size;
+        for (i, k) in keys[last_keys..].iter().enumerate() {
+            last_ref.keys[i] = *k;
+        }
+        for (i, d) in data[prev_size..].iter().enumerate() {
+            if !is_leaf {
+                let child = storage.get_node(d.into_id())?;
+                child.borrow_mut().parent = last_ref.id;
+            }
+            last_ref.data[i] = d.clone();
+        }
+        if !is_leaf {
+            last_ref.counts[..total - prev_size].copy_from_slice(&counts[prev_size..]);
+            last_ref.aggregates[..total - prev_size].clone_from_slice(&aggregates[prev_size..]);
+        }
+        storage.mark_as_changed(last_ref.id);
+        drop(last_ref);
+        self.nodes.push((last, first_key));
+        Ok(())
+    }
+}
+
+/// builds a tree bottom-up from records sorted by key. returns the root.
+pub fn build<Storage: NodeStorage, I>(
+    storage: &mut Storage,
+    items: I,
+    fill_factor: f32,
+) -> Result<Option<RcNode>>
+where
+    I: Iterator<Item = (u32, Record)>,
+{
+    let params = *storage.get_params();
+    let capacity = params.get_keys_count();
+    let max_size = 2 * params.get_t() - 1;
+    let mut next_id = storage.get_new_id().0;
+
+    let leaf_fill = fill_count(params.get_min_size_leaf(), params.get_t(), fill_factor);
+    let mut leafs = LevelBuilder::new(params.get_min_size_leaf());
+
+    let mut keys = Vec::with_capacity(leaf_fill);
+    let mut data = Vec::with_capacity(leaf_fill);
+    let mut items = items.peekable();
+    while let Some((key, value)) = items.next() {
+        keys.push(key);
+        data.push(value);
+        if keys.len() == leaf_fill || items.peek().is_none() {
+            let first_key = keys[0];
+            let count = keys.len();
+            keys.resize(capacity, 0u32);
+            data.resize(capacity, Record::Empty);
+            let leaf = Node::new_leaf(
+                Id(next_id),
+                std::mem::replace(&mut keys, Vec::with_capacity(leaf_fill)),
+                std::mem::replace(&mut data, Vec::with_capacity(leaf_fill)),
+                count,
+                count,
+            );
+            next_id += 1;
+            storage.add_node(&leaf);
+            leafs.push(leaf, first_key);
+        }
+    }
+    leafs.balance_tail(storage, max_size)?;
+
+    let mut level = leafs;
+    let node_fill = fill_count(params.get_min_size_node(), params.get_t(), fill_factor);
+    while level.nodes.len() > 1 {
+        let mut upper = LevelBuilder::new(params.get_min_size_node());
+        for children in level.nodes.chunks(node_fill) {
+            let id = Id(next_id);
+            next_id += 1;
+
+            let mut keys = vec![0u32; capacity];
+            let mut data = Record::empty_array(capacity);
+            for (i, child) in children.iter().enumerate() {
+                child.0.borrow_mut().parent = id;
+                data[i] = Record::from_id(child.0.borrow().id);
+                if i > 0 {
+                    keys[i - 1] = child.1;
+                }
+            }
+            let node = Node::new_root(id, keys, data, children.len() - 1, children.len());
+            for child in children.iter() {
+                update_child(storage, &mut node.borrow_mut(), &child.0.borrow())?;
+            }
+            storage.add_node(&node);
+            upper.push(node, children[0].1);
+        }
+        upper.balance_tail(storage, max_size)?;
+        level = upper;
+    }
+
+    match level.nodes.pop() {
+        Some((root, _)) => {
+            root.borrow_mut().parent.clear();
+            Ok(Some(root))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{
+        insert::insert,
+        mocks::MockNodeStorage,
+        order_stat::{check_counts, select},
+        read::{find, map},
+        remove::remove_key,
+        TreeParams,
+    };
+
+    fn check_tree(t: usize, count: u32, fill_factor: f32) -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        let items = (1..=count).map(|k| (k, Record::from_u32(k)));
+        let mut root = build(&mut storage, items, fill_factor)?.unwrap();
+
+        for k in 1..=count {
+            let res = find(&mut storage, &root, k)?;
+            assert_eq!(res.unwrap().into_u32(), k);
+        }
+        let leaf_min = storage.get_params().get_min_size_leaf();
+        let node_min = storage.get_params().get_min_size_node();
+        let all_sized = storage.all(|n| {
+            let n = n.borrow();
+            let min_size = if n.is_leaf { leaf_min } else { node_min };
+            n.parent.is_empty() || (n.data_count >= min_size && n.data_count < 2 * t)
+        });
+        assert!(all_sized);
+        assert_eq!(check_counts(&mut storage, &root)?, count as usize);
+        let middle = select(&mut storage, &root, (count / 2) as usize)?.unwrap();
+        assert_eq!(middle.0, count / 2 + 1);
+
+        let mut mapped = Vec::new();
+        map(&mut storage, &root, 1, count, &mut |k, _v| mapped.push(k))?;
+        assert_eq!(mapped, (1..=count).collect::<Vec<u32>>());
+
+        root = insert(&mut storage, &root, count + 1, &Record::from_u32(count + 1))?;
+        for k in 1..=count + 1 {
+            root = remove_key(&mut storage, &root, k)?;
+            assert_eq!(check_counts(&mut storage, &root)?, (count + 1 - k) as usize);
+            for rest in (k + 1)..=(count + 1) {
+                assert!(find(&mut storage, &root, rest)?.is_some());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn build_empty() -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(3));
+        let root = build(&mut storage, std::iter::empty(), 1.0)?;
+        assert!(root.is_none());
+        assert_eq!(storage.size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn build_single_leaf() -> Result<()> {
+        check_tree(3, 4, 1.0)
+    }
+
+    #[test]
+    fn build_3_full() -> Result<()> {
+        check_tree(3, 200, 1.0)
+    }
+
+    #[test]
+    fn build_4_half() -> Result<()> {
+        check_tree(4, 301, 0.5)
+    }
+
+    #[test]
+    fn build_7_tail() -> Result<()> {
+        for count in 14..60 {
+            check_tree(7, count, 0.9)?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use super::{node::RcNode, nodestorage::NodeStorage, read, record::Record, split::split_node};
+use super::{
+    node::RcNode, nodestorage::NodeStorage, read, record::Record, split::split_node,
+    stats::update_stats_up,
+};
 
 pub fn insert<Storage: NodeStorage>(
     storage: &mut Storage,
@@ -36,6 +39,7 @@ pub fn insert<Storage: NodeStorage>(
         }
         mut_ref.insert_data(index, key, value.clone());
         storage.mark_as_changed(mut_ref.id);
+        update_stats_up(storage, &mut_ref)?;
         if can_insert {
             return Ok(root.clone());
         }
@@ -5,8 +5,10 @@ use crate::{
 use std::{collections::HashMap, rc::Rc};
 
 use super::{
+    aggregate::NodeAggregate,
     node::{NodeKeyCmp, RcNode},
     nodestorage::NodeStorage,
+    record::Record,
     TreeParams,
 };
 
@@ -24,10 +26,26 @@ impl NodeKeyCmp for MockKeyCmp {
     }
 }
 
+/// sum of values as u64.
+pub struct MockSumAggregate {}
+
+impl NodeAggregate for MockSumAggregate {
+    fn summarize(&self, _key: u32, value: &Record) -> crate::Result<Vec<u8>> {
+        Ok((value.into_u32() as u64).to_le_bytes().to_vec())
+    }
+
+    fn combine(&self, left: &[u8], right: &[u8]) -> Vec<u8> {
+        let l = u64::from_le_bytes(left.try_into().unwrap());
+        let r = u64::from_le_bytes(right.try_into().unwrap());
+        (l + r).to_le_bytes().to_vec()
+    }
+}
+
 pub struct MockNodeStorage {
     nodes: HashMap<u32, RcNode>,
     params: TreeParams,
     cmp: MockKeyCmp,
+    aggregate: Option<Box<dyn NodeAggregate>>,
 }
 
 impl MockNodeStorage {
@@ -39,9 +57,14 @@ impl MockNodeStorage {
             nodes: HashMap::new(),
             params,
             cmp: MockKeyCmp::new(),
+            aggregate: None,
         }
     }
 
+    pub fn set_aggregate(&mut self, aggregate: Box<dyn NodeAggregate>) {
+        self.aggregate = Some(aggregate);
+    }
+
     pub fn is_exists(&self, id: Id) -> bool {
         self.nodes.contains_key(&id.0)
     }
@@ -109,4 +132,8 @@ impl NodeStorage for MockNodeStorage {
     }
 
     fn mark_as_changed(&mut self, _id: Id) {}
+
+    fn get_aggregate(&self) -> Option<&dyn NodeAggregate> {
+        self.aggregate.as_deref()
+    }
 }
@@ -1,3 +1,5 @@
+pub mod aggregate;
+pub mod bulk;
 pub mod cursor;
 pub mod debug;
 pub mod insert;
@@ -5,11 +7,14 @@ pub mod map_up_to_tree;
 pub mod mocks;
 pub mod node;
 pub mod nodestorage;
+pub mod order_stat;
 pub mod read;
 pub mod record;
 pub mod remove;
 pub mod rm;
 pub mod split;
+pub mod stats;
+pub mod verify;
 
 #[derive(Clone, Copy, Debug)]
 pub struct TreeParams {
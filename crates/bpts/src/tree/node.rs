@@ -23,6 +23,10 @@ pub struct Node {
     pub data_count: usize,
     pub keys: Vec<u32>,
     pub data: Vec<Record>,
+    /// records count in the subtree of each child, for internal nodes.
+    pub counts: Vec<usize>,
+    /// summary of the subtree of each child, for internal nodes.
+    pub aggregates: Vec<Vec<u8>>,
 }
 
 impl Node {
@@ -37,6 +41,8 @@ impl Node {
         left: Id,
         right: Id,
     ) -> RcNode {
+        let counts = Self::empty_stats(is_leaf, data.len());
+        let aggregates = Self::empty_stats(is_leaf, data.len());
         Rc::new(RefCell::new(Node {
             id: id,
             is_leaf: is_leaf,
@@ -47,6 +53,8 @@ impl Node {
             left,
             parent,
             right,
+            counts,
+            aggregates,
         }))
     }
 
@@ -58,6 +66,8 @@ impl Node {
         keys_count: usize,
         data_count: usize,
     ) -> RcNode {
+        let counts = Self::empty_stats(is_leaf, data.len());
+        let aggregates = Self::empty_stats(is_leaf, data.len());
         Rc::new(RefCell::new(Node {
             id: id,
             is_leaf: is_leaf,
@@ -68,9 +78,19 @@ impl Node {
             left: Id::empty(),
             parent: Id::empty(),
             right: Id::empty(),
+            counts,
+            aggregates,
         }))
     }
 
+    fn empty_stats<T: Clone + Default>(is_leaf: bool, size: usize) -> Vec<T> {
+        if is_leaf {
+            Vec::new()
+        } else {
+            vec![T::default(); size]
+        }
+    }
+
     pub fn copy(other: &Node) -> RcNode {
         Rc::new(RefCell::new(Node {
             id: other.id,
@@ -82,6 +102,8 @@ impl Node {
             left: other.left,
             parent: other.parent,
             right: other.right,
+            counts: Vec::clone(&other.counts),
+            aggregates: Vec::clone(&other.aggregates),
         }))
     }
 
@@ -96,6 +118,8 @@ impl Node {
             left: self.left,
             parent: self.parent,
             right: self.right,
+            counts: Vec::clone(&self.counts),
+            aggregates: Vec::clone(&self.aggregates),
         }))
     }
 
@@ -270,6 +294,8 @@ impl Node {
                 utils::remove_with_shift(&mut self.keys, 0);
                 self.keys_count -= 1;
                 utils::remove_with_shift(&mut self.data, 0);
+                utils::remove_with_shift(&mut self.counts, 0);
+                utils::remove_with_shift(&mut self.aggregates, 0);
                 self.data_count -= 1;
                 return;
             }
@@ -278,6 +304,8 @@ impl Node {
                 utils::remove_with_shift(&mut self.keys, self.keys_count - 1);
                 self.keys_count -= 1;
                 utils::remove_with_shift(&mut self.data, self.data_count - 1);
+                utils::remove_with_shift(&mut self.counts, self.data_count - 1);
+                utils::remove_with_shift(&mut self.aggregates, self.data_count - 1);
                 self.data_count -= 1;
                 return;
             }
@@ -289,6 +317,8 @@ impl Node {
                         self.keys_count -= 1;
                     }
                     utils::remove_with_shift(&mut self.data, i);
+                    utils::remove_with_shift(&mut self.counts, i);
+                    utils::remove_with_shift(&mut self.aggregates, i);
                     self.data_count -= 1;
                     return;
                 }
@@ -298,6 +328,30 @@ impl Node {
         }
     }
 
+    /// records count in the subtree of the node.
+    pub fn subtree_count(&self) -> usize {
+        if self.is_leaf {
+            self.data_count
+        } else {
+            self.counts.iter().take(self.data_count).sum()
+        }
+    }
+
+    /// updates the records count and the summary of the child.
+    /// returns false, if the child is not linked.
+    pub fn set_child_stats(&mut self, child: Id, count: usize, aggregate: Vec<u8>) -> bool {
+        let link = Record::from_id(child);
+        let stats = self.counts.iter_mut().zip(self.aggregates.iter_mut());
+        for (d, (c, a)) in self.data.iter().zip(stats).take(self.data_count) {
+            if *d == link {
+                *c = count;
+                *a = aggregate;
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn first_key(&self) -> u32 {
         if self.keys_count > 0 {
             return self.keys[0];
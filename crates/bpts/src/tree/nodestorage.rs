@@ -1,5 +1,6 @@
 use crate::{
     tree::{
+        aggregate::NodeAggregate,
         node::{NodeKeyCmp, RcNode},
         TreeParams,
     },
@@ -15,4 +16,8 @@ pub trait NodeStorage {
     fn get_params(&self) -> &TreeParams;
     fn get_cmp(&self) -> &dyn NodeKeyCmp;
     fn mark_as_changed(&mut self, id: Id);
+
+    fn get_aggregate(&self) -> Option<&dyn NodeAggregate> {
+        None
+    }
 }
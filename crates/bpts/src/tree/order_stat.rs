@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+
+use crate::Result;
+
+use super::{node::RcNode, nodestorage::NodeStorage, record::Record};
+
+/// count of keys, for which `is_before` is true. such keys must precede all others.
+fn rank_by<Storage: NodeStorage>(
+    storage: &Storage,
+    root: &RcNode,
+    is_before: &dyn Fn(u32) -> Result<bool>,
+) -> Result<usize> {
+    let mut result = 0;
+    let mut target = root.clone();
+    loop {
+        let next;
+        {
+            let node = target.borrow();
+            if node.is_leaf {
+                for k in node.key_iter() {
+                    if !is_before(*k)? {
+                        break;
+                    }
+                    result += 1;
+                }
+                return Ok(result);
+            }
+
+            // keys of the child `i` are not greater than the separator `i`.
+            let mut child = 0;
+            for k in node.key_iter() {
+                if !is_before(*k)? {
+                    break;
+                }
+                result += node.counts[child];
+                child += 1;
+            }
+            next = node.data[child].into_id();
+        }
+        target = storage.get_node(next)?;
+    }
+}
+
+fn rank_impl<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+    inclusive: bool,
+) -> Result<usize> {
+    let storage: &Storage = storage;
+    let cmp = storage.get_cmp();
+    let is_before = |k: u32| {
+        let ord = cmp.compare(k, key);
+        Ok(if inclusive { ord.is_le() } else { ord.is_lt() })
+    };
+    rank_by(storage, root, &is_before)
+}
+
+/// count of keys less than `key`.
+pub fn rank<Storage: NodeStorage>(storage: &mut Storage, root: &RcNode, key: u32) -> Result<usize> {
+    rank_impl(storage, root, key, false)
+}
+
+/// count of keys less than or equal to `key`.
+pub fn rank_le<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+) -> Result<usize> {
+    rank_impl(storage, root, key, true)
+}
+
+/// count of keys in [from, to].
+pub fn count_range<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    from: u32,
+    to: u32,
+) -> Result<usize> {
+    let to_rank = rank_le(storage, root, to)?;
+    let from_rank = rank(storage, root, from)?;
+    Ok(to_rank.saturating_sub(from_rank))
+}
+
+/// count of keys, for which `position` returns Equal.
+/// keys before the range must be Less, keys after the range - Greater.
+pub fn count_by<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    position: &dyn Fn(u32) -> Result<Ordering>,
+) -> Result<usize> {
+    let storage: &Storage = storage;
+    let before = rank_by(storage, root, &|k| Ok(position(k)?.is_lt()))?;
+    let not_after = rank_by(storage, root, &|k| Ok(!position(k)?.is_gt()))?;
+    Ok(not_after.saturating_sub(before))
+}
+
+/// n-th (from zero) key in the key order.
+pub fn select<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    n: usize,
+) -> Result<Option<(u32, Record)>> {
+    let mut n = n;
+    let mut target = root.clone();
+    loop {
+        let next;
+        {
+            let node = target.borrow();
+            if node.is_leaf {
+                if n < node.data_count {
+                    return Ok(Some((node.keys[n], node.data[n].clone())));
+                }
+                return Ok(None);
+            }
+
+            let mut child = None;
+            for i in 0..node.data_count {
+                if n < node.counts[i] {
+                    child = Some(i);
+                    break;
+                }
+                n -= node.counts[i];
+            }
+            match child {
+                Some(i) => next = node.data[i].into_id(),
+                None => return Ok(None),
+            }
+        }
+        target = storage.get_node(next)?;
+    }
+}
+
+/// checks, that the counts of all internal nodes match their subtrees.
+pub fn check_counts<Storage: NodeStorage>(storage: &mut Storage, root: &RcNode) -> Result<usize> {
+    let node = root.borrow();
+    if node.is_leaf {
+        return Ok(node.data_count);
+    }
+    let mut result = 0;
+    for i in 0..node.data_count {
+        let child = storage.get_node(node.data[i].into_id())?;
+        let count = check_counts(storage, &child)?;
+        if count != node.counts[i] {
+            return Err(crate::Error::Fail(format!(
+                "wrong count of child {:?} in {:?}: {} != {}",
+                node.data[i].into_id(),
+                node.id,
+                node.counts[i],
+                count
+            )));
+        }
+        result += count;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{
+        insert::insert, mocks::MockNodeStorage, node::Node, remove::remove_key, TreeParams,
+    };
+    use crate::types::Id;
+
+    fn check_queries(storage: &mut MockNodeStorage, root: &RcNode, keys: &[u32]) -> Result<()> {
+        assert_eq!(check_counts(storage, root)?, keys.len());
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(rank(storage, root, *k)?, i);
+            assert_eq!(rank_le(storage, root, *k)?, i + 1);
+            assert_eq!(rank(storage, root, *k + 1)?, i + 1);
+            let (key, value) = select(storage, root, i)?.unwrap();
+            assert_eq!(key, *k);
+            assert_eq!(value.into_u32(), *k);
+        }
+        assert!(select(storage, root, keys.len())?.is_none());
+        if keys.len() > 3 {
+            let from = keys[1];
+            let to = keys[keys.len() - 2];
+            assert_eq!(count_range(storage, root, from, to)?, keys.len() - 2);
+            assert_eq!(
+                count_range(storage, root, from - 1, to + 1)?,
+                keys.len() - 2
+            );
+            assert_eq!(count_range(storage, root, to, from)?, 0);
+
+            let position = |k: u32| {
+                Ok(if k < from {
+                    Ordering::Less
+                } else if k > to {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                })
+            };
+            assert_eq!(count_by(storage, root, &position)?, keys.len() - 2);
+        }
+        Ok(())
+    }
+
+    fn insert_and_remove(t: usize, count: u32, remove_order: &[u32]) -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        let mut root = Node::new_leaf_with_size(Id(1), t);
+        storage.add_node(&root);
+
+        // keys are even, to query the gaps between them.
+        let mut keys = Vec::new();
+        for i in 1..=count {
+            let key = ((i * 7) % count + 1) * 2;
+            root = insert(&mut storage, &root, key, &Record::from_u32(key))?;
+            keys.push(key);
+        }
+        keys.sort();
+        check_queries(&mut storage, &root, &keys)?;
+
+        for key in remove_order {
+            root = remove_key(&mut storage, &root, *key)?;
+            keys.retain(|k| k != key);
+            check_queries(&mut storage, &root, &keys)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn order_stat_forward() -> Result<()> {
+        let order: Vec<u32> = (1..=100).map(|i| i * 2).collect();
+        insert_and_remove(3, 100, &order)
+    }
+
+    #[test]
+    fn order_stat_backward() -> Result<()> {
+        let order: Vec<u32> = (1..=100).rev().map(|i| i * 2).collect();
+        insert_and_remove(3, 100, &order)
+    }
+
+    #[test]
+    fn order_stat_middle() -> Result<()> {
+        let order: Vec<u32> = (1..=150).map(|i| ((i * 37) % 150 + 1) * 2).collect();
+        insert_and_remove(4, 150, &order)
+    }
+
+    #[test]
+    fn order_stat_empty() -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(3));
+        let root = Node::new_leaf_with_size(Id(1), 3);
+        storage.add_node(&root);
+        assert_eq!(rank(&mut storage, &root, 10)?, 0);
+        assert_eq!(count_range(&mut storage, &root, 1, 10)?, 0);
+        assert!(select(&mut storage, &root, 0)?.is_none());
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
+use std::cmp::Ordering;
 use std::rc::Rc;
 
 use crate::tree::cursor;
 use crate::types::{self};
 use crate::{Error, Result};
 
-use super::node::RcNode;
+use super::node::{NodeKeyCmp, RcNode};
 use super::nodestorage::NodeStorage;
 use super::record::Record;
 
@@ -125,6 +126,189 @@ where
     Ok(())
 }
 
+/// calls `f` for the records, for which `position` returns Equal, in the key order.
+/// keys before the range must be Less, keys after the range - Greater.
+pub fn map_by<F, Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    position: &dyn Fn(u32) -> Result<Ordering>,
+    f: &mut F,
+) -> Result<()>
+where
+    F: FnMut(u32, &Record),
+{
+    // keys of the child `i` are in [keys[i-1], keys[i]].
+    let mut target = Rc::clone(root);
+    loop {
+        let next = {
+            let target_ref = target.borrow();
+            if target_ref.is_leaf {
+                break;
+            }
+            let mut child = target_ref.data_count - 1;
+            for i in 0..target_ref.keys_count {
+                if !position(target_ref.keys[i])?.is_lt() {
+                    child = i;
+                    break;
+                }
+            }
+            target_ref.data[child].into_id()
+        };
+        target = storage.get_node(next)?;
+    }
+
+    loop {
+        let right = {
+            let target_ref = target.borrow();
+            for (k, v) in target_ref.key_iter().zip(target_ref.data_iter()) {
+                match position(*k)? {
+                    Ordering::Less => continue,
+                    Ordering::Equal => f(*k, v),
+                    Ordering::Greater => return Ok(()),
+                }
+            }
+            target_ref.right
+        };
+        if right.is_empty() {
+            return Ok(());
+        }
+        target = storage.get_node(right)?;
+    }
+}
+
+/// the first key and record from the leaf `node` backward, for which `accept` is true.
+fn search_backward<Storage: NodeStorage>(
+    storage: &mut Storage,
+    node: RcNode,
+    accept: &dyn Fn(&dyn NodeKeyCmp, u32) -> bool,
+) -> Result<Option<(u32, Record)>> {
+    let mut target = node;
+    loop {
+        let left;
+        {
+            let cmp = storage.get_cmp();
+            let target_ref = target.borrow();
+            for i in (0..target_ref.keys_count).rev() {
+                if accept(cmp, target_ref.keys[i]) {
+                    return Ok(Some((target_ref.keys[i], target_ref.data[i].clone())));
+                }
+            }
+            left = target_ref.left;
+        }
+        if left.is_empty() {
+            return Ok(None);
+        }
+        target = storage.get_node(left)?;
+    }
+}
+
+/// the first key and record from the leaf `node` forward, for which `accept` is true.
+fn search_forward<Storage: NodeStorage>(
+    storage: &mut Storage,
+    node: RcNode,
+    accept: &dyn Fn(&dyn NodeKeyCmp, u32) -> bool,
+) -> Result<Option<(u32, Record)>> {
+    let mut target = node;
+    loop {
+        let right;
+        {
+            let cmp = storage.get_cmp();
+            let target_ref = target.borrow();
+            for i in 0..target_ref.keys_count {
+                if accept(cmp, target_ref.keys[i]) {
+                    return Ok(Some((target_ref.keys[i], target_ref.data[i].clone())));
+                }
+            }
+            right = target_ref.right;
+        }
+        if right.is_empty() {
+            return Ok(None);
+        }
+        target = storage.get_node(right)?;
+    }
+}
+
+/// the greatest key less than or equal to `key`.
+pub fn floor<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+) -> Result<Option<(u32, Record)>> {
+    let node = scan(storage, root, key)?;
+    search_backward(storage, node, &|cmp, k| cmp.compare(k, key).is_le())
+}
+
+/// the greatest key less than `key`.
+pub fn lower<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+) -> Result<Option<(u32, Record)>> {
+    let node = scan(storage, root, key)?;
+    search_backward(storage, node, &|cmp, k| cmp.compare(k, key).is_lt())
+}
+
+/// the smallest key greater than or equal to `key`.
+pub fn ceiling<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+) -> Result<Option<(u32, Record)>> {
+    let node = scan(storage, root, key)?;
+    search_forward(storage, node, &|cmp, k| cmp.compare(k, key).is_ge())
+}
+
+/// the smallest key greater than `key`.
+pub fn higher<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    key: u32,
+) -> Result<Option<(u32, Record)>> {
+    let node = scan(storage, root, key)?;
+    search_forward(storage, node, &|cmp, k| cmp.compare(k, key).is_gt())
+}
+
+/// the leftmost or the rightmost leaf.
+fn edge_leaf<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    leftmost: bool,
+) -> Result<RcNode> {
+    let mut target = Rc::clone(root);
+    loop {
+        let next = {
+            let target_ref = target.borrow();
+            if target_ref.is_leaf {
+                return Ok(Rc::clone(&target));
+            }
+            if leftmost {
+                target_ref.first_data().into_id()
+            } else {
+                target_ref.last_data().into_id()
+            }
+        };
+        target = storage.get_node(next)?;
+    }
+}
+
+/// the smallest key of the tree.
+pub fn first<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+) -> Result<Option<(u32, Record)>> {
+    let node = edge_leaf(storage, root, true)?;
+    search_forward(storage, node, &|_, _| true)
+}
+
+/// the greatest key of the tree.
+pub fn last<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+) -> Result<Option<(u32, Record)>> {
+    let node = edge_leaf(storage, root, false)?;
+    search_backward(storage, node, &|_, _| true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -167,4 +351,90 @@ mod tests {
         assert_eq!(res_2.unwrap().into_u32(), 2);
         return Ok(());
     }
+
+    #[test]
+    fn neighbour_lookups() -> Result<()> {
+        let t = 3;
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        let mut root = Node::new_leaf_with_size(types::Id(1), t);
+        storage.add_node(&root);
+        assert!(first(&mut storage, &root)?.is_none());
+        assert!(last(&mut storage, &root)?.is_none());
+        assert!(floor(&mut storage, &root, 10)?.is_none());
+        assert!(ceiling(&mut storage, &root, 10)?.is_none());
+
+        // keys are even, to query the gaps between them.
+        for i in 1..=100u32 {
+            let key = ((i * 7) % 100 + 1) * 2;
+            root = crate::tree::insert::insert(&mut storage, &root, key, &Record::from_u32(key))?;
+        }
+        let key_of = |r: Option<(u32, Record)>| {
+            r.map(|(k, v)| {
+                assert_eq!(k, v.into_u32());
+                k
+            })
+        };
+
+        assert_eq!(key_of(first(&mut storage, &root)?), Some(2));
+        assert_eq!(key_of(last(&mut storage, &root)?), Some(200));
+        for x in 0..=202u32 {
+            let even_below = if x % 2 == 0 { x } else { x - 1 };
+            let expected_floor = if x < 2 {
+                None
+            } else {
+                Some(even_below.min(200))
+            };
+            let expected_lower = if x <= 2 {
+                None
+            } else {
+                Some((x - 1 - (x - 1) % 2).min(200))
+            };
+            let expected_ceiling = if x > 200 {
+                None
+            } else {
+                Some((x + x % 2).max(2))
+            };
+            let expected_higher = if x >= 200 {
+                None
+            } else {
+                Some((x + 2 - x % 2).max(2))
+            };
+            assert_eq!(key_of(floor(&mut storage, &root, x)?), expected_floor);
+            assert_eq!(key_of(lower(&mut storage, &root, x)?), expected_lower);
+            assert_eq!(key_of(ceiling(&mut storage, &root, x)?), expected_ceiling);
+            assert_eq!(key_of(higher(&mut storage, &root, x)?), expected_higher);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn map_by_position() -> Result<()> {
+        let t = 3;
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        let mut root = Node::new_leaf_with_size(types::Id(1), t);
+        storage.add_node(&root);
+        for key in 1..=200u32 {
+            root = crate::tree::insert::insert(&mut storage, &root, key, &Record::from_u32(key))?;
+        }
+
+        for (from, to) in [(0, 0), (1, 1), (10, 20), (150, 300), (0, 500)] {
+            let position = |k: u32| {
+                Ok(if k < from {
+                    Ordering::Less
+                } else if k > to {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                })
+            };
+            let mut keys = Vec::new();
+            map_by(&mut storage, &root, &position, &mut |k, v| {
+                assert_eq!(k, v.into_u32());
+                keys.push(k);
+            })?;
+            let expected: Vec<u32> = (from.max(1)..=to.min(200)).collect();
+            assert_eq!(keys, expected);
+        }
+        Ok(())
+    }
 }
@@ -1,5 +1,13 @@
+use std::cmp::Ordering;
+
 use crate::{
-    tree::{node::RcNode, nodestorage::NodeStorage, read, record::Record, rm::erase_key},
+    tree::{
+        node::RcNode,
+        nodestorage::NodeStorage,
+        read,
+        record::Record,
+        rm::{erase_key, unlink::unlink_subtree},
+    },
     verbose,
 };
 
@@ -47,6 +55,149 @@ pub fn remove_key<Storage: NodeStorage>(
     }
 }
 
+enum RangeTarget {
+    /// the node and all its records are in the range.
+    Subtree(RcNode),
+    /// a key of a boundary leaf.
+    Key(RcNode, u32),
+}
+
+enum RangeBounds<'a> {
+    Keys(u32, u32),
+    By(&'a dyn Fn(u32) -> crate::Result<Ordering>),
+}
+
+impl<'a> RangeBounds<'a> {
+    fn position<Storage: NodeStorage>(
+        &self,
+        storage: &Storage,
+        key: u32,
+    ) -> crate::Result<Ordering> {
+        match self {
+            RangeBounds::Keys(from, to) => {
+                let cmp = storage.get_cmp();
+                if cmp.compare(key, *from).is_lt() {
+                    Ok(Ordering::Less)
+                } else if cmp.compare(key, *to).is_gt() {
+                    Ok(Ordering::Greater)
+                } else {
+                    Ok(Ordering::Equal)
+                }
+            }
+            RangeBounds::By(position) => position(key),
+        }
+    }
+}
+
+/// keys of the node are in [low, high]. None - unknown bound.
+fn find_range_target<Storage: NodeStorage>(
+    storage: &Storage,
+    node: &RcNode,
+    bounds: &RangeBounds,
+    low: Option<u32>,
+    high: Option<u32>,
+) -> crate::Result<Option<RangeTarget>> {
+    let position = |k: u32| bounds.position(storage, k);
+    let node_ref = node.borrow();
+    if node_ref.is_leaf {
+        let mut first = None;
+        let mut all = true;
+        for k in node_ref.key_iter() {
+            if position(*k)?.is_eq() {
+                first = first.or(Some(*k));
+            } else {
+                all = false;
+            }
+        }
+        return Ok(match first {
+            None => None,
+            Some(_) if all && node_ref.parent.exists() => Some(RangeTarget::Subtree(node.clone())),
+            Some(k) => Some(RangeTarget::Key(node.clone(), k)),
+        });
+    }
+
+    let is_in = |k: Option<u32>| match k {
+        Some(k) => position(k).map(|p| p.is_eq()),
+        None => Ok(false),
+    };
+    // keys of the child `i` are in [keys[i-1], keys[i]].
+    for i in 0..node_ref.data_count {
+        let child_low = if i == 0 {
+            low
+        } else {
+            Some(node_ref.keys[i - 1])
+        };
+        let child_high = if i + 1 < node_ref.data_count {
+            Some(node_ref.keys[i])
+        } else {
+            high
+        };
+        if let Some(h) = child_high {
+            if position(h)?.is_lt() {
+                continue;
+            }
+        }
+        if let Some(l) = child_low {
+            if position(l)?.is_gt() {
+                break;
+            }
+        }
+
+        let child = storage.get_node(node_ref.data[i].into_id())?;
+        if is_in(child_low)? && is_in(child_high)? {
+            return Ok(Some(RangeTarget::Subtree(child)));
+        }
+        if let Some(t) = find_range_target(storage, &child, bounds, child_low, child_high)? {
+            return Ok(Some(t));
+        }
+    }
+    Ok(None)
+}
+
+/// removes the records, for which `position` returns Equal. keys before the range must be Less,
+/// keys after the range - Greater. subtrees inside the range are unlinked without visiting
+/// their leafs, keys of the boundary leafs are removed one by one.
+/// returns the count of removed records and the new root.
+pub fn delete_range_by<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    position: &dyn Fn(u32) -> crate::Result<Ordering>,
+) -> crate::Result<(usize, RcNode)> {
+    delete_range_impl(storage, root, &RangeBounds::By(position))
+}
+
+/// removes the records with keys in [from, to].
+pub fn delete_range<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    from: u32,
+    to: u32,
+) -> crate::Result<(usize, RcNode)> {
+    delete_range_impl(storage, root, &RangeBounds::Keys(from, to))
+}
+
+fn delete_range_impl<Storage: NodeStorage>(
+    storage: &mut Storage,
+    root: &RcNode,
+    bounds: &RangeBounds,
+) -> crate::Result<(usize, RcNode)> {
+    let mut root = root.clone();
+    let mut removed = 0;
+    loop {
+        match find_range_target(storage, &root, bounds, None, None)? {
+            None => return Ok((removed, root)),
+            Some(RangeTarget::Subtree(node)) => {
+                removed += node.borrow().subtree_count();
+                root = unlink_subtree(storage, &node, root)?;
+            }
+            Some(RangeTarget::Key(leaf, key)) => {
+                removed += 1;
+                root = erase_key(storage, &leaf, key, Some(root))?;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
 
@@ -1147,6 +1298,11 @@ pub(crate) mod tests {
         }
         Ok(())
     }
+    #[test]
+    fn many_inserts_2_22() -> Result<()> {
+        many_inserts(2, 22)
+    }
+
     #[test]
     fn many_inserts_3_22() -> Result<()> {
         many_inserts(3, 22)
@@ -1162,6 +1318,11 @@ pub(crate) mod tests {
         many_inserts(16, 22)
     }
 
+    #[test]
+    fn many_inserts_rev_2_22() -> Result<()> {
+        many_inserts_rev(2, 22)
+    }
+
     #[test]
     fn many_inserts_rev_3_22() -> Result<()> {
         many_inserts_rev(3, 22)
@@ -1177,6 +1338,11 @@ pub(crate) mod tests {
         many_inserts_rev(16, 22)
     }
 
+    #[test]
+    fn many_inserts_middle_range_2_22() -> Result<()> {
+        many_inserts_middle_range(2, 22)
+    }
+
     #[test]
     fn many_inserts_middle_range_3_22() -> Result<()> {
         many_inserts_middle_range(3, 22)
@@ -1316,4 +1482,95 @@ pub(crate) mod tests {
         remove_by_list(4, nums)?;
         Ok(())
     }
+
+    /// separators must be the first keys of their right subtrees. returns the first key.
+    fn check_separators(storage: &MockNodeStorage, node: &RcNode) -> Result<Option<u32>> {
+        let node_ref = node.borrow();
+        if node_ref.is_leaf {
+            return Ok(node_ref.key_iter().next().cloned());
+        }
+        let mut first = None;
+        for i in 0..node_ref.data_count {
+            let child = storage.get_node(node_ref.data[i].into_id())?;
+            let child_first = check_separators(storage, &child)?;
+            if i == 0 {
+                first = child_first;
+            } else {
+                assert_eq!(child_first, Some(node_ref.keys[i - 1]));
+            }
+        }
+        Ok(first)
+    }
+
+    fn count_nodes(storage: &MockNodeStorage, node: &RcNode) -> Result<usize> {
+        let node_ref = node.borrow();
+        let mut result = 1;
+        if !node_ref.is_leaf {
+            for d in node_ref.data_iter() {
+                result += count_nodes(storage, &storage.get_node(d.into_id())?)?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn check_range_delete(t: usize, count: u32, ranges: &[(u32, u32)]) -> Result<()> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        storage.set_aggregate(Box::new(crate::tree::mocks::MockSumAggregate {}));
+        let mut root = Node::new_leaf_with_size(Id(1), t);
+        storage.add_node(&root);
+        let mut keys = Vec::new();
+        for i in 1..=count {
+            let key = ((i * 7) % count + 1) * 2;
+            root = insert::insert(&mut storage, &root, key, &Record::from_u32(key))?;
+            keys.push(key);
+        }
+        keys.sort();
+
+        for (from, to) in ranges {
+            let (removed, new_root) = super::delete_range(&mut storage, &root, *from, *to)?;
+            root = new_root;
+            let before = keys.len();
+            keys.retain(|k| k < from || k > to);
+            assert_eq!(removed, before - keys.len());
+
+            assert!(crate::tree::verify::check(&mut storage, &root)?.is_empty());
+            assert_eq!(
+                crate::tree::order_stat::check_counts(&mut storage, &root)?,
+                keys.len()
+            );
+            crate::tree::aggregate::check_aggregates(&mut storage, &root)?;
+            check_separators(&storage, &root)?;
+
+            let mut all = Vec::new();
+            map(&mut storage, &root, 0, u32::MAX, &mut |k, _| all.push(k))?;
+            assert_eq!(all, keys);
+            for k in keys.iter() {
+                assert_eq!(find(&mut storage, &root, *k)?.unwrap().into_u32(), *k);
+            }
+            // unlinked nodes are erased from the storage.
+            assert_eq!(count_nodes(&storage, &root)?, storage.size());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn delete_range_middle() -> Result<()> {
+        let ranges = [(1, 1), (100, 200), (301, 700), (50, 51), (80, 1000)];
+        check_range_delete(3, 600, &ranges)?;
+        check_range_delete(4, 600, &ranges)?;
+        check_range_delete(7, 600, &ranges)
+    }
+
+    #[test]
+    fn delete_range_edges() -> Result<()> {
+        let ranges = [(0, 40), (1150, 5000), (0, 300), (900, 1100)];
+        check_range_delete(3, 600, &ranges)?;
+        check_range_delete(5, 600, &ranges)
+    }
+
+    #[test]
+    fn delete_range_all() -> Result<()> {
+        check_range_delete(3, 600, &[(0, u32::MAX), (0, u32::MAX)])?;
+        check_range_delete(4, 10, &[(5, 5), (0, u32::MAX)])
+    }
 }
@@ -2,6 +2,7 @@ use crate::{
     tree::{
         node::{Node, NodeKeyCmp, RcNode},
         nodestorage::NodeStorage,
+        stats::update_stats_up,
     },
     utils::*,
     verbose,
@@ -13,6 +14,21 @@ pub mod move_to;
 pub mod rebalancing;
 pub mod rollup;
 pub mod take_from;
+pub mod unlink;
+
+/// a key of the subtree of the node, to find the key of the node in its parent.
+/// an internal node with one child has no keys, so the key is taken from the child.
+pub(super) fn subtree_key<Storage: NodeStorage>(
+    storage: &mut Storage,
+    node: &Node,
+) -> crate::Result<u32> {
+    if node.keys_count > 0 || node.is_leaf {
+        return Ok(node.first_key());
+    }
+    let child = storage.get_node(node.first_data().into_id())?;
+    let child_ref = child.borrow();
+    subtree_key(storage, &child_ref)
+}
 
 fn erase_from_node(cmp: &dyn NodeKeyCmp, target: &mut Node, key: u32) {
     let is_leaf = target.is_leaf;
@@ -66,6 +82,7 @@ pub(super) fn erase_key<Storage: NodeStorage>(
             let cmp = storage.get_cmp();
             erase_from_node(cmp, &mut target_ref, key);
         }
+        update_stats_up(storage, &target_ref)?;
         let mut changed_nodes = None;
         {
             let cmp = storage.get_cmp();
@@ -4,7 +4,7 @@ use crate::{
     verbose,
 };
 
-use super::rollup::rollup_keys;
+use super::{rollup::rollup_keys, subtree_key};
 
 pub(super) fn move_to_lower(
     target_node: &mut Node,
@@ -35,6 +35,12 @@ pub(super) fn move_to_lower(
     for (num, data) in target_node.data_iter().enumerate() {
         low_side_node.data[low_data_count + num] = data.clone();
     }
+    if !target_node.is_leaf {
+        for num in 0..target_node.data_count {
+            low_side_node.counts[low_data_count + num] = target_node.counts[num];
+            low_side_node.aggregates[low_data_count + num] = target_node.aggregates[num].clone();
+        }
+    }
 
     low_side_node.keys_count += target_node.keys_count;
     low_side_node.data_count += target_node.data_count;
@@ -67,6 +73,8 @@ pub(super) fn move_to_higher(
         insert_to_array(&mut high_side.data, i, data.clone());
 
         if !target.is_leaf {
+            insert_to_array(&mut high_side.counts, i, target.counts[i]);
+            insert_to_array(&mut high_side.aggregates, i, target.aggregates[i].clone());
             let node = storage.get_node(data.into_id()).unwrap();
             node.borrow_mut().parent = high_side.id;
             storage.mark_as_changed(node.borrow().id);
@@ -85,7 +93,7 @@ pub(super) fn try_move_to_low<Storage: NodeStorage>(
     t: usize,
 ) -> crate::Result<bool> {
     if (leaf_ref.keys_count + target_ref.keys_count) < 2 * t {
-        let first_key = target_ref.first_key();
+        let first_key = subtree_key(storage, target_ref)?;
         let mut middle: Option<u32> = None;
 
         let mut new_min_of_parent: Option<u32> = None;
@@ -1,15 +1,31 @@
 use crate::{
     tree::{
-        node::RcNode,
+        node::{Node, RcNode},
         nodestorage::NodeStorage,
         rm::{
             move_to::{try_move_to_high, try_move_to_low},
             take_from::{try_take_from_high, try_take_from_low},
         },
+        stats::update_child,
     },
     verbose,
 };
 
+/// updates the records counts and summaries of the target and its brother in their common parent.
+fn update_stats_in_parent<Storage: NodeStorage>(
+    storage: &mut Storage,
+    target: &Node,
+    brother: &Node,
+) -> crate::Result<()> {
+    if target.parent.exists() {
+        let parent = storage.get_node(target.parent)?;
+        let mut parent_ref = parent.borrow_mut();
+        update_child(storage, &mut parent_ref, target)?;
+        update_child(storage, &mut parent_ref, brother)?;
+    }
+    Ok(())
+}
+
 pub(in super::super) fn rebalancing<Storage: NodeStorage>(
     storage: &mut Storage,
     target: &RcNode,
@@ -28,7 +44,8 @@ pub(in super::super) fn rebalancing<Storage: NodeStorage>(
     if target_ref.data_count >= t {
         return Ok(root.unwrap());
     }
-    if target_ref.keys_count == 0 || target_ref.data_count == 0 {
+    // an internal node with one child has no keys, but is rebalanced as the others.
+    if target_ref.data_count == 0 || (target_ref.keys_count == 0 && target_ref.parent.is_empty()) {
         if target_ref.parent.is_empty() {
             if target_ref.data_count > 0 && !target_ref.is_leaf {
                 storage.erase_node(&target_ref.id);
@@ -69,6 +86,7 @@ pub(in super::super) fn rebalancing<Storage: NodeStorage>(
         let low_side_leaf = link_to_low.clone().unwrap();
         let mut leaf_ref = low_side_leaf.borrow_mut();
         if try_take_from_low(storage, &mut target_ref, &mut leaf_ref, t)? {
+            update_stats_in_parent(storage, &target_ref, &leaf_ref)?;
             return Ok(root.unwrap());
         }
     }
@@ -79,6 +97,7 @@ pub(in super::super) fn rebalancing<Storage: NodeStorage>(
         let mut leaf_ref = high_side_leaf.borrow_mut();
 
         if try_take_from_high(storage, &mut target_ref, &mut leaf_ref, t)? {
+            update_stats_in_parent(storage, &target_ref, &leaf_ref)?;
             return Ok(root.unwrap());
         }
     }
@@ -89,12 +108,18 @@ pub(in super::super) fn rebalancing<Storage: NodeStorage>(
         let low_side = link_to_low.clone().unwrap();
         let mut leaf_ref = low_side.borrow_mut();
         update_parent = try_move_to_low(storage, &mut target_ref, &mut leaf_ref, t)?;
+        if update_parent {
+            update_stats_in_parent(storage, &target_ref, &leaf_ref)?;
+        }
     }
 
     if !update_parent && link_to_high.is_some() {
         let high_side = link_to_high.unwrap();
         let mut leaf_ref = high_side.borrow_mut();
         update_parent = try_move_to_high(storage, &mut target_ref, &mut leaf_ref, t)?;
+        if update_parent {
+            update_stats_in_parent(storage, &target_ref, &leaf_ref)?;
+        }
     }
 
     if update_parent && target_ref.parent.exists() {
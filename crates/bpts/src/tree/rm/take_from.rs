@@ -4,7 +4,7 @@ use crate::{
     verbose,
 };
 
-use super::rollup::rollup_keys;
+use super::{rollup::rollup_keys, subtree_key};
 
 pub(super) fn take_from_low<Storage: NodeStorage>(
     storage: &mut Storage,
@@ -34,6 +34,12 @@ pub(super) fn take_from_low<Storage: NodeStorage>(
     }
 
     insert_to_array(&mut target.data, 0, max_data);
+    if !target.is_leaf {
+        let last = low_side.data_count - 1;
+        insert_to_array(&mut target.counts, 0, low_side.counts[last]);
+        let aggregate = std::mem::take(&mut low_side.aggregates[last]);
+        insert_to_array(&mut target.aggregates, 0, aggregate);
+    }
     low_side.keys_count -= 1;
     low_side.data_count -= 1;
     storage.mark_as_changed(low_side.id);
@@ -60,6 +66,12 @@ pub(super) fn take_from_high(target: &mut Node, high_side: &mut Node, middle: Op
         target.keys[position] = min_key;
         position = target.data_count;
         target.data[position] = min_data;
+        if !target.is_leaf {
+            target.counts[position] = high_side.counts[0];
+            target.aggregates[position] = std::mem::take(&mut high_side.aggregates[0]);
+            remove_with_shift(&mut high_side.counts, 0);
+            remove_with_shift(&mut high_side.aggregates, 0);
+        }
 
         remove_with_shift(&mut high_side.keys, 0);
         remove_with_shift(&mut high_side.data, 0);
@@ -84,7 +96,7 @@ pub(super) fn try_take_from_low<Storage: NodeStorage>(
 ) -> crate::Result<bool> {
     if leaf_ref.data_count > t {
         let mut middle: Option<u32> = None;
-        let mut first_key = target_ref.first_key();
+        let mut first_key = subtree_key(storage, target_ref)?;
         let taken_key = leaf_ref.keys[leaf_ref.keys_count - 1];
         if !target_ref.is_leaf {
             if leaf_ref.parent == target_ref.parent {
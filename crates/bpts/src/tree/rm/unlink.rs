@@ -0,0 +1,108 @@
+use crate::{
+    tree::{
+        node::RcNode,
+        nodestorage::NodeStorage,
+        rm::{rebalancing::rebalancing, rollup::rollup_keys},
+        stats::update_stats_up,
+    },
+    types::Id,
+    verbose,
+};
+
+/// links the neighbours of the subtree on each level and returns the levels count
+/// and the first key of the subtree.
+fn relink_levels<Storage: NodeStorage>(
+    storage: &mut Storage,
+    target: &RcNode,
+) -> crate::Result<(usize, u32)> {
+    let mut low = target.clone();
+    let mut high = target.clone();
+    let mut levels = 1;
+    loop {
+        let left = low.borrow().left;
+        let right = high.borrow().right;
+        if left.exists() {
+            let node = storage.get_node(left)?;
+            node.borrow_mut().right = right;
+            storage.mark_as_changed(left);
+        }
+        if right.exists() {
+            let node = storage.get_node(right)?;
+            node.borrow_mut().left = left;
+            storage.mark_as_changed(right);
+        }
+
+        if low.borrow().is_leaf {
+            let first_key = low.borrow().first_key();
+            return Ok((levels, first_key));
+        }
+        let next_low = storage.get_node(low.borrow().first_data().into_id())?;
+        let next_high = storage.get_node(high.borrow().last_data().into_id())?;
+        low = next_low;
+        high = next_high;
+        levels += 1;
+    }
+}
+
+/// erases all nodes of the subtree. leafs are not loaded.
+fn erase_subtree<Storage: NodeStorage>(
+    storage: &mut Storage,
+    target: &RcNode,
+    levels: usize,
+) -> crate::Result<()> {
+    let mut erased = vec![target.borrow().id];
+    let mut level = vec![target.clone()];
+    for depth in 1..levels {
+        let mut next_level = Vec::new();
+        for node in level.iter() {
+            for d in node.borrow().data_iter() {
+                let child = d.into_id();
+                erased.push(child);
+                if depth + 1 < levels {
+                    next_level.push(storage.get_node(child)?);
+                }
+            }
+        }
+        level = next_level;
+    }
+
+    for id in erased {
+        storage.erase_node(&id);
+        storage.mark_as_changed(id);
+    }
+    Ok(())
+}
+
+/// removes the node with its whole subtree from the tree and rebalances the parent.
+pub(in super::super) fn unlink_subtree<Storage: NodeStorage>(
+    storage: &mut Storage,
+    target: &RcNode,
+    root: RcNode,
+) -> crate::Result<RcNode> {
+    let (id, parent_id): (Id, Id) = {
+        let target_ref = target.borrow();
+        (target_ref.id, target_ref.parent)
+    };
+    verbose!("unlink subtree Id={:?} parent={:?}", id, parent_id);
+
+    let (levels, first_key) = relink_levels(storage, target)?;
+    erase_subtree(storage, target, levels)?;
+
+    let parent = storage.get_node(parent_id)?;
+    {
+        let mut parent_ref = parent.borrow_mut();
+        let is_first = parent_ref.first_data().into_id() == id;
+        let new_first_key = parent_ref.first_key();
+        parent_ref.erase_link(id);
+        storage.mark_as_changed(parent_id);
+        update_stats_up(storage, &parent_ref)?;
+
+        if is_first && parent_ref.parent.exists() {
+            let changed_nodes = rollup_keys(storage, parent_ref.parent, first_key, new_first_key)?;
+            for i in changed_nodes {
+                storage.mark_as_changed(i);
+            }
+        }
+    }
+    rebalancing(storage, &parent, Some(root))
+}
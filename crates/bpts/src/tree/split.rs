@@ -4,6 +4,7 @@ use super::{
     node::{Node, RcNode},
     nodestorage::NodeStorage,
     record::Record,
+    stats::update_child,
 };
 
 pub fn split_node<Storage: NodeStorage>(
@@ -57,6 +58,8 @@ pub fn split_node<Storage: NodeStorage>(
     }
 
     let middle_key = target_node.borrow().keys[t - ignore_middle_key];
+    let mut new_counts = Vec::new();
+    let mut new_aggregates = Vec::new();
     {
         for i in 0..brother_keys_count {
             new_keys[i] = target_node.borrow().keys[i + t];
@@ -65,6 +68,12 @@ pub fn split_node<Storage: NodeStorage>(
         for i in 0..brother_data_count {
             new_data[i] = target_node.borrow().data[i + t].clone();
         }
+
+        if !target_node.borrow().is_leaf {
+            let target_ref = target_node.borrow();
+            new_counts.extend_from_slice(&target_ref.counts[t..t + brother_data_count]);
+            new_aggregates.extend_from_slice(&target_ref.aggregates[t..t + brother_data_count]);
+        }
     }
 
     let new_brother: RcNode;
@@ -94,7 +103,10 @@ pub fn split_node<Storage: NodeStorage>(
             new_data,
             brother_keys_count,
             brother_data_count,
-        )
+        );
+        let mut brother_ref = new_brother.borrow_mut();
+        brother_ref.counts[..brother_data_count].copy_from_slice(&new_counts);
+        brother_ref.aggregates[..brother_data_count].clone_from_slice(&new_aggregates);
     }
     // println!("split new brother id: {:?}", new_id);
     {
@@ -124,6 +136,8 @@ pub fn split_node<Storage: NodeStorage>(
         ref_to_parent.data[0] = Record::from_id(target_node.borrow().id);
         ref_to_parent.data[1] = Record::from_id(new_brother.borrow().id);
         ref_to_parent.data_count = 2;
+        update_child(storage, &mut ref_to_parent, &target_node.borrow())?;
+        update_child(storage, &mut ref_to_parent, &new_brother.borrow())?;
         storage.mark_as_changed(ref_to_parent.id);
         return Ok(parent_node.clone());
     } else {
@@ -140,6 +154,9 @@ pub fn split_node<Storage: NodeStorage>(
             storage.mark_as_changed(ref_to_parent.id);
             ref_to_parent.keys_count += 1;
             ref_to_parent.data_count += 1;
+
+            update_child(storage, &mut ref_to_parent, &target_node.borrow())?;
+            update_child(storage, &mut ref_to_parent, &new_brother.borrow())?;
         }
 
         if can_insert {
@@ -166,6 +183,10 @@ fn insert_key_to_parent(
 
     utils::insert_to_array(&mut target_node.keys, pos, key);
     utils::insert_to_array(&mut target_node.data, pos + 1, Record::from_id(id));
+    if !target_node.is_leaf {
+        utils::insert_to_array(&mut target_node.counts, pos + 1, 0);
+        utils::insert_to_array(&mut target_node.aggregates, pos + 1, Vec::new());
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,51 @@
+use crate::Result;
+
+use super::{aggregate::node_summary, node::Node, nodestorage::NodeStorage};
+
+/// records count and summary of the subtree of the node, as stored in its parent.
+pub fn child_stats<Storage: NodeStorage>(
+    storage: &Storage,
+    node: &Node,
+) -> Result<(usize, Vec<u8>)> {
+    let summary = match storage.get_aggregate() {
+        Some(aggregate) => node_summary(aggregate, node)?,
+        None => Vec::new(),
+    };
+    Ok((node.subtree_count(), summary))
+}
+
+/// updates the records count and summary of `child` in `parent`.
+pub fn update_child<Storage: NodeStorage>(
+    storage: &mut Storage,
+    parent: &mut Node,
+    child: &Node,
+) -> Result<bool> {
+    let (count, summary) = child_stats(storage, child)?;
+    if parent.set_child_stats(child.id, count, summary) {
+        storage.mark_as_changed(parent.id);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// updates the records counts and summaries of all ancestors of the node.
+pub fn update_stats_up<Storage: NodeStorage>(storage: &mut Storage, node: &Node) -> Result<()> {
+    let (mut count, mut summary) = child_stats(storage, node)?;
+    let mut child = node.id;
+    let mut parent = node.parent;
+    while parent.exists() {
+        let target = storage.get_node(parent)?;
+        let mut target_ref = target.borrow_mut();
+        if !target_ref.set_child_stats(child, count, summary) {
+            return Err(crate::Error::Fail(format!(
+                "node {:?} is not a child of {:?}",
+                child, parent
+            )));
+        }
+        storage.mark_as_changed(target_ref.id);
+        (count, summary) = child_stats(storage, &target_ref)?;
+        child = target_ref.id;
+        parent = target_ref.parent;
+    }
+    Ok(())
+}
@@ -0,0 +1,500 @@
+use std::{collections::HashSet, fmt};
+
+use crate::{types::Id, Result};
+
+use super::{
+    node::{Node, RcNode},
+    nodestorage::NodeStorage,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// the child is linked from the parent, but can not be loaded.
+    MissingNode { parent: Id, child: Id },
+    /// the child is already reached by another link, the links form a cycle or a dag.
+    Revisit { parent: Id, child: Id },
+    /// keys_count does not match data_count or the capacity of the node.
+    Counts {
+        node: Id,
+        keys_count: usize,
+        data_count: usize,
+    },
+    /// the node has less or more records, than allowed by TreeParams.
+    Size {
+        node: Id,
+        size: usize,
+        min: usize,
+        max: usize,
+    },
+    /// the key is less than the previous key of the node.
+    KeyOrder { node: Id, index: usize },
+    /// the key is out of the range, given by separators of the ancestors.
+    Separator { node: Id, index: usize },
+    /// the parent link of the node does not point to the node, which links to it.
+    Parent { node: Id, expected: Id, actual: Id },
+    /// the left or right link does not point to the neighbour on the same level.
+    Sibling { node: Id, expected: Id, actual: Id },
+    /// the leaf is not on the same depth as other leafs.
+    LeafDepth {
+        node: Id,
+        depth: usize,
+        expected: usize,
+    },
+    /// the records count of the child, stored in the parent, is wrong.
+    ChildCount {
+        node: Id,
+        child: Id,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl Violation {
+    /// the node, where the violation was found.
+    pub fn node(&self) -> Id {
+        match self {
+            Violation::MissingNode { parent, .. } => *parent,
+            Violation::Revisit { parent, .. } => *parent,
+            Violation::Counts { node, .. } => *node,
+            Violation::Size { node, .. } => *node,
+            Violation::KeyOrder { node, .. } => *node,
+            Violation::Separator { node, .. } => *node,
+            Violation::Parent { node, .. } => *node,
+            Violation::Sibling { node, .. } => *node,
+            Violation::LeafDepth { node, .. } => *node,
+            Violation::ChildCount { node, .. } => *node,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MissingNode { parent, child } => {
+                write!(f, "node {} links to missing node {}", parent.0, child.0)
+            }
+            Violation::Revisit { parent, child } => {
+                write!(f, "node {} links to visited node {}", parent.0, child.0)
+            }
+            Violation::Counts {
+                node,
+                keys_count,
+                data_count,
+            } => write!(
+                f,
+                "node {}: wrong keys_count={} data_count={}",
+                node.0, keys_count, data_count
+            ),
+            Violation::Size {
+                node,
+                size,
+                min,
+                max,
+            } => write!(
+                f,
+                "node {}: size {} is out of [{}, {}]",
+                node.0, size, min, max
+            ),
+            Violation::KeyOrder { node, index } => {
+                write!(f, "node {}: key #{} is out of order", node.0, index)
+            }
+            Violation::Separator { node, index } => {
+                write!(
+                    f,
+                    "node {}: key #{} is out of the separators",
+                    node.0, index
+                )
+            }
+            Violation::Parent {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {}: parent is {}, expected {}",
+                node.0, actual.0, expected.0
+            ),
+            Violation::Sibling {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {}: link to {}, expected {}",
+                node.0, actual.0, expected.0
+            ),
+            Violation::LeafDepth {
+                node,
+                depth,
+                expected,
+            } => write!(f, "leaf {}: depth {}, expected {}", node.0, depth, expected),
+            Violation::ChildCount {
+                node,
+                child,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {}: count of child {} is {}, expected {}",
+                node.0, child.0, actual, expected
+            ),
+        }
+    }
+}
+
+/// keys of the node must be in [low, high].
+struct Bounds {
+    low: Option<u32>,
+    high: Option<u32>,
+}
+
+fn check_node<Storage: NodeStorage>(
+    storage: &Storage,
+    node: &Node,
+    bounds: &Bounds,
+    result: &mut Vec<Violation>,
+) {
+    let params = storage.get_params();
+    let t = params.get_t();
+    let capacity = params.get_keys_count();
+    let expected_keys = if node.is_leaf {
+        node.data_count
+    } else {
+        node.data_count.saturating_sub(1)
+    };
+    if node.keys_count != expected_keys || node.data_count > capacity {
+        result.push(Violation::Counts {
+            node: node.id,
+            keys_count: node.keys_count,
+            data_count: node.data_count,
+        });
+        // keys and data can not be trusted.
+        return;
+    }
+
+    let is_root = node.parent.is_empty();
+    let min = match (is_root, node.is_leaf) {
+        (true, true) => 0,
+        (true, false) => 2,
+        (false, true) => params.get_min_size_leaf(),
+        (false, false) => params.get_min_size_node(),
+    };
+    let max = 2 * t - 1;
+    if node.data_count < min || node.data_count > max {
+        result.push(Violation::Size {
+            node: node.id,
+            size: node.data_count,
+            min,
+            max,
+        });
+    }
+
+    let cmp = storage.get_cmp();
+    for (i, k) in node.key_iter().enumerate() {
+        if i > 0 && cmp.compare(node.keys[i - 1], *k).is_gt() {
+            result.push(Violation::KeyOrder {
+                node: node.id,
+                index: i,
+            });
+        }
+        let below = bounds.low.is_some_and(|l| cmp.compare(*k, l).is_lt());
+        let above = bounds.high.is_some_and(|h| cmp.compare(*k, h).is_gt());
+        if below || above {
+            result.push(Violation::Separator {
+                node: node.id,
+                index: i,
+            });
+        }
+    }
+}
+
+/// checks the invariants of the tree. returns all found violations.
+pub fn check<Storage: NodeStorage>(storage: &mut Storage, root: &RcNode) -> Result<Vec<Violation>> {
+    let storage: &Storage = storage;
+    let mut result = Vec::new();
+
+    let root_bounds = Bounds {
+        low: None,
+        high: None,
+    };
+    {
+        let root_ref = root.borrow();
+        if root_ref.parent.exists() {
+            result.push(Violation::Parent {
+                node: root_ref.id,
+                expected: Id::empty(),
+                actual: root_ref.parent,
+            });
+        }
+    }
+
+    let mut leaf_depth: Option<usize> = None;
+    let mut depth = 0;
+    // links of a corrupted tree may form a cycle.
+    let mut visited = HashSet::new();
+    visited.insert(root.borrow().id);
+    let mut level = vec![(root.clone(), root_bounds)];
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for (i, (node, bounds)) in level.iter().enumerate() {
+            let node_ref = node.borrow();
+            check_node(storage, &node_ref, bounds, &mut result);
+
+            // links between neighbours on the level.
+            let expected_left = if i > 0 {
+                level[i - 1].0.borrow().id
+            } else {
+                Id::empty()
+            };
+            let expected_right = match level.get(i + 1) {
+                Some(n) => n.0.borrow().id,
+                None => Id::empty(),
+            };
+            for (expected, actual) in [
+                (expected_left, node_ref.left),
+                (expected_right, node_ref.right),
+            ] {
+                if expected != actual {
+                    result.push(Violation::Sibling {
+                        node: node_ref.id,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+
+            if node_ref.is_leaf {
+                match leaf_depth {
+                    None => leaf_depth = Some(depth),
+                    Some(d) if d != depth => result.push(Violation::LeafDepth {
+                        node: node_ref.id,
+                        depth,
+                        expected: d,
+                    }),
+                    _ => {}
+                }
+                continue;
+            }
+            if node_ref.keys_count + 1 != node_ref.data_count {
+                continue;
+            }
+
+            for (c, d) in node_ref.data_iter().enumerate() {
+                let child_id = d.into_id();
+                if !visited.insert(child_id) {
+                    result.push(Violation::Revisit {
+                        parent: node_ref.id,
+                        child: child_id,
+                    });
+                    continue;
+                }
+                let child = match storage.get_node(child_id) {
+                    Ok(n) => n,
+                    Err(_) => {
+                        result.push(Violation::MissingNode {
+                            parent: node_ref.id,
+                            child: child_id,
+                        });
+                        continue;
+                    }
+                };
+                {
+                    let child_ref = child.borrow();
+                    if child_ref.parent != node_ref.id {
+                        result.push(Violation::Parent {
+                            node: child_id,
+                            expected: node_ref.id,
+                            actual: child_ref.parent,
+                        });
+                    }
+                    let count = child_ref.subtree_count();
+                    if count != node_ref.counts[c] {
+                        result.push(Violation::ChildCount {
+                            node: node_ref.id,
+                            child: child_id,
+                            expected: count,
+                            actual: node_ref.counts[c],
+                        });
+                    }
+                }
+                let child_bounds = Bounds {
+                    low: if c > 0 {
+                        Some(node_ref.keys[c - 1])
+                    } else {
+                        bounds.low
+                    },
+                    high: if c < node_ref.keys_count {
+                        Some(node_ref.keys[c])
+                    } else {
+                        bounds.high
+                    },
+                };
+                next.push((child, child_bounds));
+            }
+        }
+        level = next;
+        depth += 1;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{
+        insert::insert, mocks::MockNodeStorage, record::Record, remove::remove_key, TreeParams,
+    };
+
+    fn make_tree(t: usize, count: u32) -> Result<(MockNodeStorage, RcNode)> {
+        let mut storage = MockNodeStorage::new(TreeParams::default_with_t(t));
+        let mut root = Node::new_leaf_with_size(Id(1), t);
+        storage.add_node(&root);
+        for i in 1..=count {
+            let key = (i * 7) % count + 1;
+            root = insert(&mut storage, &root, key, &Record::from_u32(key))?;
+        }
+        Ok((storage, root))
+    }
+
+    fn find_leaf(storage: &MockNodeStorage, root: &RcNode, index: usize) -> Result<RcNode> {
+        let mut node = root.clone();
+        while !node.borrow().is_leaf {
+            let child = node.borrow().data[index].into_id();
+            node = storage.get_node(child)?;
+        }
+        Ok(node)
+    }
+
+    #[test]
+    fn check_valid() -> Result<()> {
+        let (mut storage, mut root) = make_tree(3, 200)?;
+        assert_eq!(check(&mut storage, &root)?, vec![]);
+        for key in (1..=200).filter(|k| k % 3 != 0) {
+            root = remove_key(&mut storage, &root, key)?;
+            assert_eq!(check(&mut storage, &root)?, vec![]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_parent() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let leaf = find_leaf(&storage, &root, 1)?;
+        let leaf_id = leaf.borrow().id;
+        let actual = Id(777);
+        leaf.borrow_mut().parent = actual;
+        let res = check(&mut storage, &root)?;
+        assert!(res.iter().any(|v| match v {
+            Violation::Parent { node, .. } => *node == leaf_id,
+            _ => false,
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_sibling_and_order() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let leaf = find_leaf(&storage, &root, 0)?;
+        let leaf_id = leaf.borrow().id;
+        {
+            let mut leaf_ref = leaf.borrow_mut();
+            leaf_ref.right.clear();
+            leaf_ref.keys.swap(0, 1);
+        }
+        let res = check(&mut storage, &root)?;
+        assert!(res.contains(&Violation::KeyOrder {
+            node: leaf_id,
+            index: 1
+        }));
+        assert!(res.iter().any(|v| match v {
+            Violation::Sibling { node, actual, .. } => *node == leaf_id && actual.is_empty(),
+            _ => false,
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_sizes() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let leaf = find_leaf(&storage, &root, 0)?;
+        let leaf_id = leaf.borrow().id;
+        {
+            let mut leaf_ref = leaf.borrow_mut();
+            leaf_ref.keys_count = 1;
+            leaf_ref.data_count = 1;
+        }
+        let res = check(&mut storage, &root)?;
+        assert!(res.iter().any(|v| match v {
+            Violation::Size { node, size, .. } => *node == leaf_id && *size == 1,
+            _ => false,
+        }));
+        assert!(res.iter().any(|v| match v {
+            Violation::ChildCount { child, .. } => *child == leaf_id,
+            _ => false,
+        }));
+
+        leaf.borrow_mut().keys_count = 3;
+        let res = check(&mut storage, &root)?;
+        assert!(res.contains(&Violation::Counts {
+            node: leaf_id,
+            keys_count: 3,
+            data_count: 1
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_separator() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let leaf = find_leaf(&storage, &root, 0)?;
+        let leaf_id = leaf.borrow().id;
+        let last = leaf.borrow().keys_count - 1;
+        leaf.borrow_mut().keys[last] = 1000;
+        let res = check(&mut storage, &root)?;
+        assert!(res.contains(&Violation::Separator {
+            node: leaf_id,
+            index: last
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_cycle() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let root_id = root.borrow().id;
+        let child = storage.get_node(root.borrow().data[0].into_id())?;
+        assert!(!child.borrow().is_leaf);
+        let child_id = child.borrow().id;
+        child.borrow_mut().data[0] = Record::Ptr(root_id);
+        let res = check(&mut storage, &root)?;
+        assert!(res.contains(&Violation::Revisit {
+            parent: child_id,
+            child: root_id
+        }));
+
+        // two links to one node.
+        let (mut storage, root) = make_tree(3, 100)?;
+        let first = root.borrow().data[0].into_id();
+        root.borrow_mut().data[1] = Record::Ptr(first);
+        let res = check(&mut storage, &root)?;
+        assert!(res.contains(&Violation::Revisit {
+            parent: root.borrow().id,
+            child: first
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn check_missing_node() -> Result<()> {
+        let (mut storage, root) = make_tree(3, 100)?;
+        let leaf = find_leaf(&storage, &root, 0)?;
+        let leaf_id = leaf.borrow().id;
+        storage.erase_node(&leaf_id);
+        let res = check(&mut storage, &root)?;
+        assert!(res.iter().any(|v| match v {
+            Violation::MissingNode { child, .. } => *child == leaf_id,
+            _ => false,
+        }));
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use crate::Result;
+
+/// key with an order-preserving encoding: encoded keys compared as byte strings
+/// are ordered as the keys. encodings are self-delimiting, so keys can be combined in tuples.
+pub trait Key: Sized {
+    /// appends the encoding of the key.
+    fn encode_key(&self, out: &mut Vec<u8>);
+    /// decodes the key from the start of the input. returns the key and the rest of the input.
+    fn decode_key(input: &[u8]) -> Result<(Self, &[u8])>;
+
+    fn to_key_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.encode_key(&mut result);
+        result
+    }
+
+    fn from_key_bytes(input: &[u8]) -> Result<Self> {
+        let (result, rest) = Self::decode_key(input)?;
+        if !rest.is_empty() {
+            return Err(bad_encoding("trailing bytes in key"));
+        }
+        Ok(result)
+    }
+}
+
+pub trait Value: Sized {
+    fn encode_value(&self) -> Vec<u8>;
+    fn decode_value(input: &[u8]) -> Result<Self>;
+}
+
+fn bad_encoding(msg: &str) -> crate::Error {
+    crate::Error::Corrupted(msg.to_owned())
+}
+
+fn split_fixed<const N: usize>(input: &[u8]) -> Result<([u8; N], &[u8])> {
+    if input.len() < N {
+        return Err(bad_encoding("key is too short"));
+    }
+    let (head, rest) = input.split_at(N);
+    Ok((head.try_into().unwrap(), rest))
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        /// big-endian.
+        impl Key for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+                let (bytes, rest) = split_fixed::<{ std::mem::size_of::<$t>() }>(input)?;
+                Ok((<$t>::from_be_bytes(bytes), rest))
+            }
+        }
+    )*};
+}
+
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => {$(
+        /// big-endian with the inverted sign bit, so negative numbers go first.
+        impl Key for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let v = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+
+            fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+                let (bytes, rest) = split_fixed::<{ std::mem::size_of::<$t>() }>(input)?;
+                let v = <$u>::from_be_bytes(bytes) ^ (1 << (<$u>::BITS - 1));
+                Ok((v as $t, rest))
+            }
+        }
+    )*};
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl Key for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+        let (v, rest) = u8::decode_key(input)?;
+        match v {
+            0 => Ok((false, rest)),
+            1 => Ok((true, rest)),
+            _ => Err(bad_encoding("bad bool")),
+        }
+    }
+}
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// zero bytes are escaped as 00 ff and the string ends with 00 01,
+/// so a string goes before all its continuations.
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for b in bytes {
+        out.push(*b);
+        if *b == ESCAPE {
+            out.push(ESCAPED_ZERO);
+        }
+    }
+    out.push(ESCAPE);
+    out.push(TERMINATOR);
+}
+
+fn decode_bytes(input: &[u8]) -> Result<(Vec<u8>, &[u8])> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] != ESCAPE {
+            result.push(input[i]);
+            i += 1;
+            continue;
+        }
+        match input.get(i + 1) {
+            Some(&ESCAPED_ZERO) => result.push(ESCAPE),
+            Some(&TERMINATOR) => return Ok((result, &input[i + 2..])),
+            _ => return Err(bad_encoding("bad escape in string")),
+        }
+        i += 2;
+    }
+    Err(bad_encoding("string is not terminated"))
+}
+
+impl Key for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+
+    fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+        decode_bytes(input)
+    }
+}
+
+impl Key for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+
+    fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+        let (bytes, rest) = decode_bytes(input)?;
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok((s, rest)),
+            Err(_) => Err(bad_encoding("string is not utf-8")),
+        }
+    }
+}
+
+macro_rules! tuple_key {
+    ($($name:ident),+) => {
+        /// components one after another.
+        impl<$($name: Key),+> Key for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+
+            #[allow(non_snake_case)]
+            fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+                let rest = input;
+                $(let ($name, rest) = $name::decode_key(rest)?;)+
+                Ok((($($name,)+), rest))
+            }
+        }
+    };
+}
+
+/// empty key, a prefix of all keys.
+impl Key for () {
+    fn encode_key(&self, _out: &mut Vec<u8>) {}
+
+    fn decode_key(input: &[u8]) -> Result<(Self, &[u8])> {
+        Ok(((), input))
+    }
+}
+
+tuple_key!(A);
+tuple_key!(A, B);
+tuple_key!(A, B, C);
+tuple_key!(A, B, C, D);
+tuple_key!(A, B, C, D, E);
+
+macro_rules! number_value {
+    ($($t:ty),*) => {$(
+        impl Value for $t {
+            fn encode_value(&self) -> Vec<u8> {
+                self.to_le_bytes().to_vec()
+            }
+
+            fn decode_value(input: &[u8]) -> Result<Self> {
+                match input.try_into() {
+                    Ok(bytes) => Ok(<$t>::from_le_bytes(bytes)),
+                    Err(_) => Err(bad_encoding("bad value size")),
+                }
+            }
+        }
+    )*};
+}
+
+number_value!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Value for bool {
+    fn encode_value(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self> {
+        match input {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(bad_encoding("bad bool")),
+        }
+    }
+}
+
+impl Value for () {
+    fn encode_value(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self> {
+        if !input.is_empty() {
+            return Err(bad_encoding("unit value is not empty"));
+        }
+        Ok(())
+    }
+}
+
+impl Value for Vec<u8> {
+    fn encode_value(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self> {
+        Ok(input.to_vec())
+    }
+}
+
+impl Value for String {
+    fn encode_value(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode_value(input: &[u8]) -> Result<Self> {
+        match String::from_utf8(input.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(bad_encoding("string is not utf-8")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_order<K: Key + Ord + Clone + std::fmt::Debug + PartialEq>(mut values: Vec<K>) {
+        values.sort();
+        for w in values.windows(2) {
+            let a = w[0].to_key_bytes();
+            let b = w[1].to_key_bytes();
+            assert_eq!(w[0].cmp(&w[1]), a.cmp(&b), "{:?} {:?}", w[0], w[1]);
+        }
+        for v in values {
+            assert_eq!(K::from_key_bytes(&v.to_key_bytes()).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn integers() {
+        check_order(vec![0u32, 1, 255, 256, 65535, u32::MAX, 7, 1 << 24]);
+        check_order(vec![0u64, 1, u64::MAX, 1 << 40, 12345]);
+        check_order(vec![0i32, -1, 1, i32::MIN, i32::MAX, -256, 256, -65536]);
+        check_order(vec![0i64, -1, 1, i64::MIN, i64::MAX, -(1 << 40)]);
+        check_order(vec![0i8, -1, 1, i8::MIN, i8::MAX]);
+        check_order(vec![false, true]);
+    }
+
+    #[test]
+    fn strings() {
+        check_order(vec![
+            String::new(),
+            "a".to_owned(),
+            "ab".to_owned(),
+            "a\0".to_owned(),
+            "a\0b".to_owned(),
+            "b".to_owned(),
+            "\u{ff}".to_owned(),
+        ]);
+        check_order(vec![
+            vec![],
+            vec![0u8],
+            vec![0, 0],
+            vec![0, 1],
+            vec![1],
+            vec![255, 0],
+        ]);
+    }
+
+    #[test]
+    fn tuples() {
+        let mut values = Vec::new();
+        for a in [0u32, 1, 300] {
+            for b in ["", "x", "x\0", "xy", "y"] {
+                for c in [-5i64, 0, 5] {
+                    values.push((a, b.to_owned(), c));
+                }
+            }
+        }
+        check_order(values);
+        check_order(vec![(1u8,), (2u8,)]);
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(u32::from_key_bytes(&[1, 2]).is_err());
+        assert!(u32::from_key_bytes(&[1, 2, 3, 4, 5]).is_err());
+        assert!(String::from_key_bytes(b"abc").is_err());
+        assert!(String::from_key_bytes(&[b'a', 0, 7]).is_err());
+        assert!(u64::decode_value(&[1]).is_err());
+        assert!(bool::decode_value(&[2]).is_err());
+    }
+
+    #[test]
+    fn values() -> Result<()> {
+        assert_eq!(u64::decode_value(&42u64.encode_value())?, 42);
+        assert_eq!(f64::decode_value(&1.5f64.encode_value())?, 1.5);
+        assert_eq!(
+            String::decode_value(&"abc".to_owned().encode_value())?,
+            "abc"
+        );
+        assert_eq!(Vec::<u8>::decode_value(&[1, 2])?, vec![1, 2]);
+        <()>::decode_value(&[])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
+
+use crate::{
+    storage::{store::Storage, BytewiseKeyCmp, KeyCmp},
+    Result,
+};
+
+pub mod encoding;
+
+pub use encoding::{Key, Value};
+
+/// typed view of a tree with encoded keys and values.
+/// the tree must be registered with the bytewise comparator, see `TypedTree::key_cmp`.
+pub struct TypedTree<K: Key, V: Value> {
+    tree_id: u32,
+    _kv: PhantomData<(K, V)>,
+}
+
+impl<K: Key, V: Value> Clone for TypedTree<K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K: Key, V: Value> Copy for TypedTree<K, V> {}
+
+type TypedRecord<K, V> = Option<(K, V)>;
+
+impl<K: Key, V: Value> TypedTree<K, V> {
+    pub fn new(tree_id: u32) -> Self {
+        TypedTree {
+            tree_id,
+            _kv: PhantomData,
+        }
+    }
+
+    pub fn tree_id(&self) -> u32 {
+        self.tree_id
+    }
+
+    /// comparator for the tree.
+    pub fn key_cmp() -> Rc<RefCell<dyn KeyCmp>> {
+        Rc::new(RefCell::new(BytewiseKeyCmp {}))
+    }
+
+    fn decode(record: Option<(Vec<u8>, Vec<u8>)>) -> Result<TypedRecord<K, V>> {
+        match record {
+            Some((key, value)) => Ok(Some((K::from_key_bytes(&key)?, V::decode_value(&value)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_all(records: Vec<(Vec<u8>, Vec<u8>)>) -> Result<Vec<(K, V)>> {
+        let mut result = Vec::with_capacity(records.len());
+        for (key, value) in records {
+            result.push((K::from_key_bytes(&key)?, V::decode_value(&value)?));
+        }
+        Ok(result)
+    }
+
+    pub fn insert(
+        &self,
+        storage: &mut Storage,
+        transaction: u64,
+        key: &K,
+        value: &V,
+    ) -> Result<()> {
+        storage.insert(
+            transaction,
+            self.tree_id,
+            &key.to_key_bytes(),
+            &value.encode_value(),
+        )
+    }
+
+    pub fn find(&self, storage: &mut Storage, key: &K) -> Result<Option<V>> {
+        match storage.find(self.tree_id, &key.to_key_bytes())? {
+            Some(value) => Ok(Some(V::decode_value(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn remove(&self, storage: &mut Storage, key: &K) -> Result<()> {
+        storage.remove(self.tree_id, &key.to_key_bytes())
+    }
+
+    /// count of keys less than `key`.
+    pub fn rank(&self, storage: &mut Storage, key: &K) -> Result<usize> {
+        storage.rank(self.tree_id, &key.to_key_bytes())
+    }
+
+    /// count of keys in [from, to].
+    pub fn count_range(&self, storage: &mut Storage, from: &K, to: &K) -> Result<usize> {
+        storage.count_range(self.tree_id, &from.to_key_bytes(), &to.to_key_bytes())
+    }
+
+    /// removes the records with keys in [from, to]. returns the count of removed records.
+    pub fn delete_range(
+        &self,
+        storage: &mut Storage,
+        transaction: u64,
+        from: &K,
+        to: &K,
+    ) -> Result<usize> {
+        storage.delete_range(
+            transaction,
+            self.tree_id,
+            &from.to_key_bytes(),
+            &to.to_key_bytes(),
+        )
+    }
+
+    pub fn select(&self, storage: &mut Storage, n: usize) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.select(self.tree_id, n)?)
+    }
+
+    pub fn first(&self, storage: &mut Storage) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.first(self.tree_id)?)
+    }
+
+    pub fn last(&self, storage: &mut Storage) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.last(self.tree_id)?)
+    }
+
+    pub fn floor(&self, storage: &mut Storage, key: &K) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.floor(self.tree_id, &key.to_key_bytes())?)
+    }
+
+    pub fn lower(&self, storage: &mut Storage, key: &K) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.lower(self.tree_id, &key.to_key_bytes())?)
+    }
+
+    pub fn ceiling(&self, storage: &mut Storage, key: &K) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.ceiling(self.tree_id, &key.to_key_bytes())?)
+    }
+
+    pub fn higher(&self, storage: &mut Storage, key: &K) -> Result<TypedRecord<K, V>> {
+        Self::decode(storage.higher(self.tree_id, &key.to_key_bytes())?)
+    }
+
+    /// records with keys starting with the prefix, in the key order.
+    /// the prefix is a key of the leading components, e.g. `(tenant,)` for `(u32, String, i64)` keys.
+    pub fn scan_prefix<P: Key>(&self, storage: &mut Storage, prefix: &P) -> Result<Vec<(K, V)>> {
+        Self::decode_all(storage.scan_prefix(self.tree_id, &prefix.to_key_bytes())?)
+    }
+
+    pub fn count_prefix<P: Key>(&self, storage: &mut Storage, prefix: &P) -> Result<usize> {
+        storage.count_prefix(self.tree_id, &prefix.to_key_bytes())
+    }
+
+    pub fn delete_prefix<P: Key>(
+        &self,
+        storage: &mut Storage,
+        transaction: u64,
+        prefix: &P,
+    ) -> Result<usize> {
+        storage.delete_prefix(transaction, self.tree_id, &prefix.to_key_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        storage::{memory_storage::MemoryStorage, StorageParams},
+        tree::TreeParams,
+    };
+
+    type Events = TypedTree<(u32, String, i64), u64>;
+
+    fn make_storage() -> Result<Storage> {
+        let mut all_cmp: HashMap<u32, Rc<RefCell<dyn KeyCmp>>> = HashMap::new();
+        all_cmp.insert(1, Events::key_cmp());
+        let mut params = StorageParams::default();
+        params.tree_params = TreeParams::default_with_t(3);
+        let fstore = Rc::new(RefCell::new(MemoryStorage::new()));
+        Storage::new(fstore, &params, all_cmp)
+    }
+
+    #[test]
+    fn typed_tree() -> Result<()> {
+        let mut storage = make_storage()?;
+        let events = Events::new(1);
+
+        let mut expected = Vec::new();
+        let tr = storage.begin_transaction()?;
+        for tenant in [3u32, 1, 256, 2] {
+            for name in ["b", "", "a\0", "a", "ab"] {
+                for ts in [10i64, -10, 0, i64::MIN] {
+                    let key = (tenant, name.to_owned(), ts);
+                    let value = tenant as u64 * 1000 + ts.unsigned_abs() % 1000;
+                    events.insert(&mut storage, tr, &key, &value)?;
+                    expected.push((key, value));
+                }
+            }
+        }
+        storage.commit_transaction(tr)?;
+        expected.sort();
+
+        for (i, (key, value)) in expected.iter().enumerate() {
+            assert_eq!(events.find(&mut storage, key)?, Some(*value));
+            assert_eq!(events.rank(&mut storage, key)?, i);
+        }
+        assert_eq!(events.first(&mut storage)?, expected.first().cloned());
+        assert_eq!(events.last(&mut storage)?, expected.last().cloned());
+        assert_eq!(events.select(&mut storage, 5)?, Some(expected[5].clone()));
+
+        let probe = (2u32, "a".to_owned(), 5i64);
+        let floor = expected.iter().rev().find(|kv| kv.0 <= probe).cloned();
+        let ceiling = expected.iter().find(|kv| kv.0 >= probe).cloned();
+        assert_eq!(events.floor(&mut storage, &probe)?, floor);
+        assert_eq!(events.lower(&mut storage, &probe)?, floor);
+        assert_eq!(events.ceiling(&mut storage, &probe)?, ceiling);
+        assert_eq!(events.higher(&mut storage, &probe)?, ceiling);
+
+        let from = (1u32, "a".to_owned(), 0i64);
+        let to = (3u32, String::new(), 0i64);
+        let in_range = expected
+            .iter()
+            .filter(|kv| kv.0 >= from && kv.0 <= to)
+            .count();
+        assert_eq!(events.count_range(&mut storage, &from, &to)?, in_range);
+
+        let found = events.scan_prefix(&mut storage, &(2u32, "a".to_owned()))?;
+        assert_eq!(found.len(), 4);
+        assert!(found.iter().all(|kv| kv.0 .0 == 2 && kv.0 .1 == "a"));
+        assert_eq!(events.count_prefix(&mut storage, &(256u32,))?, 20);
+
+        let tr = storage.begin_transaction()?;
+        assert_eq!(events.delete_prefix(&mut storage, tr, &(256u32,))?, 20);
+        assert_eq!(events.delete_range(&mut storage, tr, &from, &to)?, in_range);
+        storage.commit_transaction(tr)?;
+        let left: Vec<_> = expected
+            .iter()
+            .filter(|kv| kv.0 .0 != 256 && (kv.0 < from || kv.0 > to))
+            .cloned()
+            .collect();
+        assert_eq!(events.scan_prefix(&mut storage, &())?, left);
+
+        events.remove(&mut storage, &left[0].0)?;
+        assert_eq!(events.find(&mut storage, &left[0].0)?, None);
+        Ok(())
+    }
+}